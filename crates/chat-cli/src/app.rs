@@ -1,11 +1,27 @@
 use crate::{
-    event::EventHandler,
+    config::ProjectConfig,
+    event::{AppEvent, Event, EventHandler},
     ui::{self, Handler},
 };
 
 use ratatui::DefaultTerminal;
 
-use streuen_chat::app::ToChat;
+use streuen_chat::app::{ToChat, messages::SqliteMessageStore};
+
+/// File inside the data directory holding the message history.
+const MESSAGES_FILE: &str = "messages.db";
+
+/// Number of the latest messages shown of the selected conversation.
+const HISTORY_PAGE_SIZE: usize = 50;
+
+/// Public libp2p bootstrap nodes, used to join the public DHT or to look up peers in it while
+/// running the streuen DHT.
+const BOOTSTRAP_PEERS: [&str; 4] = [
+    "QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
+    "QmQCU2EcMqAqQPR2i9bChDtGNJchTbq5TbXJJ16u19uLTa",
+    "QmbLHAnMoJPWSCR5Zhtx6BHJX9KiKNN6tpvbUcqanj75Nb",
+    "QmcZf59bWwK5XFi76CZX8cbJ4BhTzzA3gU1ZjYZcYW3dwt",
+];
 
 /// Application.
 pub struct App {
@@ -21,12 +37,69 @@ pub struct App {
 
 impl App {
     /// Constructs a new instance of [`App`].
-    pub fn new() -> color_eyre::Result<Self> {
-        let chat_app = streuen_chat::ChatApp::new("Me".to_string())?;
+    pub fn new(project_config: &ProjectConfig) -> color_eyre::Result<Self> {
+        let keypair = project_config.load_or_generate_keypair()?;
+        let message_store =
+            SqliteMessageStore::open(project_config.data_dir().join(MESSAGES_FILE))?;
+        let session_store = project_config.session_store()?;
+        let mut builder = streuen_chat::ChatApp::builder()
+            .keypair(keypair)
+            .message_store(message_store)
+            .session_store(session_store)
+            .peer_store(project_config.peer_store())
+            // Listen on all interfaces and whatever port the OS assigns
+            .listen_on("/ip4/0.0.0.0/tcp/0".parse()?);
+        let pre_shared_key = project_config.pre_shared_key()?;
+        if let Some(key) = pre_shared_key {
+            tracing::info!("Joining the private network {}", key.fingerprint());
+            builder = builder.pre_shared_key(key);
+        } else {
+            // the WebSocket and WebRTC-direct addresses are the ones browsers can dial
+            builder = builder
+                .listen_on("/ip4/0.0.0.0/udp/0/quic-v1".parse()?)
+                .listen_on("/ip4/0.0.0.0/tcp/0/ws".parse()?)
+                .listen_on("/ip4/0.0.0.0/udp/0/webrtc-direct".parse()?)
+                .webrtc_certificate(project_config.load_or_generate_webrtc_certificate()?);
+            if let Some(tls) = project_config.websocket_tls()? {
+                builder = builder.websocket_tls(tls);
+            }
+        }
+        for addr in project_config.extra_listen_addresses()? {
+            builder = builder.listen_on(addr);
+        }
+        for addr in project_config.external_addresses()? {
+            builder = builder.external_address(addr);
+        }
+        for addr in project_config.rendezvous_points()? {
+            builder = builder.rendezvous_point(addr);
+        }
+        for namespace in project_config.rendezvous_namespaces() {
+            builder = builder.rendezvous_namespace(namespace);
+        }
+        let private_bootstrap_peers = project_config.private_bootstrap_peers()?;
+        let private_dht = !private_bootstrap_peers.is_empty();
+        if private_dht {
+            builder = builder.private_dht();
+        }
+        for addr in private_bootstrap_peers {
+            builder = builder.bootstrap_peer(addr);
+        }
+        for bootstrap_peer in BOOTSTRAP_PEERS {
+            let addr = format!("/dnsaddr/bootstrap.libp2p.io/p2p/{bootstrap_peer}").parse()?;
+            builder = if private_dht {
+                builder.public_dht_peer(addr)
+            } else {
+                builder.bootstrap_peer(addr)
+            };
+        }
+        let chat_app = builder.build()?;
+        // Network events are delivered through the terminal event loop
+        let events = EventHandler::new(chat_app.events());
+
         let ui_state = ui::State::new(&chat_app);
         Ok(Self {
             running: true,
-            events: EventHandler::new(),
+            events,
             chat_app,
             ui_state,
         })
@@ -34,39 +107,55 @@ pub fn new() -> color_eyre::Result<Self> {
 
     /// Run the application's main loop.
     pub async fn run(mut self, mut terminal: DefaultTerminal) -> color_eyre::Result<()> {
-        let bootstrap_peers = [
-            "QmNnooDu7bfjPFoTZYxMNLWUQJyrVwtbZg5gBMjTezGAJN",
-            "QmQCU2EcMqAqQPR2i9bChDtGNJchTbq5TbXJJ16u19uLTa",
-            "QmbLHAnMoJPWSCR5Zhtx6BHJX9KiKNN6tpvbUcqanj75Nb",
-            "QmcZf59bWwK5XFi76CZX8cbJ4BhTzzA3gU1ZjYZcYW3dwt",
-        ];
-
-        for bootstrap_peer in bootstrap_peers {
-            let bootstrap_addr = format!("/dnsaddr/bootstrap.libp2p.io/p2p/{bootstrap_peer}")
-                .parse()
-                .unwrap();
-            self.chat_app
-                .chat_dispatch(ToChat::AddBoostrapPeer(bootstrap_addr));
-        }
-
-        // Listen on all interfaces and whatever port the OS assigns
-        self.chat_app
-            .chat_dispatch(ToChat::ListenOn("/ip4/0.0.0.0/udp/0/quic-v1".parse()?));
-        self.chat_app
-            .chat_dispatch(ToChat::ListenOn("/ip4/0.0.0.0/tcp/0".parse()?));
+        let conversations = self.chat_app.messages().conversations().await?;
+        self.ui_state.load_conversations(&self.events.sender(), conversations)?;
 
         while self.running {
             terminal.draw(|frame| frame.render_widget(&self.ui_state, frame.area()))?;
             let event = self.events.next().await?;
             if event.should_quit() {
                 self.quit();
+            } else if let Event::App(AppEvent::Dispatch(command)) = event {
+                self.execute(command);
+            } else if let Event::App(AppEvent::LoadHistory(peer_id)) = event {
+                self.load_history(peer_id);
             } else {
                 self.ui_state.handle(&mut self.events.sender(), event)?;
             }
         }
+        // Let pending messages go out and remember the known peers for the next start
+        self.chat_app.shutdown().await?;
         Ok(())
     }
 
+    /// Reads the most recent messages exchanged with `peer_id` in the background, handing them to
+    /// the UI as an event.
+    fn load_history(&self, peer_id: libp2p::PeerId) {
+        let messages = self.chat_app.messages();
+        let event_sender = self.events.sender();
+        tokio::task::spawn_local(async move {
+            match messages.history(peer_id, None, HISTORY_PAGE_SIZE).await {
+                Ok(history) => {
+                    let _ = event_sender.send(AppEvent::HistoryLoaded(peer_id, history));
+                }
+                Err(err) => tracing::error!("Failed to load history with {peer_id}: {err}"),
+            }
+        });
+    }
+
+    /// Runs a command in the background, reporting its outcome as an event once it completed.
+    fn execute(&self, command: ToChat) {
+        let mut commands = self.chat_app.commands();
+        let event_sender = self.events.sender();
+        tokio::task::spawn_local(async move {
+            let result = commands
+                .execute(command.clone())
+                .await
+                .map_err(|err| err.to_string());
+            let _ = event_sender.send(AppEvent::CommandCompleted(command, result));
+        });
+    }
+
     /// Set running to false to quit the application.
     pub fn quit(&mut self) {
         self.running = false;
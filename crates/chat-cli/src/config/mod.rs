@@ -1,9 +1,55 @@
+mod peers;
+mod sessions;
+
 use std::{
     io,
     path::{Path, PathBuf},
 };
 
 use directories::ProjectDirs;
+use libp2p::{Multiaddr, identity::Keypair, pnet::PreSharedKey, websocket};
+use streuen_chat::WebRtcCertificate;
+
+pub use peers::FilePeerStore;
+pub use sessions::FileSessionStore;
+
+/// File inside the data directory holding the protobuf encoded node identity.
+const IDENTITY_FILE: &str = "identity.key";
+
+/// Directory inside the data directory holding the end-to-end encryption sessions.
+const SESSIONS_DIR: &str = "sessions";
+
+/// File inside the data directory holding the addresses of known peers.
+const PEERS_FILE: &str = "peers";
+
+/// File inside the data directory holding the pre-shared key of the private network, in the
+/// `swarm.key` format shared with other libp2p implementations.
+const SWARM_KEY_FILE: &str = "swarm.key";
+
+/// File inside the data directory holding the certificate of the WebRTC-direct listeners, whose
+/// hash is part of their addresses.
+const WEBRTC_CERTIFICATE_FILE: &str = "webrtc.pem";
+
+/// Files inside the data directory holding the PEM encoded certificate chain and private key for
+/// secure WebSocket listeners.
+const WEBSOCKET_CERTIFICATE_FILE: &str = "websocket.crt";
+const WEBSOCKET_KEY_FILE: &str = "websocket.key";
+
+/// Environment variable listing the comma separated bootstrap peers of the streuen DHT.
+const BOOTSTRAP_VAR: &str = "STREUEN_CHAT_BOOTSTRAP";
+
+/// Environment variable listing comma separated addresses to listen on besides the default ones.
+const LISTEN_VAR: &str = "STREUEN_CHAT_LISTEN";
+
+/// Environment variable listing comma separated addresses we are reachable at, e.g. through a
+/// forwarded port, which rendezvous registrations need if autonat cannot confirm any.
+const EXTERNAL_VAR: &str = "STREUEN_CHAT_EXTERNAL";
+
+/// Environment variable listing the comma separated rendezvous points to register at.
+const RENDEZVOUS_VAR: &str = "STREUEN_CHAT_RENDEZVOUS";
+
+/// Environment variable listing the comma separated team namespaces to register in.
+const NAMESPACES_VAR: &str = "STREUEN_CHAT_NAMESPACES";
 
 pub struct ProjectConfig {
     _project_dirs: Option<ProjectDirs>,
@@ -25,7 +71,7 @@ pub fn new() -> io::Result<Self> {
     }
 
     fn get_data_dir(project_dirs: &Option<ProjectDirs>) -> io::Result<PathBuf> {
-        let data_folder = std::env::var("STREUEN_CHAT_DATA".to_string())
+        let data_folder = std::env::var("STREUEN_CHAT_DATA")
             .ok()
             .map(PathBuf::from);
         let directory = if let Some(s) = data_folder {
@@ -42,4 +88,169 @@ fn get_data_dir(project_dirs: &Option<ProjectDirs>) -> io::Result<PathBuf> {
     pub fn data_dir(&self) -> &Path {
         self.data_dir.as_path()
     }
+
+    /// Loads the node identity from the data directory, generating and saving a new one on the
+    /// first run.
+    pub fn load_or_generate_keypair(&self) -> io::Result<Keypair> {
+        let identity_path = self.data_dir.join(IDENTITY_FILE);
+        match std::fs::read(&identity_path) {
+            Ok(bytes) => Keypair::from_protobuf_encoding(&bytes)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let keypair = Keypair::generate_ed25519();
+                let bytes = keypair
+                    .to_protobuf_encoding()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                write_private_file(&identity_path, &bytes)?;
+                tracing::info!("Generated new identity: {}", keypair.public().to_peer_id());
+                Ok(keypair)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Opens the end-to-end encryption sessions kept next to the node identity.
+    pub fn session_store(&self) -> io::Result<FileSessionStore> {
+        FileSessionStore::open(self.data_dir.join(SESSIONS_DIR))
+    }
+
+    /// Bootstrap peers of the streuen DHT, which is joined instead of the public one if any are
+    /// set.
+    pub fn private_bootstrap_peers(&self) -> io::Result<Vec<Multiaddr>> {
+        addresses_from_env(BOOTSTRAP_VAR)
+    }
+
+    /// Addresses to listen on besides the default ones, e.g. a secure WebSocket address on a
+    /// fixed port.
+    pub fn extra_listen_addresses(&self) -> io::Result<Vec<Multiaddr>> {
+        addresses_from_env(LISTEN_VAR)
+    }
+
+    pub fn external_addresses(&self) -> io::Result<Vec<Multiaddr>> {
+        addresses_from_env(EXTERNAL_VAR)
+    }
+
+    pub fn rendezvous_points(&self) -> io::Result<Vec<Multiaddr>> {
+        addresses_from_env(RENDEZVOUS_VAR)
+    }
+
+    /// Team namespaces to register in at the rendezvous points.
+    pub fn rendezvous_namespaces(&self) -> Vec<String> {
+        std::env::var(NAMESPACES_VAR)
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|namespace| !namespace.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /// Loads the certificate of the WebRTC-direct listeners, generating and saving a new one on
+    /// the first run so browsers can dial the same addresses again.
+    pub fn load_or_generate_webrtc_certificate(&self) -> io::Result<WebRtcCertificate> {
+        let path = self.data_dir.join(WEBRTC_CERTIFICATE_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(pem) => WebRtcCertificate::from_pem(&pem)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let certificate = streuen_chat::generate_webrtc_certificate();
+                write_private_file(&path, certificate.serialize_pem().as_bytes())?;
+                Ok(certificate)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The certificate and key for secure WebSocket listeners, if both were put into the data
+    /// directory.
+    pub fn websocket_tls(&self) -> io::Result<Option<websocket::tls::Config>> {
+        let certificate_chain = match std::fs::read(self.data_dir.join(WEBSOCKET_CERTIFICATE_FILE)) {
+            Ok(certificate_chain) => certificate_chain,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let private_key = std::fs::read(self.data_dir.join(WEBSOCKET_KEY_FILE))?;
+        streuen_chat::websocket_tls_config(&certificate_chain, &private_key).map(Some)
+    }
+
+    pub fn swarm_key_path(&self) -> PathBuf {
+        self.data_dir.join(SWARM_KEY_FILE)
+    }
+
+    /// Loads the key of the private network this profile takes part in, if it takes part in any.
+    pub fn pre_shared_key(&self) -> io::Result<Option<PreSharedKey>> {
+        match std::fs::read_to_string(self.swarm_key_path()) {
+            Ok(key) => key
+                .parse()
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Generates the key of a new private network for this profile, never replacing an existing
+    /// one as that would cut the profile off from its network.
+    pub fn generate_pre_shared_key(&self) -> io::Result<PreSharedKey> {
+        let path = self.swarm_key_path();
+        if path.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", path.display()),
+            ));
+        }
+        let mut key = [0; 32];
+        getrandom::fill(&mut key).map_err(io::Error::other)?;
+        let key = PreSharedKey::new(key);
+        write_private_file(&path, key.to_string().as_bytes())?;
+        Ok(key)
+    }
+
+    /// Writes the key of this profile's private network to `path`, to be handed to the other
+    /// members of the network.
+    pub fn export_pre_shared_key(&self, path: &Path) -> io::Result<PreSharedKey> {
+        let key = self.pre_shared_key()?.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                "the profile does not take part in a private network",
+            )
+        })?;
+        write_private_file(path, key.to_string().as_bytes())?;
+        Ok(key)
+    }
+
+    /// The addresses of the peers known from previous runs.
+    pub fn peer_store(&self) -> FilePeerStore {
+        FilePeerStore::new(self.data_dir.join(PEERS_FILE))
+    }
+}
+
+/// Parses the comma separated addresses of the environment variable `var`, if it is set.
+fn addresses_from_env(var: &str) -> io::Result<Vec<Multiaddr>> {
+    let Ok(addresses) = std::env::var(var) else {
+        return Ok(Vec::new());
+    };
+    addresses
+        .split(',')
+        .map(str::trim)
+        .filter(|addr| !addr.is_empty())
+        .map(|addr| {
+            addr.parse()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+        })
+        .collect()
+}
+
+/// Writes a file only readable by the current user.
+fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
 }
@@ -0,0 +1,28 @@
+use std::{io, path::PathBuf};
+
+use streuen_chat::app::{StorageError, peers::PeerStore};
+
+/// Stores the known peers in a single file.
+pub struct FilePeerStore {
+    path: PathBuf,
+}
+
+impl FilePeerStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl PeerStore for FilePeerStore {
+    fn load(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&mut self, peers: &[u8]) -> Result<(), StorageError> {
+        Ok(super::write_private_file(&self.path, peers)?)
+    }
+}
@@ -0,0 +1,41 @@
+use std::{io, path::PathBuf};
+
+use libp2p::PeerId;
+use streuen_chat::app::{StorageError, sessions::SessionStore};
+
+/// Stores every end-to-end encryption session in its own file, named after the peer id.
+pub struct FileSessionStore {
+    dir: PathBuf,
+}
+
+impl FileSessionStore {
+    pub fn open(dir: PathBuf) -> io::Result<Self> {
+        let mut builder = std::fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder.create(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn path(&self, peer_id: PeerId) -> PathBuf {
+        self.dir.join(peer_id.to_base58())
+    }
+}
+
+impl SessionStore for FileSessionStore {
+    fn load(&self, peer_id: PeerId) -> Result<Option<Vec<u8>>, StorageError> {
+        match std::fs::read(self.path(peer_id)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn save(&mut self, peer_id: PeerId, session: &[u8]) -> Result<(), StorageError> {
+        Ok(super::write_private_file(&self.path(peer_id), session)?)
+    }
+}
@@ -2,6 +2,7 @@
 use futures::{FutureExt, StreamExt};
 use ratatui::crossterm::event::Event as CrosstermEvent;
 use std::time::Duration;
+use streuen_chat::app::EventStream;
 use tokio::sync::mpsc;
 
 /// The frequency at which tick events are emitted.
@@ -28,10 +29,7 @@ pub enum Event {
 
 impl Event {
     pub(crate) fn should_quit(&self) -> bool {
-        match self {
-            Event::App(AppEvent::Quit) => true,
-            _ => false,
-        }
+        matches!(self, Event::App(AppEvent::Quit))
     }
 }
 
@@ -42,6 +40,16 @@ pub(crate) fn should_quit(&self) -> bool {
 pub enum AppEvent {
     /// Quit the application.
     Quit,
+    /// An event from the chat network.
+    Network(Box<streuen_chat::app::ToApp>),
+    /// A command to be dispatched to the chat network.
+    Dispatch(streuen_chat::app::ToChat),
+    /// A dispatched command completed, successfully or with the error message.
+    CommandCompleted(streuen_chat::app::ToChat, Result<(), String>),
+    /// The latest messages exchanged with a peer are to be read from the message history.
+    LoadHistory(libp2p::PeerId),
+    /// The latest messages exchanged with a peer, oldest first.
+    HistoryLoaded(libp2p::PeerId, Vec<streuen_chat::app::messages::Message>),
 }
 
 #[derive(Debug, Clone)]
@@ -55,7 +63,7 @@ impl EventSender {
     ///
     /// This is useful for sending events to the event handler which will be processed by the next
     /// iteration of the application's event loop.
-    pub fn send(&mut self, app_event: AppEvent) -> color_eyre::Result<()> {
+    pub fn send(&self, app_event: AppEvent) -> color_eyre::Result<()> {
         self.sender.send(Event::App(app_event)).wrap_err("Failed to send app event.")
     }
 }
@@ -68,10 +76,11 @@ pub struct EventHandler {
 }
 
 impl EventHandler {
-    /// Constructs a new instance of [`EventHandler`] and spawns a new thread to handle events.
-    pub fn new() -> Self {
+    /// Constructs a new instance of [`EventHandler`] and spawns a new thread to handle events,
+    /// including the events of the chat network.
+    pub fn new(network: EventStream) -> Self {
         let (sender, receiver) = mpsc::unbounded_channel();
-        let actor = EventTask::new(sender.clone());
+        let actor = EventTask::new(sender.clone(), network);
         tokio::spawn(async { actor.run().await });
         Self { sender: EventSender { sender }, receiver }
     }
@@ -111,18 +120,21 @@ pub fn sender(&self) -> EventSender {
 struct EventTask {
     /// Event sender channel.
     sender: mpsc::UnboundedSender<Event>,
+    /// Events of the chat network.
+    network: EventStream,
 }
 
 impl EventTask {
     /// Constructs a new instance of [`EventThread`].
-    fn new(sender: mpsc::UnboundedSender<Event>) -> Self {
-        Self { sender }
+    fn new(sender: mpsc::UnboundedSender<Event>, network: EventStream) -> Self {
+        Self { sender, network }
     }
 
     /// Runs the event thread.
     ///
-    /// This function emits tick events at a fixed rate and polls for crossterm events in between.
-    async fn run(self) -> color_eyre::Result<()> {
+    /// This function emits tick events at a fixed rate and polls for crossterm and network events
+    /// in between.
+    async fn run(mut self) -> color_eyre::Result<()> {
         let tick_rate = Duration::from_secs_f64(1.0 / TICK_FPS);
         let mut reader = crossterm::event::EventStream::new();
         let mut tick = tokio::time::interval(tick_rate);
@@ -139,6 +151,10 @@ async fn run(self) -> color_eyre::Result<()> {
               Some(Ok(evt)) = crossterm_event => {
                 self.send(Event::Crossterm(evt));
               }
+              Some(network_event) = self.network.next() => match network_event {
+                Ok(evt) => self.send(Event::App(AppEvent::Network(Box::new(evt)))),
+                Err(lagged) => tracing::warn!("Dropped network events: {lagged}"),
+              },
             };
         }
         Ok(())
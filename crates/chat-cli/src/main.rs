@@ -4,15 +4,41 @@
 use streuen_chat_cli::app::App;
 use streuen_chat_cli::config;
 
+/// Commands managing the profile, run instead of the chat.
+const USAGE: &str = "Usage: streuen-chat-cli [generate-swarm-key | export-swarm-key <file>]";
+
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
 
     let project_config = config::ProjectConfig::new()?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [] => {}
+        ["generate-swarm-key"] => {
+            let key = project_config.generate_pre_shared_key()?;
+            println!(
+                "Generated the key of private network {} at {}",
+                key.fingerprint(),
+                project_config.swarm_key_path().display()
+            );
+            return Ok(());
+        }
+        ["export-swarm-key", path] => {
+            let key = project_config.export_pre_shared_key(path.as_ref())?;
+            println!("Exported the key of private network {} to {path}", key.fingerprint());
+            return Ok(());
+        }
+        _ => color_eyre::eyre::bail!(USAGE),
+    }
     initialize_logging(&project_config)?;
 
+    // the chat app delivers its events to callbacks on the local task set
+    let local = tokio::task::LocalSet::new();
     let terminal = ratatui::init();
-    let result = App::new()?.run(terminal).await;
+    let result = local
+        .run_until(async { App::new(&project_config)?.run(terminal).await })
+        .await;
     ratatui::restore();
 
     result
@@ -14,16 +14,26 @@
 
 pub enum NavSection {
     Home(home::Home),
-    Chats(chats::Chats),
-    Settings(settings::Settings),
+    /// Shows the chat view of the [`State`], which outlives the section.
+    Chats,
+    Settings(Box<settings::Settings>),
     Help,
 }
 
 impl Handler for NavSection {
+    fn handle(&mut self, event_sender: &mut EventSender, event: event::Event) -> color_eyre::Result<()> {
+        match self {
+            NavSection::Home(section) => section.handle(event_sender, event),
+            NavSection::Chats => Ok(()),
+            NavSection::Settings(section) => section.handle(event_sender, event),
+            NavSection::Help => { Ok(()) }
+        }
+    }
+
     fn handle_key(&mut self, event_sender: &mut EventSender, key_event: KeyEvent) -> color_eyre::Result<()> {
         match self {
             NavSection::Home(section) => section.handle_key(event_sender, key_event),
-            NavSection::Chats(section) => section.handle_key(event_sender, key_event),
+            NavSection::Chats => Ok(()),
             NavSection::Settings(section) => section.handle_key(event_sender, key_event),
             NavSection::Help => { Ok(()) }
         }
@@ -34,7 +44,7 @@ impl NavSection {
     pub fn index(&self) -> usize {
         match self {
             NavSection::Home(_) => 0,
-            NavSection::Chats(_) => 1,
+            NavSection::Chats => 1,
             NavSection::Settings(_) => 2,
             NavSection::Help => 3,
         }
@@ -43,19 +53,19 @@ pub fn index(&self) -> usize {
     pub fn prev(&self, state: &State) -> NavSection {
         match self {
             NavSection::Home(_) => NavSection::Help,
-            NavSection::Chats(_) => NavSection::Home(Default::default()),
-            NavSection::Settings(_) => NavSection::Chats(Default::default()),
+            NavSection::Chats => NavSection::Home(Default::default()),
+            NavSection::Settings(_) => NavSection::Chats,
             NavSection::Help => {
-                NavSection::Settings(settings::Settings::new(state.local_peer_id.clone()))
+                NavSection::Settings(Box::new(settings::Settings::new(state.local_peer_id, state.listen_addresses.clone(), state.network_status.clone())))
             }
         }
     }
 
     pub fn next(&self, state: &State) -> NavSection {
         match self {
-            NavSection::Home(_) => NavSection::Chats(Default::default()),
-            NavSection::Chats(_) => {
-                NavSection::Settings(settings::Settings::new(state.local_peer_id.clone()))
+            NavSection::Home(_) => NavSection::Chats,
+            NavSection::Chats => {
+                NavSection::Settings(Box::new(settings::Settings::new(state.local_peer_id, state.listen_addresses.clone(), state.network_status.clone())))
             }
             NavSection::Settings(_) => NavSection::Help,
             NavSection::Help => NavSection::Home(Default::default()),
@@ -73,7 +83,7 @@ impl Widget for &NavSection {
     fn render(self, area: Rect, buf: &mut Buffer) {
         match self {
             NavSection::Home(section) => section.render(area, buf),
-            NavSection::Chats(section) => section.render(area, buf),
+            NavSection::Chats => {}
             NavSection::Settings(section) => section.render(area, buf),
             NavSection::Help => {}
         }
@@ -99,20 +109,37 @@ fn handle(&mut self, event_sender: &mut EventSender, event: event::Event) -> col
 
 pub struct State {
     local_peer_id: libp2p::PeerId,
+    /// Addresses we listen on, handed to the settings whenever they are shown.
+    listen_addresses: Vec<libp2p::Multiaddr>,
+    /// Latest reachability reported by the swarm, handed to the settings as well.
+    network_status: streuen_chat::app::NetworkStatus,
+    /// Chat view kept up to date while another section is shown.
+    chats: chats::Chats,
     nav_bar: nav::NavBar,
     section: NavSection,
 }
 
 impl State {
     pub fn new(chat_app: &streuen_chat::ChatApp) -> Self {
+        let local_peer_id = chat_app.current_user().peer_id();
         Self {
-            local_peer_id: chat_app.current_user().peer_id(),
+            local_peer_id,
+            listen_addresses: Vec::new(),
+            network_status: chat_app.network_status(),
+            chats: chats::Chats::new(local_peer_id),
             nav_bar: Default::default(),
             section: Default::default(),
         }
     }
 }
 
+impl State {
+    /// Lists the conversations found in the message history.
+    pub fn load_conversations(&mut self, event_sender: &EventSender, conversations: Vec<libp2p::PeerId>) -> color_eyre::Result<()> {
+        self.chats.load_conversations(event_sender, conversations)
+    }
+}
+
 impl Widget for &State {
     fn render(self, area: Rect, buf: &mut Buffer) {
         // Split the terminal vertically: top bar and main area
@@ -122,18 +149,34 @@ fn render(self, area: Rect, buf: &mut Buffer) {
                 Constraint::Length(3), // Navigation bar height
                 Constraint::Min(0),    // Main area
             ])
-            .split(area.clone());
+            .split(area);
 
         self.nav_bar.render(chunks[0], buf);
 
-        self.section.render(chunks[1], buf);
+        if let NavSection::Chats = self.section {
+            self.chats.render(chunks[1], buf);
+        } else {
+            self.section.render(chunks[1], buf);
+        }
     }
 }
 
 impl Handler for State {
     fn handle(&mut self, event_sender: &mut EventSender, event: crate::event::Event) -> color_eyre::Result<()> {
         self.section.handle(event_sender, event.clone())?;
+        // key presses only go to the chats while they are shown
+        if matches!(self.section, NavSection::Chats) || !matches!(event, crate::event::Event::Crossterm(_)) {
+            self.chats.handle(event_sender, event.clone())?;
+        }
         match event {
+            crate::event::Event::App(AppEvent::Network(network_event)) => {
+                match network_event.as_ref() {
+                    streuen_chat::app::ToApp::ListenAddressAdded(addr) => self.listen_addresses.push(addr.clone()),
+                    streuen_chat::app::ToApp::NetworkStatusChanged(status) => self.network_status = status.clone(),
+                    _ => {}
+                }
+                Ok(())
+            }
             crate::event::Event::App(_) => Ok(()),
             crate::event::Event::Tick => Ok(()),
             crate::event::Event::Crossterm(crossterm_event) => match crossterm_event {
@@ -152,12 +195,12 @@ fn handle_key(&mut self, event_sender: &mut EventSender, key_event: KeyEvent) ->
                 event_sender.send(AppEvent::Quit)
             }
             KeyCode::Right if key_event.modifiers == KeyModifiers::SHIFT => {
-                self.section = self.section.next(&self);
+                self.section = self.section.next(self);
                 self.nav_bar.navigate(&self.section);
                 Ok(())
             }
             KeyCode::Left if key_event.modifiers == KeyModifiers::SHIFT => {
-                self.section = self.section.prev(&self);
+                self.section = self.section.prev(self);
                 self.nav_bar.navigate(&self.section);
                 Ok(())
             }
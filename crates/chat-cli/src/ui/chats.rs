@@ -1,14 +1,180 @@
-use crossterm::event::{KeyCode, KeyEvent};
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use libp2p::PeerId;
 use ratatui::{
     buffer::Buffer,
     layout::{Constraint, Direction, Layout, Rect},
+    style::{Modifier, Style},
+    text::Line,
     widgets::{Block, Borders, List, ListItem, Paragraph, Widget},
 };
+use streuen_chat::app::{
+    DiscoverySource, ToApp, ToChat,
+    messages::{Message, MessageStatus, RoomMessage},
+    network::Latency,
+};
 
 use crate::event::{AppEvent, EventSender};
 
-#[derive(Default)]
-pub struct Chats {}
+/// A conversation shown in the chat box, and the recipient of the composed messages.
+#[derive(Clone, PartialEq)]
+enum Conversation {
+    Direct(PeerId),
+    Room(String),
+}
+
+pub struct Chats {
+    local_peer_id: PeerId,
+    /// Peers we exchanged messages with or are connected to.
+    users: Vec<PeerId>,
+    /// Latest ping results of the connected peers.
+    latencies: HashMap<PeerId, Latency>,
+    /// Rooms we are currently subscribed to.
+    rooms: Vec<String>,
+    /// Messages published to our rooms since startup, ordered by message id, as rooms have no
+    /// history.
+    room_messages: Vec<RoomMessage>,
+    selected: Option<Conversation>,
+    /// Latest page of the history with the selected user, oldest first, as last read from the
+    /// message store of the chat app.
+    history: Vec<Message>,
+    /// Text typed into the composer.
+    input: String,
+    /// Why the last message could not be sent.
+    status: Option<String>,
+}
+
+impl Chats {
+    pub fn new(local_peer_id: PeerId) -> Self {
+        Self {
+            local_peer_id,
+            users: Vec::new(),
+            latencies: HashMap::new(),
+            rooms: Vec::new(),
+            room_messages: Vec::new(),
+            selected: None,
+            history: Vec::new(),
+            input: String::new(),
+            status: None,
+        }
+    }
+
+    /// Lists the conversations found in the message history, selecting the most recent one.
+    pub fn load_conversations(&mut self, event_sender: &EventSender, conversations: Vec<PeerId>) -> color_eyre::Result<()> {
+        for peer_id in conversations {
+            self.add_user(peer_id);
+        }
+        match self.users.first() {
+            Some(peer_id) if self.selected.is_none() => self.select(event_sender, Conversation::Direct(*peer_id)),
+            _ => Ok(()),
+        }
+    }
+
+    fn add_user(&mut self, peer_id: PeerId) {
+        if !self.users.contains(&peer_id) {
+            self.users.push(peer_id);
+        }
+    }
+
+    /// Shows `conversation` in the chat box, reading the history of a direct one from the chat app.
+    fn select(&mut self, event_sender: &EventSender, conversation: Conversation) -> color_eyre::Result<()> {
+        self.history.clear();
+        self.status = None;
+        let load = match &conversation {
+            Conversation::Direct(peer_id) => Some(*peer_id),
+            Conversation::Room(_) => None,
+        };
+        self.selected = Some(conversation);
+        match load {
+            Some(peer_id) => event_sender.send(AppEvent::LoadHistory(peer_id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the selection through the users followed by the rooms, by `offset` entries.
+    fn move_selection(&mut self, event_sender: &EventSender, offset: isize) -> color_eyre::Result<()> {
+        let conversations: Vec<Conversation> = self
+            .users
+            .iter()
+            .map(|peer_id| Conversation::Direct(*peer_id))
+            .chain(self.rooms.iter().map(|room_id| Conversation::Room(room_id.clone())))
+            .collect();
+        if conversations.is_empty() {
+            return Ok(());
+        }
+        let index = match conversations.iter().position(|c| Some(c) == self.selected.as_ref()) {
+            Some(index) => (index as isize + offset).rem_euclid(conversations.len() as isize) as usize,
+            None => 0,
+        };
+        self.select(event_sender, conversations[index].clone())
+    }
+
+    /// Sends the composed text to the selected conversation.
+    fn submit_input(&mut self, event_sender: &EventSender) -> color_eyre::Result<()> {
+        let text = self.input.trim().to_string();
+        if text.is_empty() {
+            return Ok(());
+        }
+        // shown once the chat app reports the message as sent
+        let command = match &self.selected {
+            Some(Conversation::Direct(peer_id)) => ToChat::SendMessage(*peer_id, text),
+            Some(Conversation::Room(room_id)) => ToChat::SendRoomMessage(room_id.clone(), text),
+            None => {
+                self.status = Some("Select a user or room first".to_string());
+                return Ok(());
+            }
+        };
+        self.input.clear();
+        self.status = None;
+        event_sender.send(AppEvent::Dispatch(command))
+    }
+
+    /// Update the chat view from an event of the chat network.
+    fn handle_network(&mut self, event_sender: &EventSender, event: &ToApp) -> color_eyre::Result<()> {
+        match event {
+            ToApp::PeerConnected(peer_id) | ToApp::PeerDiscovered { peer_id, source: DiscoverySource::Rendezvous(_) } => {
+                self.add_user(*peer_id);
+            }
+            ToApp::PeerDisconnected(peer_id) => {
+                self.latencies.remove(peer_id);
+            }
+            ToApp::PeerLatency { peer_id, latency } => {
+                self.latencies.insert(*peer_id, latency.clone());
+            }
+            ToApp::MessageSent(message) | ToApp::MessageReceived(message) => {
+                // the chat app records the message before handing out the event
+                let peer_id = message.conversation(self.local_peer_id);
+                self.add_user(peer_id);
+                return self.reload(event_sender, peer_id);
+            }
+            ToApp::MessageStatusChanged { to, .. } => return self.reload(event_sender, *to),
+            ToApp::RoomJoined(room_id) if !self.rooms.contains(room_id) => {
+                self.rooms.push(room_id.clone());
+            }
+            ToApp::RoomLeft(room_id) => {
+                self.rooms.retain(|r| r != room_id);
+            }
+            ToApp::RoomMessageSent(message) | ToApp::RoomMessageReceived(message) => {
+                let index = self
+                    .room_messages
+                    .partition_point(|m| m.message_id() <= message.message_id());
+                self.room_messages.insert(index, message.clone());
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Reads the history with `peer_id` again if it is shown.
+    fn reload(&self, event_sender: &EventSender, peer_id: PeerId) -> color_eyre::Result<()> {
+        if self.selected == Some(Conversation::Direct(peer_id)) {
+            event_sender.send(AppEvent::LoadHistory(peer_id))
+        } else {
+            Ok(())
+        }
+    }
+}
 
 impl Widget for &Chats {
     fn render(self, area: Rect, buf: &mut Buffer) {
@@ -21,27 +187,159 @@ fn render(self, area: Rect, buf: &mut Buffer) {
             ])
             .split(area);
 
+        // Split the left side vertically: top (user list), bottom (room list)
+        let list_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(main_chunks[0]);
+
+        // Split the right side vertically: top (chat box), bottom (composer)
+        let chat_chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(0), Constraint::Length(3)])
+            .split(main_chunks[1]);
+
+        let selected_style = Style::default().add_modifier(Modifier::REVERSED);
+
         // User list (left)
-        let users = vec![
-            ListItem::new("Alice"),
-            ListItem::new("Bob"),
-            ListItem::new("Carol"),
-        ];
+        let users: Vec<ListItem> = self
+            .users
+            .iter()
+            .map(|peer_id| {
+                let quality = self.latencies.get(peer_id).and_then(|latency| {
+                    let quality = latency.quality()?;
+                    Some(match latency.average() {
+                        Some(rtt) => format!("  {quality}, {} ms", rtt.as_millis()),
+                        None => format!("  {quality}"),
+                    })
+                });
+                let mut lines = vec![Line::from(peer_id.to_base58())];
+                lines.extend(quality.map(Line::from));
+                let item = ListItem::new(lines);
+                if self.selected == Some(Conversation::Direct(*peer_id)) {
+                    item.style(selected_style)
+                } else {
+                    item
+                }
+            })
+            .collect();
         let user_list =
             List::new(users).block(Block::default().title("Users").borders(Borders::ALL));
-        user_list.render(main_chunks[0], buf);
+        user_list.render(list_chunks[0], buf);
+
+        // Room list (left)
+        let rooms: Vec<ListItem> = self
+            .rooms
+            .iter()
+            .map(|room_id| {
+                let item = ListItem::new(format!("#{room_id}"));
+                if self.selected.as_ref() == Some(&Conversation::Room(room_id.clone())) {
+                    item.style(selected_style)
+                } else {
+                    item
+                }
+            })
+            .collect();
+        let room_list =
+            List::new(rooms).block(Block::default().title("Rooms").borders(Borders::ALL));
+        room_list.render(list_chunks[1], buf);
 
-        // Chat box (right)
-        let chat_box = Paragraph::new("Chat messages go here")
-            .block(Block::default().title("Chat").borders(Borders::ALL));
-        chat_box.render(main_chunks[1], buf);
+        // Chat box (right), the selected conversation oldest message first
+        let (title, lines): (String, Vec<Line>) = match &self.selected {
+            Some(Conversation::Direct(peer_id)) => {
+                let lines = self
+                    .history
+                    .iter()
+                    .map(|message| {
+                        let line = if message.from() == self.local_peer_id {
+                            format!("me: {} {}", message.text(), status_marker(message.status()))
+                        } else {
+                            format!("{}: {}", message.from(), message.text())
+                        };
+                        Line::from(line)
+                    })
+                    .collect();
+                (format!("Chat with {peer_id}"), lines)
+            }
+            Some(Conversation::Room(room_id)) => {
+                let lines = self
+                    .room_messages
+                    .iter()
+                    .filter(|message| message.room_id() == room_id)
+                    .map(|message| {
+                        let sender = if message.from() == self.local_peer_id {
+                            "me".to_string()
+                        } else {
+                            message.from().to_base58()
+                        };
+                        Line::from(format!("{sender}: {}", message.text()))
+                    })
+                    .collect();
+                (format!("Chat in #{room_id}"), lines)
+            }
+            None => ("Chat".to_string(), Vec::new()),
+        };
+        let chat_box =
+            Paragraph::new(lines).block(Block::default().title(title).borders(Borders::ALL));
+        chat_box.render(chat_chunks[0], buf);
+
+        // Composer (right)
+        let composer_title = self.status.as_deref().unwrap_or("Message (Up/Down to select, Enter to send)");
+        Paragraph::new(self.input.as_str())
+            .block(Block::default().title(composer_title).borders(Borders::ALL))
+            .render(chat_chunks[1], buf);
+    }
+}
+
+fn status_marker(status: MessageStatus) -> &'static str {
+    match status {
+        MessageStatus::Queued => "…",
+        MessageStatus::Sent => "✓",
+        MessageStatus::Delivered => "✓✓",
+        MessageStatus::Failed => "✗",
     }
 }
 
 impl super::Handler for Chats {
+    fn handle(&mut self, event_sender: &mut EventSender, event: crate::event::Event) -> color_eyre::Result<()> {
+        match event {
+            crate::event::Event::App(AppEvent::Network(network_event)) => {
+                self.handle_network(event_sender, &network_event)
+            }
+            crate::event::Event::App(AppEvent::HistoryLoaded(peer_id, history)) => {
+                if self.selected == Some(Conversation::Direct(peer_id)) {
+                    self.history = history;
+                }
+                Ok(())
+            }
+            crate::event::Event::App(AppEvent::CommandCompleted(
+                ToChat::SendMessage(..) | ToChat::SendRoomMessage(..),
+                Err(err),
+            )) => {
+                self.status = Some(err);
+                Ok(())
+            }
+            crate::event::Event::Crossterm(crossterm::event::Event::Key(key_event)) => {
+                self.handle_key(event_sender, key_event)
+            }
+            _ => Ok(()),
+        }
+    }
+
     fn handle_key(&mut self, event_sender: &mut EventSender, key_event: KeyEvent) -> color_eyre::Result<()> {
         match key_event.code {
             KeyCode::Esc => event_sender.send(AppEvent::Quit),
+            KeyCode::Up => self.move_selection(event_sender, -1),
+            KeyCode::Down => self.move_selection(event_sender, 1),
+            KeyCode::Enter => self.submit_input(event_sender),
+            KeyCode::Backspace => {
+                self.input.pop();
+                Ok(())
+            }
+            KeyCode::Char(c) if !key_event.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                self.input.push(c);
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
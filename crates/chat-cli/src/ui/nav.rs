@@ -6,6 +6,7 @@
     widgets::{Block, Borders, Tabs, Widget},
 };
 
+#[derive(Default)]
 pub struct NavBar {
     index: usize,
 }
@@ -16,12 +17,6 @@ pub fn navigate(&mut self, section: &super::NavSection) {
     }
 }
 
-impl Default for NavBar {
-    fn default() -> Self {
-        Self { index: 0 }
-    }
-}
-
 impl Widget for &NavBar {
     fn render(self, area: Rect, buf: &mut Buffer) {
         // Split the terminal vertically: top bar and main area
@@ -31,7 +26,7 @@ fn render(self, area: Rect, buf: &mut Buffer) {
                 Constraint::Length(3), // Navigation bar height
                 Constraint::Min(0),    // Main area
             ])
-            .split(area.clone());
+            .split(area);
 
         // Navigation bar (top)
         let nav_titles = [
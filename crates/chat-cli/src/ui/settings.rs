@@ -1,25 +1,39 @@
 use crossterm::event::{KeyCode, KeyEvent};
-use libp2p::PeerId;
+use libp2p::{Multiaddr, PeerId};
 use ratatui::{
     buffer::Buffer,
     layout::{Constraint, Flex, Layout, Rect},
-    widgets::{Block, Borders, Clear, List, ListItem, Widget},
+    text::Line,
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph, Widget},
 };
+use streuen_chat::app::{ConnectStep, DiscoverySource, NetworkStatus, ToApp, ToChat, network::HolePunch};
 
 use crate::event::{AppEvent, EventSender};
 
 pub struct Settings {
     local_peer_id: PeerId,
+    /// Addresses we listen on, for others to dial us at.
+    listen_addresses: Vec<Multiaddr>,
+    /// How other peers can reach us, to tell why a connection does not come about.
+    network_status: NetworkStatus,
     selected: usize,
     show_selected: bool,
+    /// Text typed into the popup of the selected option.
+    input: String,
+    /// Outcome of the last command dispatched from here.
+    status: Option<String>,
 }
 
 impl Settings {
-    pub fn new(local_peer_id: PeerId) -> Self {
+    pub fn new(local_peer_id: PeerId, listen_addresses: Vec<Multiaddr>, network_status: NetworkStatus) -> Self {
         Self {
             local_peer_id,
+            listen_addresses,
+            network_status,
             selected: 1,
-            show_selected: false
+            show_selected: false,
+            input: String::new(),
+            status: None,
         }
     }
 
@@ -36,12 +50,87 @@ fn handle_enter_selected(&mut self, num: usize) -> color_eyre::Result<()> {
                 }
                 Ok(())
             }
-            _ => {
+            2..=4 | 6 => {
                 self.selected = num;
                 self.show_selected = true;
+                self.input.clear();
                 Ok(())
             }
+            5 => {
+                let addresses = self
+                    .listen_addresses
+                    .iter()
+                    .map(|addr| format!("{addr}/p2p/{}", self.local_peer_id))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                match cli_clipboard::set_contents(addresses) {
+                    Ok(_) => {
+                        tracing::info!("Copied {} addresses to clipboard", self.listen_addresses.len());
+                    }
+                    Err(_) => {
+                        tracing::error!("Failed to copy addresses to clipboard");
+                    }
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Dispatches the command of the selected option using the text typed into its popup.
+    fn submit_input(&mut self, event_sender: &mut EventSender) -> color_eyre::Result<()> {
+        let input = self.input.trim().to_string();
+        self.show_selected = false;
+        self.input.clear();
+        if input.is_empty() {
+            return Ok(());
         }
+        let command = match self.selected {
+            2 => {
+                if let Ok(peer_id) = input.parse::<PeerId>() {
+                    ToChat::Connect(peer_id)
+                } else if let Ok(addr) = input.parse::<Multiaddr>() {
+                    ToChat::Dial(addr)
+                } else {
+                    tracing::error!("Not a peer id or address: {input}");
+                    return Ok(());
+                }
+            }
+            3 => ToChat::JoinRoom(input),
+            4 => ToChat::LeaveRoom(input),
+            6 => ToChat::RegisterNamespace(input),
+            _ => return Ok(()),
+        };
+        self.status = Some(format!("{}...", describe(&command)));
+        event_sender.send(AppEvent::Dispatch(command))
+    }
+}
+
+/// What a command does, as shown in the status line.
+fn describe(command: &ToChat) -> String {
+    match command {
+        ToChat::ListenOn(addr) => format!("Listening on {addr}"),
+        ToChat::AddBoostrapPeer(addr) => format!("Bootstrapping from {addr}"),
+        ToChat::Connect(peer_id) => format!("Dialing {peer_id}"),
+        ToChat::Dial(addr) => format!("Dialing {addr}"),
+        ToChat::AddContact(peer_id) => format!("Adding contact {peer_id}"),
+        ToChat::RemoveContact(peer_id) => format!("Removing contact {peer_id}"),
+        ToChat::SendMessage(peer_id, _) => format!("Sending message to {peer_id}"),
+        ToChat::JoinRoom(room_id) => format!("Joining room {room_id}"),
+        ToChat::LeaveRoom(room_id) => format!("Leaving room {room_id}"),
+        ToChat::SendRoomMessage(room_id, _) => format!("Sending message to room {room_id}"),
+        ToChat::AddRendezvousPoint(addr) => format!("Adding rendezvous point {addr}"),
+        ToChat::RegisterNamespace(namespace) => format!("Registering in namespace {namespace}"),
+        ToChat::UnregisterNamespace(namespace) => format!("Unregistering from namespace {namespace}"),
+    }
+}
+
+/// How a peer is being reached, as shown in the status line.
+fn describe_step(peer_id: PeerId, step: ConnectStep) -> String {
+    match step {
+        ConnectStep::Dialing => format!("Dialing {peer_id}"),
+        ConnectStep::LookingUp => format!("Looking up {peer_id} in the DHT"),
+        ConnectStep::Relaying => format!("Dialing {peer_id} through a relay"),
     }
 }
 
@@ -51,10 +140,35 @@ fn render(self, area: Rect, buf: &mut Buffer) {
         let settings_options = vec![
             ListItem::new(format!("1: Copy Peer Id: {}", self.local_peer_id.to_base58())),
             ListItem::new("2: Dial Peer"),
+            ListItem::new("3: Join Room"),
+            ListItem::new("4: Leave Room"),
+            ListItem::new(format!("5: Copy Addresses ({} known)", self.listen_addresses.len())),
+            ListItem::new("6: Register In Namespace"),
         ];
+        let [list_area, network_area, status_area] =
+            Layout::vertical([Constraint::Min(0), Constraint::Length(7), Constraint::Length(3)]).areas(area);
         let user_list =
             List::new(settings_options).block(Block::default().title("Users").borders(Borders::ALL));
-        user_list.render(area, buf);
+        user_list.render(list_area, buf);
+        let network = &self.network_status;
+        let hole_punches_succeeded =
+            network.hole_punches.values().filter(|hole_punch| **hole_punch == HolePunch::Succeeded).count();
+        let network_lines = vec![
+            Line::from(format!("Reachability: {}", network.reachability)),
+            Line::from(format!("External Addresses: {}", network.external_addresses.len())),
+            Line::from(format!("UPnP: {}", network.upnp)),
+            Line::from(format!("Relay Reservations: {}", network.relay_reservations.len())),
+            Line::from(format!(
+                "Hole Punches: {hole_punches_succeeded} succeeded, {} failed",
+                network.hole_punches.len() - hole_punches_succeeded
+            )),
+        ];
+        Paragraph::new(network_lines)
+            .block(Block::bordered().title("Network"))
+            .render(network_area, buf);
+        Paragraph::new(self.status.as_deref().unwrap_or_default())
+            .block(Block::bordered().title("Status"))
+            .render(status_area, buf);
 
         if self.show_selected {
             let popup_area = area;
@@ -62,19 +176,52 @@ fn render(self, area: Rect, buf: &mut Buffer) {
             let horizontal_layout = Layout::horizontal([Constraint::Percentage(60)]).flex(Flex::Center);
             let [popup_area] = vertical_layout.areas(popup_area);
             let [popup_area] = horizontal_layout.areas(popup_area);
-            match self.selected {
-                2 => {
-                    let block = Block::bordered().title("Enter Peer To Be Dialed:");
-                    Clear.render(popup_area, buf);
-                    block.render(popup_area, buf);
-                }
-                _ => (),
-            }
+            let title = match self.selected {
+                2 => "Enter Peer To Be Dialed:",
+                3 => "Enter Room To Be Joined:",
+                4 => "Enter Room To Be Left:",
+                6 => "Enter Namespace To Be Registered In:",
+                _ => return,
+            };
+            Clear.render(popup_area, buf);
+            Paragraph::new(self.input.as_str())
+                .block(Block::bordered().title(title))
+                .render(popup_area, buf);
         }
     }
 }
 
 impl super::Handler for Settings {
+    fn handle(&mut self, event_sender: &mut EventSender, event: crate::event::Event) -> color_eyre::Result<()> {
+        match event {
+            crate::event::Event::App(AppEvent::CommandCompleted(command, result)) => {
+                self.status = Some(match result {
+                    Ok(()) => format!("{}: done", describe(&command)),
+                    Err(err) => err,
+                });
+                Ok(())
+            }
+            crate::event::Event::App(AppEvent::Network(event)) => {
+                match *event {
+                    ToApp::Connecting { peer_id, step } => {
+                        self.status = Some(format!("{}...", describe_step(peer_id, step)));
+                    }
+                    ToApp::ListenAddressAdded(addr) => self.listen_addresses.push(addr),
+                    ToApp::NetworkStatusChanged(status) => self.network_status = status,
+                    ToApp::PeerDiscovered { peer_id, source: DiscoverySource::Rendezvous(namespace) } => {
+                        self.status = Some(format!("Discovered {peer_id} in namespace {namespace}"));
+                    }
+                    _ => {}
+                }
+                Ok(())
+            }
+            crate::event::Event::Crossterm(crossterm::event::Event::Key(key_event)) => {
+                self.handle_key(event_sender, key_event)
+            }
+            _ => Ok(()),
+        }
+    }
+
     fn handle_key(&mut self, event_sender: &mut EventSender, key_event: KeyEvent) -> color_eyre::Result<()> {
         match key_event.code {
             KeyCode::Esc => {
@@ -85,6 +232,17 @@ fn handle_key(&mut self, event_sender: &mut EventSender, key_event: KeyEvent) ->
                     event_sender.send(AppEvent::Quit)
                 }
             }
+            KeyCode::Char(c) if self.show_selected => {
+                self.input.push(c);
+                Ok(())
+            }
+            KeyCode::Backspace if self.show_selected => {
+                self.input.pop();
+                Ok(())
+            }
+            KeyCode::Enter if self.show_selected => {
+                self.submit_input(event_sender)
+            }
             KeyCode::Char(n) => {
                 self.show_selected = false;
                 if let Some(num) = n.to_digit(10) {
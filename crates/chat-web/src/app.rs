@@ -1,10 +1,12 @@
+use libp2p::identity::Keypair;
 use streuen_chat::ChatApp;
-use streuen_chat::app;
+use streuen_chat::app::{self, messages};
 use yew::prelude::*;
 
 use crate::chat::Chat;
 
 pub enum AppMsg {
+    MessageStoreOpened(Result<messages::IndexedDbMessageStore, app::StorageError>),
     RegisterAppHandler(app::AppCallback),
     SwarmDispatchEvent(app::ToChat),
     ChangeUserName(String),
@@ -12,36 +14,80 @@ pub enum AppMsg {
 }
 
 pub struct App {
-    chat_app: ChatApp,
+    keypair: Keypair,
+    /// Created once the message store has been opened.
+    chat_app: Option<ChatApp>,
+    /// Why the chat app could not be started, shown in place of the chat.
+    error: Option<app::ChatAppError>,
 }
 
-impl<'a> Component for App {
+impl Component for App {
     type Message = AppMsg;
     type Properties = ();
 
-    fn create(_ctx: &Context<Self>) -> Self {
-        let chat_app = ChatApp::new("Me".to_string()).unwrap();
+    fn create(ctx: &Context<Self>) -> Self {
+        let keypair = crate::identity::load_or_generate_keypair();
 
-        tracing::debug!(
-            "local_id = {}",
-            chat_app.current_user().peer_id().to_base58()
-        );
+        let link = ctx.link().clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let store = messages::IndexedDbMessageStore::open().await;
+            link.send_message(AppMsg::MessageStoreOpened(store));
+        });
 
         // maybe bootstrap address to be used
         // need to determine the peer id of the bootstrap peer
         // /dns4/ws-star.discovery.libp2p.io/tcp/443/wss/p2p-websocket-star
 
-        Self { chat_app }
+        Self {
+            keypair,
+            chat_app: None,
+            error: None,
+        }
     }
 
     fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
+            AppMsg::MessageStoreOpened(store) => {
+                let builder = ChatApp::builder()
+                    .keypair(self.keypair.clone())
+                    .session_store(crate::identity::LocalStorageSessionStore)
+                    .peer_store(crate::identity::LocalStoragePeerStore);
+                let built = match store {
+                    Ok(store) => builder.message_store(store),
+                    Err(err) => {
+                        tracing::error!("Failed to open message store, history is disabled: {err}");
+                        builder
+                    }
+                }
+                .build();
+
+                match built {
+                    Ok(chat_app) => {
+                        tracing::debug!(
+                            "local_id = {}",
+                            chat_app.current_user().peer_id().to_base58()
+                        );
+                        self.chat_app = Some(chat_app);
+                    }
+                    Err(err) => {
+                        tracing::error!("Failed to start the chat app: {err}");
+                        self.error = Some(err);
+                    }
+                }
+                true
+            }
             AppMsg::RegisterAppHandler(handler) => {
-                self.chat_app.register_app_handler(handler);
+                if let Some(chat_app) = self.chat_app.as_mut() {
+                    chat_app.register_app_handler(handler);
+                }
                 false
             }
             AppMsg::SwarmDispatchEvent(event) => {
-                self.chat_app.chat_dispatch(event);
+                if let Some(chat_app) = self.chat_app.as_mut()
+                    && let Err(err) = chat_app.chat_dispatch(event)
+                {
+                    tracing::error!("Failed to dispatch command: {err}");
+                }
                 false
             }
             AppMsg::ChangeUserName(_user_name) => true,
@@ -53,12 +99,25 @@ fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
+        if let Some(err) = &self.error {
+            return html! {
+                <div class="streuen-chat-settings-window">
+                    <div class="streuen-chat-settings-header">{ "Failed to start the chat" }</div>
+                    <p class="streuen-settings-status">{ err.to_string() }</p>
+                </div>
+            };
+        }
+        let Some(chat_app) = self.chat_app.as_ref() else {
+            return html! {};
+        };
         let swarm_dispatch = ctx.link().callback(AppMsg::SwarmDispatchEvent);
         let register_app_cb = ctx.link().callback(AppMsg::RegisterAppHandler);
         html! {
             <>
                 <Chat
-                    peer_id={self.chat_app.current_user().peer_id()}
+                    peer_id={chat_app.current_user().peer_id()}
+                    messages={chat_app.messages()}
+                    commands={chat_app.commands()}
                     swarm_dispatch_cb={swarm_dispatch.clone()}
                     register_app_cb={register_app_cb.clone()}
                 />
@@ -1,3 +1,5 @@
+use libp2p::PeerId;
+use streuen_chat::app::messages::{MessageId, MessageStatus};
 use yew::prelude::*;
 
 #[derive(Clone, PartialEq)]
@@ -5,6 +7,49 @@ pub struct Message {
     pub text: String,
     pub sender: String,
     pub recipient: String,
+    /// Id of a direct message of the chat app.
+    pub message_id: Option<MessageId>,
+    /// Delivery state of a direct message sent by us.
+    pub status: Option<MessageStatus>,
+}
+
+impl Message {
+    /// Converts a message of the chat app, naming the local user "me".
+    pub fn from_app(message: &streuen_chat::app::messages::Message, local_peer_id: PeerId) -> Self {
+        let name = |peer_id: PeerId| {
+            if peer_id == local_peer_id {
+                "me".to_string()
+            } else {
+                peer_id.to_base58()
+            }
+        };
+        Self {
+            text: message.text().to_string(),
+            sender: name(message.from()),
+            recipient: name(message.to()),
+            message_id: Some(message.message_id()),
+            status: (message.from() == local_peer_id).then_some(message.status()),
+        }
+    }
+
+    /// Converts a room message of the chat app, using `#<room id>` as the recipient.
+    pub fn from_room(
+        message: &streuen_chat::app::messages::RoomMessage,
+        local_peer_id: PeerId,
+    ) -> Self {
+        let sender = if message.from() == local_peer_id {
+            "me".to_string()
+        } else {
+            message.from().to_base58()
+        };
+        Self {
+            text: message.text().to_string(),
+            sender,
+            recipient: format!("#{}", message.room_id()),
+            message_id: None,
+            status: None,
+        }
+    }
 }
 
 #[derive(Properties, PartialEq)]
@@ -20,11 +65,7 @@ pub fn chat_messages(props: &ChatMessagesProps) -> Html {
             {
                 props.messages.iter().enumerate().map(|(i, msg)| {
                     let is_me = msg.sender == props.current_user;
-                    let show_sender = if i == 0 || props.messages[i-1].sender != msg.sender {
-                        true
-                    } else {
-                        false
-                    };
+                    let show_sender = i == 0 || props.messages[i-1].sender != msg.sender;
                     html! {
                         <div class={classes!("streuen-message-row", if is_me { Some("me") } else { None })}>
                             { if show_sender {
@@ -33,6 +74,11 @@ pub fn chat_messages(props: &ChatMessagesProps) -> Html {
                                 html! {}
                             }}
                             <div class="streuen-message-bubble">{ &msg.text }</div>
+                            { if let Some(status) = msg.status {
+                                html! { <span class="streuen-message-status">{ status_marker(status) }</span> }
+                            } else {
+                                html! {}
+                            }}
                         </div>
                     }
                 }).collect::<Html>()
@@ -40,3 +86,12 @@ pub fn chat_messages(props: &ChatMessagesProps) -> Html {
         </div>
     }
 }
+
+fn status_marker(status: MessageStatus) -> &'static str {
+    match status {
+        MessageStatus::Queued => "Queued",
+        MessageStatus::Sent => "Sent",
+        MessageStatus::Delivered => "Delivered",
+        MessageStatus::Failed => "Failed to send",
+    }
+}
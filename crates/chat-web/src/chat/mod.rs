@@ -4,9 +4,12 @@
 mod users_panel;
 mod window;
 
+use std::{collections::HashMap, str::FromStr};
+
 use streuen_chat::app;
 use yew::prelude::*;
 
+use crate::chat::messages::Message;
 use crate::chat::navigation::Navigation;
 use crate::chat::settings_menu::SettingsMenu;
 use crate::chat::users_panel::UsersPanel;
@@ -14,25 +17,42 @@
 
 pub enum ChatMsg {
     SelectUser(String),
-    Bootstrap(libp2p::Multiaddr),
-    Connect(libp2p::PeerId),
     AddUser(String),
     RemoveUser(String),
+    JoinRoom(String),
+    LeaveRoom(String),
     ToggleSettings,
+    SendMessage(String),
+    HistoryLoaded(Vec<app::messages::Message>),
     Receive(app::ToApp),
 }
 
+/// Number of messages per conversation shown on startup.
+const HISTORY_PAGE_SIZE: usize = 50;
+
 #[derive(Properties, PartialEq)]
 pub struct ChatProps {
     pub peer_id: libp2p::PeerId,
+    pub messages: app::messages::Messages,
     pub swarm_dispatch_cb: Callback<app::ToChat>,
+    /// Used by the settings to report the outcome of their commands.
+    pub commands: app::ChatCommands,
     pub register_app_cb: Callback<app::AppCallback>,
 }
 
 pub struct Chat {
     users: Vec<String>,
+    /// Rooms joined, without the `#` prefix used to select them.
+    rooms: Vec<String>,
     selected_user: String,
+    messages: Vec<Message>,
     settings_open: bool,
+    /// The latest step taken to reach a peer, shown in the settings.
+    connecting: Option<(libp2p::PeerId, app::ConnectStep)>,
+    /// How other peers can reach us, shown in the settings.
+    network_status: app::NetworkStatus,
+    /// Latest ping results of the connected users, keyed like `users`.
+    latencies: HashMap<String, app::network::Latency>,
 }
 
 impl Component for Chat {
@@ -46,10 +66,43 @@ fn create(ctx: &Context<Self>) -> Self {
             .emit(app::AppCallback::from(move |event| {
                 receive_app_event_cb.emit(event)
             }));
+
+        let link = ctx.link().clone();
+        let messages = ctx.props().messages.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut history = Vec::new();
+            let conversations = match messages.conversations().await {
+                Ok(conversations) => conversations,
+                Err(err) => {
+                    tracing::error!("Failed to load conversations: {err}");
+                    Vec::new()
+                }
+            };
+            for peer_id in conversations {
+                match messages.history(peer_id, None, HISTORY_PAGE_SIZE).await {
+                    Ok(page) => history.extend(page),
+                    Err(err) => tracing::error!("Failed to load history with {peer_id}: {err}"),
+                }
+            }
+            history.sort_by_key(|message| message.timestamp());
+            link.send_message(ChatMsg::HistoryLoaded(history));
+        });
+
         Self {
             users: vec!["me".to_string(), "alice".to_string()],
+            rooms: Vec::new(),
             selected_user: "me".to_string(),
+            messages: vec![Message {
+                text: "Welcome to the chat!".to_string(),
+                sender: "system".to_string(),
+                recipient: "me".to_string(),
+                message_id: None,
+                status: None,
+            }],
             settings_open: false,
+            connecting: None,
+            network_status: app::NetworkStatus::default(),
+            latencies: HashMap::new(),
         }
     }
 
@@ -61,6 +114,11 @@ fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
                 true
             }
             ChatMsg::AddUser(user) => {
+                if let Ok(peer_id) = libp2p::PeerId::from_str(&user) {
+                    ctx.props()
+                        .swarm_dispatch_cb
+                        .emit(app::ToChat::AddContact(peer_id));
+                }
                 if !self.users.contains(&user) {
                     self.users.push(user.clone());
                     self.selected_user = user.clone();
@@ -73,6 +131,11 @@ fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
                 }
             }
             ChatMsg::RemoveUser(user) => {
+                if let Ok(peer_id) = libp2p::PeerId::from_str(&user) {
+                    ctx.props()
+                        .swarm_dispatch_cb
+                        .emit(app::ToChat::RemoveContact(peer_id));
+                }
                 if let Some(pos) = self.users.iter().position(|u| u == &user) {
                     self.users.remove(pos);
                     if self.selected_user == user {
@@ -87,27 +150,156 @@ fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
                     false
                 }
             }
-            ChatMsg::ToggleSettings => {
-                self.settings_open = !self.settings_open;
-                true
-            }
-            ChatMsg::Bootstrap(addr) => {
-                tracing::debug!("Boostrap: {addr}");
+            ChatMsg::JoinRoom(room_id) => {
+                // listed once the chat app reports the room as joined
                 ctx.props()
                     .swarm_dispatch_cb
-                    .emit(app::ToChat::AddBoostrapPeer(addr));
+                    .emit(app::ToChat::JoinRoom(room_id));
                 false
             }
-            ChatMsg::Connect(peer_id) => {
-                tracing::debug!("Connect: {peer_id}");
+            ChatMsg::LeaveRoom(room_id) => {
                 ctx.props()
                     .swarm_dispatch_cb
-                    .emit(app::ToChat::Connect(peer_id));
+                    .emit(app::ToChat::LeaveRoom(room_id));
                 false
             }
+            ChatMsg::ToggleSettings => {
+                self.settings_open = !self.settings_open;
+                true
+            }
+            ChatMsg::SendMessage(text) => {
+                let selected_user = self.selected_user.clone();
+                if let Some(room_id) = selected_user.strip_prefix('#') {
+                    ctx.props()
+                        .swarm_dispatch_cb
+                        .emit(app::ToChat::SendRoomMessage(room_id.to_string(), text));
+                    return false;
+                }
+                match libp2p::PeerId::from_str(&selected_user) {
+                    // shown once the chat app reports the message as sent
+                    Ok(peer_id) => {
+                        ctx.props()
+                            .swarm_dispatch_cb
+                            .emit(app::ToChat::SendMessage(peer_id, text));
+                        false
+                    }
+                    Err(_) => {
+                        tracing::debug!("Not sending message to local user: {selected_user}");
+                        self.messages.push(Message {
+                            text,
+                            sender: "me".to_string(),
+                            recipient: selected_user,
+                            message_id: None,
+                            status: None,
+                        });
+                        true
+                    }
+                }
+            }
+            ChatMsg::HistoryLoaded(history) => {
+                let local_peer_id = ctx.props().peer_id;
+                for message in &history {
+                    let user = message.conversation(local_peer_id).to_base58();
+                    if !self.users.contains(&user) {
+                        self.users.push(user);
+                    }
+                }
+                let history = history
+                    .iter()
+                    .map(|message| Message::from_app(message, local_peer_id));
+                // history is older than anything received since startup
+                self.messages.splice(1..1, history);
+                true
+            }
             ChatMsg::Receive(msg) => {
                 tracing::debug!("Received message in chat app: {msg:?}");
-                false
+                match msg {
+                    app::ToApp::MessageSent(message) => {
+                        self.messages
+                            .push(Message::from_app(&message, ctx.props().peer_id));
+                        true
+                    }
+                    app::ToApp::MessageReceived(message) => {
+                        let sender = message.from().to_base58();
+                        if !self.users.contains(&sender) {
+                            self.users.push(sender);
+                        }
+                        self.messages
+                            .push(Message::from_app(&message, ctx.props().peer_id));
+                        true
+                    }
+                    app::ToApp::MessageStatusChanged {
+                        message_id,
+                        to,
+                        status,
+                    } => {
+                        let recipient = to.to_base58();
+                        match self.messages.iter_mut().find(|message| {
+                            message.sender == "me"
+                                && message.recipient == recipient
+                                && message.message_id == Some(message_id)
+                        }) {
+                            Some(message) => {
+                                message.status = Some(status);
+                                true
+                            }
+                            None => false,
+                        }
+                    }
+                    app::ToApp::RoomJoined(room_id) => {
+                        self.selected_user = format!("#{room_id}");
+                        if !self.rooms.contains(&room_id) {
+                            self.rooms.push(room_id);
+                        }
+                        true
+                    }
+                    app::ToApp::RoomLeft(room_id) => {
+                        self.rooms.retain(|r| *r != room_id);
+                        if self.selected_user == format!("#{room_id}") {
+                            self.selected_user = "me".to_string();
+                        }
+                        true
+                    }
+                    app::ToApp::RoomMessageSent(message)
+                    | app::ToApp::RoomMessageReceived(message) => {
+                        self.messages
+                            .push(Message::from_room(&message, ctx.props().peer_id));
+                        true
+                    }
+                    app::ToApp::PeerConnected(peer_id)
+                    | app::ToApp::PeerDiscovered {
+                        peer_id,
+                        source: app::DiscoverySource::Rendezvous(_),
+                    } => {
+                        let user = peer_id.to_base58();
+                        if !self.users.contains(&user) {
+                            self.users.push(user);
+                            true
+                        } else {
+                            false
+                        }
+                    }
+                    app::ToApp::Connecting { peer_id, step } => {
+                        self.connecting = Some((peer_id, step));
+                        self.settings_open
+                    }
+                    app::ToApp::PeerLatency { peer_id, latency } => {
+                        self.latencies.insert(peer_id.to_base58(), latency);
+                        true
+                    }
+                    app::ToApp::PeerDisconnected(peer_id) => {
+                        self.latencies.remove(&peer_id.to_base58()).is_some()
+                    }
+                    app::ToApp::NetworkStatusChanged(status) => {
+                        self.network_status = status;
+                        self.settings_open
+                    }
+                    app::ToApp::Error(error) => {
+                        tracing::warn!("Chat error: {error}");
+                        false
+                    }
+                    _ => false,
+                }
             }
         }
     }
@@ -117,9 +309,10 @@ fn view(&self, ctx: &Context<Self>) -> Html {
         let on_select_user = ctx.link().callback(ChatMsg::SelectUser);
         let on_add_user = ctx.link().callback(ChatMsg::AddUser);
         let on_remove_user = ctx.link().callback(ChatMsg::RemoveUser);
+        let on_join_room = ctx.link().callback(ChatMsg::JoinRoom);
+        let on_leave_room = ctx.link().callback(ChatMsg::LeaveRoom);
         let on_toggle_settings = ctx.link().callback(|_| ChatMsg::ToggleSettings);
-        let on_boostrap = ctx.link().callback(ChatMsg::Bootstrap);
-        let on_connect = ctx.link().callback(ChatMsg::Connect);
+        let on_send = ctx.link().callback(ChatMsg::SendMessage);
 
         html! {
             <>
@@ -129,10 +322,14 @@ fn view(&self, ctx: &Context<Self>) -> Html {
                         <div style="width: 220px; min-width: 220px; border-right: 1px solid #23272a;">
                             <UsersPanel
                                 users={self.users.clone()}
+                                latencies={self.latencies.clone()}
                                 selected_user={self.selected_user.clone()}
                                 on_select_user={on_select_user}
                                 on_add_user={on_add_user}
                                 on_remove_user={on_remove_user}
+                                rooms={self.rooms.clone()}
+                                on_join_room={on_join_room}
+                                on_leave_room={on_leave_room}
                             />
                         </div>
                         <div style="flex: 1; display: flex; justify-content: center; align-items: stretch; min-width: 0;">
@@ -142,11 +339,18 @@ fn view(&self, ctx: &Context<Self>) -> Html {
                                         <SettingsMenu
                                             peer_id={ ctx.props().peer_id }
                                             on_close={on_toggle_settings.clone()}
-                                            bootstrap={on_boostrap}
-                                            connect={on_connect}
+                                            commands={ctx.props().commands.clone()}
+                                            connecting={self.connecting}
+                                            network_status={self.network_status.clone()}
                                         /> }
                                 } else {
-                                    html! { <ChatWindow selected_user={self.selected_user.clone()} /> }
+                                    html! {
+                                        <ChatWindow
+                                            selected_user={self.selected_user.clone()}
+                                            messages={self.messages.clone()}
+                                            on_send={on_send}
+                                        />
+                                    }
                                 }
                             }
                         </div>
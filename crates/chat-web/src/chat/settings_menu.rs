@@ -1,6 +1,7 @@
 use std::str::FromStr;
 
 use libp2p::PeerId;
+use streuen_chat::app;
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
 
@@ -9,18 +10,29 @@ pub enum SettingsMenuMsg {
     CloseSettings,
     InputConnectPeer(String),
     ConnectToPeer,
+    InputNamespace(String),
+    RegisterNamespace,
+    CommandCompleted(Result<(), String>),
 }
 
 #[derive(Properties, PartialEq)]
 pub struct SettingsMenuProps {
     pub peer_id: PeerId,
     pub on_close: Callback<()>,
-    pub bootstrap: Callback<libp2p::Multiaddr>,
-    pub connect: Callback<PeerId>,
+    pub commands: app::ChatCommands,
+    /// The latest step taken to reach a peer.
+    pub connecting: Option<(PeerId, app::ConnectStep)>,
+    pub network_status: app::NetworkStatus,
 }
 
 pub struct SettingsMenu {
     connect_string: String,
+    /// Team namespace to register in, or the address of a rendezvous point to register at.
+    namespace: String,
+    /// Outcome of the last connect, if any.
+    status: Option<String>,
+    /// Peer being connected to, whose progress is shown until the connect completed.
+    pending: Option<PeerId>,
 }
 
 impl Component for SettingsMenu {
@@ -30,6 +42,9 @@ impl Component for SettingsMenu {
     fn create(_ctx: &Context<Self>) -> Self {
         Self {
             connect_string: String::new(),
+            namespace: String::new(),
+            status: None,
+            pending: None,
         }
     }
 
@@ -60,13 +75,55 @@ fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
             }
             SettingsMenuMsg::ConnectToPeer => {
                 tracing::debug!("Connect to peer: {}", self.connect_string);
-                if let Ok(addr) = libp2p::Multiaddr::from_str(&self.connect_string) {
-                    ctx.props().bootstrap.emit(addr);
-                }
-                if let Ok(peer_id) = libp2p::PeerId::from_str(&self.connect_string) {
-                    ctx.props().connect.emit(peer_id);
-                }
+                let command = if let Ok(addr) = libp2p::Multiaddr::from_str(&self.connect_string) {
+                    app::ToChat::Dial(addr)
+                } else if let Ok(peer_id) = libp2p::PeerId::from_str(&self.connect_string) {
+                    app::ToChat::Connect(peer_id)
+                } else {
+                    self.status =
+                        Some(format!("Not a peer id or address: {}", self.connect_string));
+                    return true;
+                };
+                self.status = Some("Connecting...".to_string());
+                self.pending = match &command {
+                    app::ToChat::Connect(peer_id) => Some(*peer_id),
+                    app::ToChat::Dial(addr) => match addr.iter().last() {
+                        Some(libp2p::multiaddr::Protocol::P2p(peer_id)) => Some(peer_id),
+                        _ => None,
+                    },
+                    _ => None,
+                };
                 self.connect_string = String::new();
+                execute(ctx, command);
+                true
+            }
+            SettingsMenuMsg::InputNamespace(namespace) => {
+                self.namespace = namespace;
+                true
+            }
+            SettingsMenuMsg::RegisterNamespace => {
+                let namespace = std::mem::take(&mut self.namespace);
+                if namespace.is_empty() {
+                    return false;
+                }
+                // rendezvous points are entered in the same field as they are rarely changed
+                let command = if let Ok(addr) = libp2p::Multiaddr::from_str(&namespace) {
+                    self.status = Some("Adding rendezvous point...".to_string());
+                    app::ToChat::AddRendezvousPoint(addr)
+                } else {
+                    self.status = Some(format!("Registering in {namespace}..."));
+                    app::ToChat::RegisterNamespace(namespace)
+                };
+                self.pending = None;
+                execute(ctx, command);
+                true
+            }
+            SettingsMenuMsg::CommandCompleted(result) => {
+                self.pending = None;
+                self.status = Some(match result {
+                    Ok(()) => "Done".to_string(),
+                    Err(err) => err,
+                });
                 true
             }
         }
@@ -87,6 +144,29 @@ fn view(&self, ctx: &Context<Self>) -> Html {
             SettingsMenuMsg::ConnectToPeer
         });
         let connect_to_peer_click = ctx.link().callback(|_| SettingsMenuMsg::ConnectToPeer);
+        let on_namespace_input = ctx.link().callback(|e: InputEvent| {
+            let input: Option<HtmlInputElement> = e.target_dyn_into();
+            SettingsMenuMsg::InputNamespace(input.map(|i| i.value()).unwrap_or_default())
+        });
+        let register_namespace_submit = ctx.link().callback(|e: SubmitEvent| {
+            e.prevent_default();
+            SettingsMenuMsg::RegisterNamespace
+        });
+        let register_namespace_click = ctx.link().callback(|_| SettingsMenuMsg::RegisterNamespace);
+        let status = match (self.pending, ctx.props().connecting) {
+            (Some(pending), Some((peer_id, step))) if pending == peer_id => Some(match step {
+                app::ConnectStep::Dialing => "Dialing...".to_string(),
+                app::ConnectStep::LookingUp => "Looking up peer in the DHT...".to_string(),
+                app::ConnectStep::Relaying => "Dialing through a relay...".to_string(),
+            }),
+            _ => self.status.clone(),
+        };
+        let network = &ctx.props().network_status;
+        let hole_punches_succeeded = network
+            .hole_punches
+            .values()
+            .filter(|hole_punch| **hole_punch == app::network::HolePunch::Succeeded)
+            .count();
         html! {
             <>
                 <div class="streuen-chat-settings-window">
@@ -101,6 +181,15 @@ fn view(&self, ctx: &Context<Self>) -> Html {
                         <p>{ ctx.props().peer_id.to_base58() }</p>
                         <button class="streuen-settings-bubble" onclick={copy_peer_id_to_clipboard}>{ "Copy" }</button>
                     </div>
+                    <div class="streuen-settings-row" style="position: relative">
+                        <p>{ "Network:" }</p>
+                        <p>{ format!("Reachability {}", network.reachability) }</p>
+                        <p>{ format!("{} relay reservations", network.relay_reservations.len()) }</p>
+                        <p>{ format!(
+                            "{hole_punches_succeeded} of {} hole punches succeeded",
+                            network.hole_punches.len()
+                        ) }</p>
+                    </div>
                     <div class="streuen-settings-row" style="position: relative">
                         <form class="streuen-chat-add-user-form" onsubmit={connect_to_peer_submit}>
                             <p>{ "Connect:"}</p>
@@ -108,13 +197,42 @@ fn view(&self, ctx: &Context<Self>) -> Html {
                                 type="text"
                                 value={self.connect_string.clone()}
                                 oninput={on_connect_input}
-                                placeholder="Enter address or peer id..."
+                                placeholder="Enter peer id or address ending with /p2p/<peer id>..."
                             />
                             <button class="streuen-settings-bubble" onclick={connect_to_peer_click}>{ "Connect" }</button>
                         </form>
+                        <form class="streuen-chat-add-user-form" onsubmit={register_namespace_submit}>
+                            <p>{ "Team:"}</p>
+                            <input
+                                type="text"
+                                value={self.namespace.clone()}
+                                oninput={on_namespace_input}
+                                placeholder="Enter team namespace or rendezvous point address..."
+                            />
+                            <button class="streuen-settings-bubble" onclick={register_namespace_click}>{ "Join" }</button>
+                        </form>
+                        {
+                            if let Some(status) = status {
+                                html! { <p class="streuen-settings-status">{ status }</p> }
+                            } else {
+                                html! {}
+                            }
+                        }
                     </div>
                 </div>
             </>
         }
     }
 }
+
+/// Runs `command`, reporting its outcome back to the menu.
+fn execute(ctx: &Context<SettingsMenu>, command: app::ToChat) {
+    let mut commands = ctx.props().commands.clone();
+    let link = ctx.link().clone();
+    wasm_bindgen_futures::spawn_local(async move {
+        let result = commands.execute(command).await;
+        link.send_message(SettingsMenuMsg::CommandCompleted(
+            result.map_err(|err| err.to_string()),
+        ));
+    });
+}
@@ -1,22 +1,34 @@
+use std::collections::HashMap;
+
+use streuen_chat::app::network::Latency;
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
 
 pub enum UserPanelMsg {
     NewUser(String),
     AddUser,
+    NewRoom(String),
+    JoinRoom,
 }
 
 #[derive(Properties, PartialEq)]
 pub struct UsersPanelProps {
     pub users: Vec<String>,
+    /// Latest ping results of the connected users.
+    pub latencies: HashMap<String, Latency>,
     pub selected_user: String,
     pub on_select_user: Callback<String>,
     pub on_add_user: Callback<String>,
     pub on_remove_user: Callback<String>,
+    /// Rooms joined, selected as `#<room id>`.
+    pub rooms: Vec<String>,
+    pub on_join_room: Callback<String>,
+    pub on_leave_room: Callback<String>,
 }
 
 pub struct UsersPanel {
     new_user: String,
+    new_room: String,
 }
 
 impl Component for UsersPanel {
@@ -26,6 +38,7 @@ impl Component for UsersPanel {
     fn create(_ctx: &Context<Self>) -> Self {
         Self {
             new_user: String::new(),
+            new_room: String::new(),
         }
     }
 
@@ -45,6 +58,20 @@ fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
                     false
                 }
             }
+            UserPanelMsg::NewRoom(val) => {
+                self.new_room = val;
+                true
+            }
+            UserPanelMsg::JoinRoom => {
+                let room_id = self.new_room.trim().trim_start_matches('#').to_string();
+                if !room_id.is_empty() {
+                    ctx.props().on_join_room.emit(room_id);
+                    self.new_room = String::new();
+                    true
+                } else {
+                    false
+                }
+            }
         }
     }
 
@@ -57,6 +84,14 @@ fn view(&self, ctx: &Context<Self>) -> Html {
             e.prevent_default();
             UserPanelMsg::AddUser
         });
+        let oninput_room = ctx.link().callback(|e: InputEvent| {
+            let input: Option<HtmlInputElement> = e.target_dyn_into();
+            UserPanelMsg::NewRoom(input.map(|i| i.value()).unwrap_or_default())
+        });
+        let onsubmit_room = ctx.link().callback(|e: SubmitEvent| {
+            e.prevent_default();
+            UserPanelMsg::JoinRoom
+        });
 
         html! {
             <>
@@ -85,6 +120,19 @@ fn view(&self, ctx: &Context<Self>) -> Html {
                                     onclick={on_click}
                                 >
                                     <span>{ user }</span>
+                                    { if let Some(latency) = ctx.props().latencies.get(user)
+                                        && let Some(quality) = latency.quality()
+                                    {
+                                        let title = match latency.average() {
+                                            Some(rtt) => format!("{quality}, {} ms", rtt.as_millis()),
+                                            None => quality.to_string(),
+                                        };
+                                        html! {
+                                            <span class={classes!("user-list-item-quality", quality.to_string())} {title}></span>
+                                        }
+                                    } else {
+                                        html! {}
+                                    }}
                                     { if show_remove {
                                         html! {
                                             <button class="user-list-item-remove" onclick={on_remove}>{ "✕" }</button>
@@ -104,6 +152,43 @@ fn view(&self, ctx: &Context<Self>) -> Html {
                             placeholder="Add user..."
                         />
                     </form>
+                    <h3>{ "ROOMS" }</h3>
+                    <ul class="streuen-chat-user-list" style="list-style: none; margin: 0;">
+                        { for ctx.props().rooms.iter().map(|room_id| {
+                            let room = format!("#{room_id}");
+                            let is_selected = room == ctx.props().selected_user;
+                            let on_click = {
+                                let room = room.clone();
+                                let on_select_user = ctx.props().on_select_user.clone();
+                                Callback::from(move |_| on_select_user.emit(room.clone()))
+                            };
+                            let on_leave = {
+                                let room_id = room_id.clone();
+                                let on_leave_room = ctx.props().on_leave_room.clone();
+                                Callback::from(move |e: MouseEvent| {
+                                    e.stop_propagation();
+                                    on_leave_room.emit(room_id.clone());
+                                })
+                            };
+                            html! {
+                                <li
+                                    class={classes!("user-list-item", if is_selected { Some("selected") } else { None })}
+                                    onclick={on_click}
+                                >
+                                    <span>{ room }</span>
+                                    <button class="user-list-item-remove" onclick={on_leave}>{ "✕" }</button>
+                                </li>
+                            }
+                        }) }
+                    </ul>
+                    <form class="streuen-chat-add-user-form" onsubmit={onsubmit_room}>
+                        <input
+                            type="text"
+                            value={self.new_room.clone()}
+                            oninput={oninput_room}
+                            placeholder="Join room..."
+                        />
+                    </form>
                 </div>
             </>
         }
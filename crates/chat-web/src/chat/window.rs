@@ -12,10 +12,11 @@ pub enum ChatWindowMsg {
 #[derive(Properties, PartialEq)]
 pub struct ChatWindowProps {
     pub selected_user: String,
+    pub messages: Vec<Message>,
+    pub on_send: Callback<String>,
 }
 
 pub struct ChatWindow {
-    messages: Vec<Message>,
     input_value: String,
     current_user: String,
 }
@@ -26,11 +27,6 @@ impl Component for ChatWindow {
 
     fn create(_ctx: &Context<Self>) -> Self {
         Self {
-            messages: vec![Message {
-                text: "Welcome to the chat!".to_string(),
-                sender: "system".to_string(),
-                recipient: "me".to_string(),
-            }],
             input_value: String::new(),
             current_user: "me".to_string(),
         }
@@ -45,12 +41,7 @@ fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
             }
             ChatWindowMsg::SendMessage => {
                 if !self.input_value.is_empty() {
-                    let selected_user = ctx.props().selected_user.clone();
-                    self.messages.push(Message {
-                        text: self.input_value.clone(),
-                        sender: self.current_user.clone(),
-                        recipient: selected_user,
-                    });
+                    ctx.props().on_send.emit(self.input_value.clone());
                     self.input_value.clear();
                     true
                 } else {
@@ -70,15 +61,17 @@ fn view(&self, ctx: &Context<Self>) -> Html {
             ChatWindowMsg::SendMessage
         });
         let selected_user = &ctx.props().selected_user;
-        let filtered_messages: Vec<Message> = self
+        let filtered_messages: Vec<Message> = ctx
+            .props()
             .messages
             .iter()
-            .cloned()
             .filter(|msg| {
-                (msg.sender == self.current_user && &msg.recipient == selected_user)
-                    || (msg.sender == *selected_user && msg.recipient == self.current_user)
-                    || (msg.sender == "system" && msg.recipient == self.current_user)
+                let to_selected = msg.recipient == *selected_user;
+                let to_me = msg.recipient == self.current_user;
+                (to_selected && (selected_user.starts_with('#') || msg.sender == self.current_user))
+                    || (to_me && (msg.sender == *selected_user || msg.sender == "system"))
             })
+            .cloned()
             .collect();
         html! {
             <>
@@ -0,0 +1,111 @@
+use libp2p::{PeerId, identity::Keypair};
+use streuen_chat::app::{StorageError, peers::PeerStore, sessions::SessionStore};
+
+/// Local storage key holding the hex encoded protobuf of the node identity.
+const IDENTITY_KEY: &str = "streuen-chat-identity";
+
+/// Prefix of the local storage keys holding the hex encoded end-to-end encryption sessions.
+const SESSION_KEY_PREFIX: &str = "streuen-chat-session-";
+
+/// Local storage key holding the hex encoded addresses of known peers.
+const PEERS_KEY: &str = "streuen-chat-peers";
+
+/// Loads the node identity from the browser's local storage, generating and storing a new one if
+/// none exists yet.
+///
+/// When local storage is unavailable (e.g. disabled by the browser) a new identity is generated
+/// for this session only.
+pub fn load_or_generate_keypair() -> Keypair {
+    let storage = web_sys::window().and_then(|window| window.local_storage().ok().flatten());
+
+    let stored = storage
+        .as_ref()
+        .and_then(|storage| storage.get_item(IDENTITY_KEY).ok().flatten())
+        .and_then(|encoded| decode_hex(&encoded))
+        .and_then(|bytes| Keypair::from_protobuf_encoding(&bytes).ok());
+    if let Some(keypair) = stored {
+        return keypair;
+    }
+
+    let keypair = Keypair::generate_ed25519();
+    match (storage, keypair.to_protobuf_encoding()) {
+        (Some(storage), Ok(bytes)) => {
+            if storage.set_item(IDENTITY_KEY, &encode_hex(&bytes)).is_err() {
+                tracing::warn!("Failed to store identity, it will not survive a reload");
+            }
+        }
+        _ => tracing::warn!("Local storage unavailable, identity will not survive a reload"),
+    }
+    keypair
+}
+
+fn local_storage() -> Result<web_sys::Storage, StorageError> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or_else(|| StorageError::WebStorage("local storage unavailable".to_string()))
+}
+
+/// Reads a hex encoded value from local storage.
+fn load_item(key: &str) -> Result<Option<Vec<u8>>, StorageError> {
+    let Some(encoded) = local_storage()?
+        .get_item(key)
+        .map_err(|err| StorageError::WebStorage(format!("{err:?}")))?
+    else {
+        return Ok(None);
+    };
+    decode_hex(&encoded)
+        .map(Some)
+        .ok_or_else(|| StorageError::Serialization("invalid hex".to_string()))
+}
+
+fn save_item(key: &str, value: &[u8]) -> Result<(), StorageError> {
+    local_storage()?
+        .set_item(key, &encode_hex(value))
+        .map_err(|err| StorageError::WebStorage(format!("{err:?}")))
+}
+
+/// Keeps the end-to-end encryption sessions in local storage next to the identity.
+pub struct LocalStorageSessionStore;
+
+impl LocalStorageSessionStore {
+    fn key(peer_id: PeerId) -> String {
+        format!("{SESSION_KEY_PREFIX}{peer_id}")
+    }
+}
+
+impl SessionStore for LocalStorageSessionStore {
+    fn load(&self, peer_id: PeerId) -> Result<Option<Vec<u8>>, StorageError> {
+        load_item(&Self::key(peer_id))
+    }
+
+    fn save(&mut self, peer_id: PeerId, session: &[u8]) -> Result<(), StorageError> {
+        save_item(&Self::key(peer_id), session)
+    }
+}
+
+/// Keeps the addresses of known peers in local storage.
+pub struct LocalStoragePeerStore;
+
+impl PeerStore for LocalStoragePeerStore {
+    fn load(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        load_item(PEERS_KEY)
+    }
+
+    fn save(&mut self, peers: &[u8]) -> Result<(), StorageError> {
+        save_item(PEERS_KEY, peers)
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn decode_hex(encoded: &str) -> Option<Vec<u8>> {
+    if !encoded.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..encoded.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(encoded.get(i..i + 2)?, 16).ok())
+        .collect()
+}
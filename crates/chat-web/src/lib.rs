@@ -2,3 +2,5 @@
 pub mod app;
 #[cfg(target_arch = "wasm32")]
 mod chat;
+#[cfg(target_arch = "wasm32")]
+mod identity;
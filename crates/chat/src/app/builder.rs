@@ -0,0 +1,332 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc, time::Duration};
+
+use futures_channel::mpsc;
+use libp2p::{Multiaddr, gossipsub, identity::Keypair, kad, ping, request_response};
+
+use super::{
+    ChatApp, NetworkConfig, STREUEN_KAD_PROTOCOL, SwarmExecutor, error, messages, peers, sessions,
+    users,
+};
+use crate::libp2p::SwarmStores;
+
+/// Configures and starts a [`ChatApp`].
+///
+/// Without further configuration the app gets a fresh identity, keeps its history and sessions in
+/// memory, does not listen on any address and discovers peers with every mechanism available on
+/// the target. Rendezvous only discovers peers once rendezvous points and namespaces are added.
+pub struct ChatAppBuilder {
+    name: String,
+    keypair: Option<Keypair>,
+    message_store: Rc<dyn messages::MessageStore>,
+    session_store: Box<dyn sessions::SessionStore>,
+    peer_store: Box<dyn peers::PeerStore>,
+    network: NetworkConfig,
+    executor: SwarmExecutor,
+}
+
+impl Default for ChatAppBuilder {
+    fn default() -> Self {
+        Self {
+            name: "Me".to_string(),
+            keypair: None,
+            message_store: Rc::new(messages::MemoryMessageStore::default()),
+            session_store: Box::new(sessions::MemorySessionStore::default()),
+            peer_store: Box::new(peers::MemoryPeerStore::default()),
+            network: NetworkConfig::default(),
+            executor: SwarmExecutor::default(),
+        }
+    }
+}
+
+impl ChatAppBuilder {
+    pub fn name(mut self, name: String) -> Self {
+        self.name = name;
+        self
+    }
+
+    /// Uses an existing identity, so the [`libp2p::PeerId`] stays the same across restarts.
+    pub fn keypair(mut self, keypair: Keypair) -> Self {
+        self.keypair = Some(keypair);
+        self
+    }
+
+    /// Records the message history in `message_store`.
+    pub fn message_store(mut self, message_store: impl messages::MessageStore + 'static) -> Self {
+        self.message_store = Rc::new(message_store);
+        self
+    }
+
+    /// Records the end-to-end encryption sessions in `session_store`.
+    pub fn session_store(mut self, session_store: impl sessions::SessionStore + 'static) -> Self {
+        self.session_store = Box::new(session_store);
+        self
+    }
+
+    /// Records the addresses of known peers in `peer_store`, to reach them again after a restart.
+    pub fn peer_store(mut self, peer_store: impl peers::PeerStore + 'static) -> Self {
+        self.peer_store = Box::new(peer_store);
+        self
+    }
+
+    /// Replaces all network settings made so far.
+    pub fn network_config(mut self, config: NetworkConfig) -> Self {
+        self.network = config;
+        self
+    }
+
+    /// Runs the swarm with `executor` rather than on the current tokio runtime.
+    pub fn swarm_executor(mut self, executor: SwarmExecutor) -> Self {
+        self.executor = executor;
+        self
+    }
+
+    /// Adds an address to listen on once the app started.
+    pub fn listen_on(mut self, addr: Multiaddr) -> Self {
+        self.network.listen_addresses.push(addr);
+        self
+    }
+
+    /// Adds an address we are reachable at, which autonat cannot confirm, e.g. a forwarded port.
+    pub fn external_address(mut self, addr: Multiaddr) -> Self {
+        self.network.external_addresses.push(addr);
+        self
+    }
+
+    /// Adds a Kademlia bootstrap peer, the address has to end with its `/p2p` peer id.
+    pub fn bootstrap_peer(mut self, addr: Multiaddr) -> Self {
+        self.network.bootstrap_peers.push(addr);
+        self
+    }
+
+    /// Discovers peers on the local network. Has no effect in the browser.
+    pub fn mdns(mut self, enabled: bool) -> Self {
+        self.network.mdns = enabled;
+        self
+    }
+
+    /// Asks the gateway to forward a port to our listen addresses. Has no effect in the browser.
+    pub fn upnp(mut self, enabled: bool) -> Self {
+        self.network.upnp = enabled;
+        self
+    }
+
+    /// Joins the private network of the peers knowing `key`, connections to other peers fail. Only
+    /// TCP is used then, as QUIC, WebSocket and WebRTC connections cannot be restricted to the
+    /// network.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn pre_shared_key(mut self, key: libp2p::pnet::PreSharedKey) -> Self {
+        self.network.pre_shared_key = Some(key);
+        self
+    }
+
+    /// Keeps the `/webrtc-direct` listen addresses across restarts, see
+    /// [`crate::generate_webrtc_certificate`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn webrtc_certificate(mut self, certificate: libp2p_webrtc::tokio::Certificate) -> Self {
+        self.network.webrtc_certificate = Some(certificate);
+        self
+    }
+
+    /// Allows listening on secure WebSocket addresses ending with `/tls/ws`, see
+    /// [`crate::websocket_tls_config`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn websocket_tls(mut self, tls: libp2p::websocket::tls::Config) -> Self {
+        self.network.websocket_tls = Some(tls);
+        self
+    }
+
+    pub fn kademlia(mut self, enabled: bool) -> Self {
+        self.network.kademlia = enabled;
+        self
+    }
+
+    /// Connects through relays, along with hole punching to upgrade relayed connections.
+    pub fn relay(mut self, enabled: bool) -> Self {
+        self.network.relay = enabled;
+        self
+    }
+
+    pub fn rendezvous(mut self, enabled: bool) -> Self {
+        self.network.rendezvous = enabled;
+        self
+    }
+
+    /// Adds a rendezvous point to register at, the address has to end with its `/p2p` peer id.
+    pub fn rendezvous_point(mut self, addr: Multiaddr) -> Self {
+        self.network.rendezvous_points.push(addr);
+        self
+    }
+
+    /// Registers in the team namespace at every rendezvous point to discover the other members.
+    pub fn rendezvous_namespace(mut self, namespace: impl Into<String>) -> Self {
+        self.network.rendezvous_namespaces.push(namespace.into());
+        self
+    }
+
+    /// Time a connection without any open streams is kept alive.
+    pub fn idle_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.network.idle_connection_timeout = timeout;
+        self
+    }
+
+    /// Configures the mesh of the group chat rooms.
+    pub fn gossipsub_config(mut self, config: gossipsub::Config) -> Self {
+        self.network.gossipsub = config;
+        self
+    }
+
+    pub fn kademlia_config(mut self, config: kad::Config) -> Self {
+        self.network.kademlia_config = config;
+        self
+    }
+
+    /// Joins the DHT of streuen nodes rather than the public IPFS DHT, replacing the Kademlia
+    /// config with the default one for [`STREUEN_KAD_PROTOCOL`]. The bootstrap peers have to
+    /// take part in that DHT.
+    pub fn private_dht(mut self) -> Self {
+        self.network.kademlia_config = kad::Config::new(STREUEN_KAD_PROTOCOL);
+        self
+    }
+
+    /// Adds a bootstrap peer of the public IPFS DHT, which is then used to look up peers while
+    /// running a [private DHT](Self::private_dht). The address has to end with its `/p2p` peer id.
+    pub fn public_dht_peer(mut self, addr: Multiaddr) -> Self {
+        self.network.public_dht_peers.push(addr);
+        self
+    }
+
+    /// Configures the protocol carrying direct messages, its request timeout is the time after
+    /// which an unacknowledged message counts as failed.
+    pub fn request_response_config(mut self, config: request_response::Config) -> Self {
+        self.network.request_response = config;
+        self
+    }
+
+    /// Configures the pings to connected peers, a connection is closed once a few of them in a
+    /// row went unanswered.
+    pub fn ping_config(mut self, config: ping::Config) -> Self {
+        self.network.ping = config;
+        self
+    }
+
+    /// Starts the swarm and spawns the app loop onto the current thread.
+    ///
+    /// On native targets this has to be called from within a [`tokio::task::LocalSet`], which also
+    /// runs the swarm unless another [`SwarmExecutor`] was chosen. Hosts without one use
+    /// [`Self::build_with_loop`] instead.
+    pub fn build(self) -> Result<ChatApp, error::ChatAppError> {
+        let (app, app_loop) = self.build_with_loop()?;
+        super::spawn_app_loop(app_loop);
+        Ok(app)
+    }
+
+    /// Starts the swarm, returning the app along with its loop for the caller to drive.
+    ///
+    /// Events are only recorded and delivered while the loop is polled, which needs no runtime,
+    /// e.g. a [`futures::executor::LocalPool`] run from the host's own event loop. Along with
+    /// [`SwarmExecutor::Thread`] or [`SwarmExecutor::Spawner`] the app is built without any tokio
+    /// runtime around.
+    pub fn build_with_loop(self) -> Result<(ChatApp, super::AppLoop), error::ChatAppError> {
+        let keypair = self.keypair.unwrap_or_else(Keypair::generate_ed25519);
+        let local_peer_id = keypair.public().to_peer_id();
+        let current_user = users::User::new(self.name, local_peer_id);
+        let users = users::Users::new(current_user, keypair);
+        let messages = messages::Messages::new(local_peer_id, self.message_store);
+
+        let (app_sender, app_receiver) = mpsc::unbounded();
+        let stores = SwarmStores {
+            sessions: self.session_store,
+            peers: self.peer_store,
+        };
+        let (commands, swarm) = crate::libp2p::run_swarm(
+            users.keypair().clone(),
+            stores,
+            self.network.clone(),
+            app_sender.clone(),
+            &self.executor,
+        )?;
+
+        let app_callbacks = Rc::new(RefCell::new(Vec::new()));
+        let subscribers = Rc::new(RefCell::new(Vec::new()));
+        let network_status = Rc::new(RefCell::new(super::NetworkStatus::default()));
+        let latencies = Rc::new(RefCell::new(HashMap::new()));
+        let app_loop = super::AppLoop(Box::pin(super::run_app_loop(
+            app_receiver,
+            messages.clone(),
+            app_callbacks.clone(),
+            subscribers.clone(),
+            network_status.clone(),
+            latencies.clone(),
+        )));
+
+        let app = ChatApp {
+            users,
+            messages,
+
+            app_callbacks,
+            subscribers,
+            network_status,
+            latencies,
+
+            app_sender,
+            commands,
+            network: self.network,
+            executor: self.executor,
+            swarm: Some(swarm),
+            stores: None,
+        };
+        Ok((app, app_loop))
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use futures::{StreamExt, executor::LocalPool, future, task::LocalSpawnExt};
+
+    use super::*;
+    use crate::app::ToApp;
+
+    /// Builds an app outside of any runtime, drives its loop with a plain executor and waits for
+    /// the swarm to listen.
+    fn listens_without_runtime(executor: SwarmExecutor) {
+        assert!(tokio::runtime::Handle::try_current().is_err());
+        let mut pool = LocalPool::new();
+        let (mut app, app_loop) = ChatApp::builder()
+            .mdns(false)
+            .upnp(false)
+            .swarm_executor(executor)
+            .listen_on("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .build_with_loop()
+            .unwrap();
+        pool.spawner().spawn_local(app_loop).unwrap();
+        let mut events = app.events();
+
+        pool.run_until(async {
+            let listening = async {
+                while let Some(event) = events.next().await {
+                    if let Ok(ToApp::ListenAddressAdded(_)) = event {
+                        return;
+                    }
+                }
+                panic!("the app stopped before listening");
+            };
+            let timeout = futures_timer::Delay::new(Duration::from_secs(10));
+            if let future::Either::Right(_) = future::select(Box::pin(listening), timeout).await {
+                panic!("the swarm did not start listening");
+            }
+            app.shutdown().await.unwrap();
+        });
+    }
+
+    #[test]
+    fn thread_needs_no_runtime() {
+        listens_without_runtime(SwarmExecutor::Thread);
+    }
+
+    #[test]
+    fn spawner_needs_no_runtime() {
+        listens_without_runtime(SwarmExecutor::spawner(|task| {
+            std::thread::spawn(|| futures::executor::block_on(task));
+        }));
+    }
+}
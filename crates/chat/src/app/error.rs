@@ -8,6 +8,114 @@ pub enum ChatAppError {
     BehaviorError(#[from] libp2p::BehaviourBuilderError),
     #[error(transparent)]
     SenderError(#[from] futures_channel::mpsc::SendError),
+    #[error(transparent)]
+    StorageError(#[from] StorageError),
+    #[error(transparent)]
+    EncryptionError(#[from] EncryptionError),
+    #[error(transparent)]
+    CommandError(#[from] CommandError),
     #[error("Missing swarm sender.")]
     MissingSender,
+    #[error("The swarm stopped before the command completed.")]
+    SwarmStopped,
+    #[error("No tokio runtime to spawn the swarm onto, run it on its own thread instead.")]
+    NoRuntime,
+}
+
+/// Why a command dispatched to the swarm failed.
+#[derive(Debug, thiserror::Error)]
+pub enum CommandError {
+    #[error("Failed to listen on address [{0}]: {1}")]
+    Listen(
+        libp2p::Multiaddr,
+        #[source] libp2p::TransportError<std::io::Error>,
+    ),
+    #[error("Invalid bootstrap address [{0}], it has to end with the peer id.")]
+    InvalidBootstrapAddress(libp2p::Multiaddr),
+    #[error("Invalid peer address [{0}], it has to end with the peer id.")]
+    InvalidPeerAddress(libp2p::Multiaddr),
+    #[error("Kademlia is disabled.")]
+    KademliaDisabled,
+    #[error("Invalid rendezvous point [{0}], it has to end with the peer id.")]
+    InvalidRendezvousPoint(libp2p::Multiaddr),
+    #[error("Rendezvous is disabled.")]
+    RendezvousDisabled,
+    #[error("Invalid namespace [{0}], it is too long.")]
+    InvalidNamespace(String),
+    #[error("Not registered in namespace [{0}].")]
+    NotRegistered(String),
+    #[error("Failed to reach peer [{0}] at its known addresses, through the DHT or a relay.")]
+    Unreachable(libp2p::PeerId),
+    #[error("Failed to generate a message id: {0}")]
+    MessageId(#[source] getrandom::Error),
+    #[error("Failed to encrypt message for peer [{0}]: {1}")]
+    Encryption(libp2p::PeerId, #[source] Box<EncryptionError>),
+    #[error("Failed to deliver message to peer [{0}]: {1}")]
+    Delivery(
+        libp2p::PeerId,
+        #[source] libp2p::request_response::OutboundFailure,
+    ),
+    #[error("Peer [{0}] acknowledged message [{2}] instead of message [{1}].")]
+    MismatchedAcknowledgement(
+        libp2p::PeerId,
+        crate::app::messages::MessageId,
+        crate::app::messages::MessageId,
+    ),
+    #[error("Failed to join room [{0}]: {1}")]
+    JoinRoom(String, #[source] libp2p::gossipsub::SubscriptionError),
+    #[error("Not a member of room [{0}].")]
+    NotInRoom(String),
+    #[error("Failed to send message to room [{0}]: {1}")]
+    Publish(String, #[source] libp2p::gossipsub::PublishError),
+    #[error("Failed to encode message for room [{0}]: {1}")]
+    Encoding(String, String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[cfg(target_arch = "wasm32")]
+    #[error("IndexedDB error: {0}")]
+    IndexedDb(String),
+    #[cfg(target_arch = "wasm32")]
+    #[error("Web storage error: {0}")]
+    WebStorage(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("Invalid stored value: {0}")]
+    Serialization(String),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionError {
+    #[error("Peer [{0}] has no ed25519 identity key")]
+    UnsupportedIdentity(libp2p::PeerId),
+    #[error("Invalid public key.")]
+    InvalidKey,
+    #[error("No session with peer [{0}].")]
+    MissingSession(libp2p::PeerId),
+    #[error("Too many skipped messages.")]
+    TooManySkipped,
+    #[error("Message authentication failed.")]
+    Decryption,
+    #[error("Failed to gather randomness: {0}")]
+    Random(#[from] getrandom::Error),
+    #[error(transparent)]
+    StorageError(#[from] StorageError),
+}
+
+#[cfg(target_arch = "wasm32")]
+impl From<rexie::Error> for StorageError {
+    fn from(err: rexie::Error) -> Self {
+        Self::IndexedDb(err.to_string())
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl From<serde_wasm_bindgen::Error> for StorageError {
+    fn from(err: serde_wasm_bindgen::Error) -> Self {
+        Self::Serialization(err.to_string())
+    }
 }
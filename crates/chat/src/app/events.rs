@@ -0,0 +1,69 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::Stream;
+use futures_channel::mpsc;
+
+use super::ToApp;
+
+/// Number of events buffered for a subscriber before it starts to lag behind.
+const EVENT_BUFFER: usize = 256;
+
+/// Some events were dropped because the subscriber did not keep up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("Lagged behind by {0} events.")]
+pub struct Lagged(pub u64);
+
+/// Stream of the events of a [`super::ChatApp`], created by [`super::ChatApp::events`].
+///
+/// A subscriber that does not keep up misses events, which it is told about by a [`Lagged`] item
+/// in place of the missed events. The stream ends once the app stopped.
+pub struct EventStream {
+    receiver: mpsc::Receiver<Result<ToApp, Lagged>>,
+}
+
+impl Stream for EventStream {
+    type Item = Result<ToApp, Lagged>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+/// The sending half of an [`EventStream`], owned by the app loop.
+pub(crate) struct Subscriber {
+    sender: mpsc::Sender<Result<ToApp, Lagged>>,
+    /// Events dropped since the last one delivered.
+    missed: u64,
+}
+
+impl Subscriber {
+    pub fn new() -> (Self, EventStream) {
+        let (sender, receiver) = mpsc::channel(EVENT_BUFFER);
+        (Self { sender, missed: 0 }, EventStream { receiver })
+    }
+
+    /// Delivers `event` without waiting, returning false once the stream was dropped.
+    pub fn send(&mut self, event: &ToApp) -> bool {
+        if self.missed > 0 {
+            match self.sender.try_send(Err(Lagged(self.missed))) {
+                Ok(()) => self.missed = 0,
+                Err(err) if err.is_disconnected() => return false,
+                Err(_) => {
+                    self.missed += 1;
+                    return true;
+                }
+            }
+        }
+        match self.sender.try_send(Ok(event.clone())) {
+            Ok(()) => true,
+            Err(err) if err.is_disconnected() => false,
+            Err(_) => {
+                self.missed += 1;
+                true
+            }
+        }
+    }
+}
@@ -1,18 +1,326 @@
+mod id;
+#[cfg(target_arch = "wasm32")]
+mod indexed_db;
+#[cfg(not(target_arch = "wasm32"))]
+mod sqlite;
+
+use std::{cell::RefCell, rc::Rc};
+
+use libp2p::PeerId;
+
+use super::error::StorageError;
+
+pub use id::{InvalidMessageId, MessageId};
+#[cfg(target_arch = "wasm32")]
+pub use indexed_db::IndexedDbMessageStore;
+#[cfg(not(target_arch = "wasm32"))]
+pub use sqlite::SqliteMessageStore;
+
+/// Delivery state of a direct message. Received messages are always delivered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MessageStatus {
+    /// Waiting for a connection to the recipient.
+    Queued,
+    /// Written to a connection to the recipient.
+    Sent,
+    /// Acknowledged by the recipient.
+    Delivered,
+    /// The recipient could not be reached or did not acknowledge the message in time.
+    Failed,
+}
+
+impl MessageStatus {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            MessageStatus::Queued => "queued",
+            MessageStatus::Sent => "sent",
+            MessageStatus::Delivered => "delivered",
+            MessageStatus::Failed => "failed",
+        }
+    }
+
+    pub(crate) fn parse(value: &str) -> Option<Self> {
+        match value {
+            "queued" => Some(MessageStatus::Queued),
+            "sent" => Some(MessageStatus::Sent),
+            "delivered" => Some(MessageStatus::Delivered),
+            "failed" => Some(MessageStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// A direct message sent or received by this node.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Message {
-    message_id: u32,
-    to: super::users::User,
-    from: super::users::User,
+    message_id: MessageId,
+    to: PeerId,
+    from: PeerId,
     text: String,
+    status: MessageStatus,
+}
+
+impl Message {
+    pub fn new(
+        message_id: MessageId,
+        to: PeerId,
+        from: PeerId,
+        text: String,
+        status: MessageStatus,
+    ) -> Self {
+        Self {
+            message_id,
+            to,
+            from,
+            text,
+            status,
+        }
+    }
+
+    pub fn message_id(&self) -> MessageId {
+        self.message_id
+    }
+
+    pub fn to(&self) -> PeerId {
+        self.to
+    }
+
+    pub fn from(&self) -> PeerId {
+        self.from
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Milliseconds since the unix epoch at which the sender created the message, the same on
+    /// every peer.
+    pub fn timestamp(&self) -> u64 {
+        self.message_id.timestamp()
+    }
+
+    pub fn status(&self) -> MessageStatus {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: MessageStatus) {
+        self.status = status;
+    }
+
+    /// The remote peer this message was exchanged with.
+    pub fn conversation(&self, local_peer_id: PeerId) -> PeerId {
+        if self.from == local_peer_id {
+            self.to
+        } else {
+            self.from
+        }
+    }
+}
+
+/// A message published to a room. Room messages are not kept in the message history, so they are
+/// gone once the app restarts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoomMessage {
+    room_id: String,
+    message_id: MessageId,
+    from: PeerId,
+    text: String,
+}
+
+impl RoomMessage {
+    pub fn new(room_id: String, message_id: MessageId, from: PeerId, text: String) -> Self {
+        Self {
+            room_id,
+            message_id,
+            from,
+            text,
+        }
+    }
+
+    pub fn room_id(&self) -> &str {
+        &self.room_id
+    }
+
+    pub fn message_id(&self) -> MessageId {
+        self.message_id
+    }
+
+    pub fn from(&self) -> PeerId {
+        self.from
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Milliseconds since the unix epoch at which the sender created the message, the same on
+    /// every peer.
+    pub fn timestamp(&self) -> u64 {
+        self.message_id.timestamp()
+    }
 }
 
+/// Milliseconds since the unix epoch, usable on both native and wasm.
+pub(crate) fn now_millis() -> u64 {
+    web_time::SystemTime::now()
+        .duration_since(web_time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Backend persisting the message history, keyed by message id.
+#[async_trait::async_trait(?Send)]
+pub trait MessageStore {
+    /// Stores the message, replacing a stored message with the same id.
+    async fn insert(&self, message: &Message) -> Result<(), StorageError>;
+
+    async fn get(&self, message_id: MessageId) -> Result<Option<Message>, StorageError>;
+
+    /// Updates the status of a stored message, ignoring unknown messages.
+    async fn set_status(
+        &self,
+        message_id: MessageId,
+        status: MessageStatus,
+    ) -> Result<(), StorageError>;
+
+    /// Returns up to `limit` messages exchanged with `peer_id` whose id sorts before `before`
+    /// (or the newest messages if `None`), ordered oldest first. Passing the id of the oldest
+    /// message of a page yields the page before it.
+    async fn page(
+        &self,
+        peer_id: PeerId,
+        before: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Vec<Message>, StorageError>;
+
+    /// Every peer a message was exchanged with, most recently active first.
+    async fn conversations(&self) -> Result<Vec<PeerId>, StorageError>;
+}
+
+/// Message store which is lost once the app exits.
+#[derive(Default)]
+pub struct MemoryMessageStore {
+    messages: RefCell<Vec<Message>>,
+}
+
+#[async_trait::async_trait(?Send)]
+impl MessageStore for MemoryMessageStore {
+    async fn insert(&self, message: &Message) -> Result<(), StorageError> {
+        let mut messages = self.messages.borrow_mut();
+        messages.retain(|m| m.message_id != message.message_id);
+        let index = messages.partition_point(|m| m.message_id < message.message_id);
+        messages.insert(index, message.clone());
+        Ok(())
+    }
+
+    async fn get(&self, message_id: MessageId) -> Result<Option<Message>, StorageError> {
+        Ok(self
+            .messages
+            .borrow()
+            .iter()
+            .find(|m| m.message_id == message_id)
+            .cloned())
+    }
+
+    async fn set_status(
+        &self,
+        message_id: MessageId,
+        status: MessageStatus,
+    ) -> Result<(), StorageError> {
+        if let Some(message) = self
+            .messages
+            .borrow_mut()
+            .iter_mut()
+            .find(|m| m.message_id == message_id)
+        {
+            message.status = status;
+        }
+        Ok(())
+    }
+
+    async fn page(
+        &self,
+        peer_id: PeerId,
+        before: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Vec<Message>, StorageError> {
+        let messages = self.messages.borrow();
+        let mut page: Vec<Message> = messages
+            .iter()
+            .rev()
+            .filter(|m| m.to == peer_id || m.from == peer_id)
+            .filter(|m| before.is_none_or(|before| m.message_id < before))
+            .take(limit)
+            .cloned()
+            .collect();
+        page.reverse();
+        Ok(page)
+    }
+
+    async fn conversations(&self) -> Result<Vec<PeerId>, StorageError> {
+        let mut conversations = Vec::new();
+        for message in self.messages.borrow().iter().rev() {
+            for peer_id in [message.from, message.to] {
+                if !conversations.contains(&peer_id) {
+                    conversations.push(peer_id);
+                }
+            }
+        }
+        Ok(conversations)
+    }
+}
+
+/// Shared handle to the message history of the chat app.
+#[derive(Clone)]
 pub struct Messages {
-    messages: Vec<Message>,
+    local_peer_id: PeerId,
+    store: Rc<dyn MessageStore>,
 }
 
 impl Messages {
-    pub fn new() -> Self {
+    pub(crate) fn new(local_peer_id: PeerId, store: Rc<dyn MessageStore>) -> Self {
         Self {
-            messages: Vec::new(),
+            local_peer_id,
+            store,
         }
     }
+
+    pub(crate) async fn record(&self, message: &Message) -> Result<(), StorageError> {
+        self.store.insert(message).await
+    }
+
+    pub(crate) async fn update_status(
+        &self,
+        message_id: MessageId,
+        status: MessageStatus,
+    ) -> Result<(), StorageError> {
+        self.store.set_status(message_id, status).await
+    }
+
+    pub async fn get(&self, message_id: MessageId) -> Result<Option<Message>, StorageError> {
+        self.store.get(message_id).await
+    }
+
+    /// Pages backwards through the history with `peer_id`, see [`MessageStore::page`].
+    pub async fn history(
+        &self,
+        peer_id: PeerId,
+        before: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Vec<Message>, StorageError> {
+        self.store.page(peer_id, before, limit).await
+    }
+
+    /// Every remote peer a message was exchanged with, most recently active first.
+    pub async fn conversations(&self) -> Result<Vec<PeerId>, StorageError> {
+        let mut conversations = self.store.conversations().await?;
+        conversations.retain(|peer_id| *peer_id != self.local_peer_id);
+        Ok(conversations)
+    }
+}
+
+impl PartialEq for Messages {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.store, &other.store)
+    }
 }
@@ -0,0 +1,122 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Globally unique id of a direct or room message.
+///
+/// Like a ULID it consists of the creation time in milliseconds since the unix epoch followed by
+/// 80 random bits, so ids of different senders do not collide and sort by creation time. The text
+/// form is 32 lowercase hex digits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+pub struct MessageId([u8; 16]);
+
+impl MessageId {
+    /// Generates the id of a message created now, failing only without system randomness.
+    pub fn generate() -> Result<Self, getrandom::Error> {
+        Self::with_timestamp(super::now_millis())
+    }
+
+    pub(crate) fn with_timestamp(timestamp: u64) -> Result<Self, getrandom::Error> {
+        let mut bytes = [0u8; 16];
+        bytes[..6].copy_from_slice(&timestamp.to_be_bytes()[2..]);
+        getrandom::fill(&mut bytes[6..])?;
+        Ok(Self(bytes))
+    }
+
+    /// Milliseconds since the unix epoch at which the message was created.
+    pub fn timestamp(&self) -> u64 {
+        let mut timestamp = [0u8; 8];
+        timestamp[2..].copy_from_slice(&self.0[..6]);
+        u64::from_be_bytes(timestamp)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
+impl fmt::Display for MessageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{byte:02x}"))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Invalid message id: {0}")]
+pub struct InvalidMessageId(String);
+
+impl FromStr for MessageId {
+    type Err = InvalidMessageId;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidMessageId(value.to_string());
+        // `from_str_radix` alone would accept a sign
+        if value.len() != 32 || !value.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let mut bytes = [0u8; 16];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            let digits = &value[i * 2..i * 2 + 2];
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| invalid())?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_form_round_trips() {
+        let message_id = MessageId::with_timestamp(1_700_000_000_000).unwrap();
+        let text = message_id.to_string();
+
+        assert_eq!(text.len(), 32);
+        assert!(text.starts_with("018bcfe56800"));
+        assert_eq!(text.parse::<MessageId>().unwrap(), message_id);
+        assert_eq!(
+            "018BCFE56800ABCDEF0123456789ABCD"
+                .parse::<MessageId>()
+                .unwrap()
+                .to_string(),
+            "018bcfe56800abcdef0123456789abcd"
+        );
+    }
+
+    #[test]
+    fn malformed_text_is_rejected() {
+        for text in [
+            "",
+            "018bcfe56800abcdef0123456789abc",
+            "018bcfe56800abcdef0123456789abcde",
+            "018bcfe56800abcdef0123456789abcg",
+            "+18bcfe56800abcdef0123456789abcd",
+            " 018bcfe56800abcdef0123456789abc",
+            // 32 bytes, but not 32 characters
+            "018bcfe56800abcdef0123456789abé",
+        ] {
+            assert!(text.parse::<MessageId>().is_err(), "{text:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn ids_sort_by_timestamp() {
+        let earlier = MessageId::with_timestamp(1_700_000_000_000).unwrap();
+        let later = MessageId::with_timestamp(1_700_000_000_001).unwrap();
+
+        assert_eq!(earlier.timestamp(), 1_700_000_000_000);
+        assert_eq!(later.timestamp(), 1_700_000_000_001);
+        assert!(earlier < later);
+        assert!(earlier.to_string() < later.to_string());
+    }
+
+    #[test]
+    fn ids_of_the_same_millisecond_differ() {
+        let first = MessageId::with_timestamp(1_700_000_000_000).unwrap();
+        let second = MessageId::with_timestamp(1_700_000_000_000).unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(first.timestamp(), second.timestamp());
+    }
+}
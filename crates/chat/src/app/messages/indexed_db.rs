@@ -0,0 +1,267 @@
+use std::str::FromStr;
+
+use libp2p::PeerId;
+use rexie::{Direction, Index, KeyRange, ObjectStore, Rexie, TransactionMode};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::JsValue;
+
+use super::{Message, MessageId, MessageStatus, MessageStore};
+use crate::app::error::StorageError;
+
+const DATABASE: &str = "streuen-chat";
+const MESSAGES: &str = "messages";
+/// Message ids sort by creation time, so the indexes on them serve paging through a
+/// conversation.
+const SENDER_INDEX: &str = "sender_id";
+const RECIPIENT_INDEX: &str = "recipient_id";
+
+/// Shape of a message inside the object store, its timestamp is part of the message id.
+#[derive(Serialize, Deserialize)]
+struct StoredMessage {
+    sender: String,
+    message_id: String,
+    recipient: String,
+    text: String,
+    status: String,
+}
+
+impl From<&Message> for StoredMessage {
+    fn from(message: &Message) -> Self {
+        Self {
+            sender: message.from.to_base58(),
+            message_id: message.message_id.to_string(),
+            recipient: message.to.to_base58(),
+            text: message.text.clone(),
+            status: message.status.as_str().to_string(),
+        }
+    }
+}
+
+impl TryFrom<StoredMessage> for Message {
+    type Error = StorageError;
+
+    fn try_from(stored: StoredMessage) -> Result<Self, Self::Error> {
+        Ok(Self {
+            message_id: MessageId::from_str(&stored.message_id)
+                .map_err(|err| StorageError::Serialization(err.to_string()))?,
+            to: parse_peer_id(&stored.recipient)?,
+            from: parse_peer_id(&stored.sender)?,
+            text: stored.text,
+            status: MessageStatus::parse(&stored.status).ok_or_else(|| {
+                StorageError::Serialization(format!("unknown message status: {}", stored.status))
+            })?,
+        })
+    }
+}
+
+fn parse_peer_id(value: &str) -> Result<PeerId, StorageError> {
+    PeerId::from_str(value).map_err(|err| StorageError::Serialization(err.to_string()))
+}
+
+fn to_js<T: Serialize>(value: &T) -> Result<JsValue, StorageError> {
+    Ok(serde_wasm_bindgen::to_value(value)?)
+}
+
+fn from_js<T: for<'de> Deserialize<'de>>(value: JsValue) -> Result<T, StorageError> {
+    Ok(serde_wasm_bindgen::from_value(value)?)
+}
+
+/// Message store backed by the browser's IndexedDB.
+pub struct IndexedDbMessageStore {
+    database: Rexie,
+}
+
+impl IndexedDbMessageStore {
+    pub async fn open() -> Result<Self, StorageError> {
+        Self::open_database(DATABASE).await
+    }
+
+    async fn open_database(name: &str) -> Result<Self, StorageError> {
+        let database = Rexie::builder(name)
+            .version(1)
+            .add_object_store(
+                ObjectStore::new(MESSAGES)
+                    .key_path("message_id")
+                    .add_index(Index::new_array(SENDER_INDEX, ["sender", "message_id"]))
+                    .add_index(Index::new_array(
+                        RECIPIENT_INDEX,
+                        ["recipient", "message_id"],
+                    )),
+            )
+            .build()
+            .await?;
+        Ok(Self { database })
+    }
+
+    /// The newest `limit` messages of one index for `peer_id` whose id sorts before `before`.
+    async fn scan_index(
+        &self,
+        index: &str,
+        peer_id: &str,
+        before: Option<&str>,
+        limit: usize,
+    ) -> Result<Vec<StoredMessage>, StorageError> {
+        let transaction = self
+            .database
+            .transaction(&[MESSAGES], TransactionMode::ReadOnly)?;
+        let store = transaction.store(MESSAGES)?;
+        // arrays sort after every string in IndexedDB, so an empty one bounds no message id
+        let upper = match before {
+            Some(before) => to_js(&(peer_id, before))?,
+            None => to_js(&(peer_id, Vec::<String>::new()))?,
+        };
+        let range = KeyRange::bound(&to_js(&(peer_id, ""))?, &upper, Some(false), Some(true))
+            .map_err(rexie::Error::from)?;
+        store
+            .index(index)?
+            .scan(Some(range), Some(limit as u32), None, Some(Direction::Prev))
+            .await?
+            .into_iter()
+            .map(|(_, value)| from_js(value))
+            .collect()
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl MessageStore for IndexedDbMessageStore {
+    async fn insert(&self, message: &Message) -> Result<(), StorageError> {
+        let transaction = self
+            .database
+            .transaction(&[MESSAGES], TransactionMode::ReadWrite)?;
+        let store = transaction.store(MESSAGES)?;
+        store
+            .put(&to_js(&StoredMessage::from(message))?, None)
+            .await?;
+        transaction.done().await?;
+        Ok(())
+    }
+
+    async fn get(&self, message_id: MessageId) -> Result<Option<Message>, StorageError> {
+        let transaction = self
+            .database
+            .transaction(&[MESSAGES], TransactionMode::ReadOnly)?;
+        let store = transaction.store(MESSAGES)?;
+        match store.get(to_js(&message_id.to_string())?).await? {
+            Some(value) => Ok(Some(from_js::<StoredMessage>(value)?.try_into()?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set_status(
+        &self,
+        message_id: MessageId,
+        status: MessageStatus,
+    ) -> Result<(), StorageError> {
+        let transaction = self
+            .database
+            .transaction(&[MESSAGES], TransactionMode::ReadWrite)?;
+        let store = transaction.store(MESSAGES)?;
+        if let Some(value) = store.get(to_js(&message_id.to_string())?).await? {
+            let mut stored: StoredMessage = from_js(value)?;
+            stored.status = status.as_str().to_string();
+            store.put(&to_js(&stored)?, None).await?;
+        }
+        transaction.done().await?;
+        Ok(())
+    }
+
+    async fn page(
+        &self,
+        peer_id: PeerId,
+        before: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Vec<Message>, StorageError> {
+        let peer_id = peer_id.to_base58();
+        let before = before.map(|before| before.to_string());
+
+        // IndexedDB has no OR queries, so the newest of both directions are merged here
+        let mut page = self
+            .scan_index(SENDER_INDEX, &peer_id, before.as_deref(), limit)
+            .await?;
+        page.extend(
+            self.scan_index(RECIPIENT_INDEX, &peer_id, before.as_deref(), limit)
+                .await?,
+        );
+        page.sort_by(|a, b| b.message_id.cmp(&a.message_id));
+        page.truncate(limit);
+        page.into_iter().rev().map(Message::try_from).collect()
+    }
+
+    async fn conversations(&self) -> Result<Vec<PeerId>, StorageError> {
+        let transaction = self
+            .database
+            .transaction(&[MESSAGES], TransactionMode::ReadOnly)?;
+        let store = transaction.store(MESSAGES)?;
+
+        // the latest message id of each peer, read from the messages as the index keys are not
+        // handed out alongside the primary keys
+        let mut latest: Vec<(String, String)> = Vec::new();
+        for value in store.get_all(None, None).await? {
+            let stored: StoredMessage = from_js(value)?;
+            for peer_id in [stored.sender, stored.recipient] {
+                match latest.iter_mut().find(|(p, _)| *p == peer_id) {
+                    Some((_, latest_id)) if *latest_id < stored.message_id => {
+                        *latest_id = stored.message_id.clone()
+                    }
+                    Some(_) => {}
+                    None => latest.push((peer_id, stored.message_id.clone())),
+                }
+            }
+        }
+        latest.sort_by(|a, b| b.1.cmp(&a.1));
+        latest
+            .into_iter()
+            .map(|(peer_id, _)| parse_peer_id(&peer_id))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wasm_bindgen_test::{wasm_bindgen_test, wasm_bindgen_test_configure};
+
+    use super::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    /// A message sent at `timestamp`, so messages sort the way they were inserted.
+    fn message(timestamp: u64, from: PeerId, to: PeerId, text: &str) -> Message {
+        Message::new(
+            MessageId::with_timestamp(timestamp).unwrap(),
+            to,
+            from,
+            text.to_string(),
+            MessageStatus::Sent,
+        )
+    }
+
+    #[wasm_bindgen_test]
+    async fn conversations_cover_both_directions() {
+        let _ = Rexie::delete("streuen-chat-test-conversations").await;
+        let store = IndexedDbMessageStore::open_database("streuen-chat-test-conversations")
+            .await
+            .unwrap();
+        let local = PeerId::random();
+        let (alice, bob) = (PeerId::random(), PeerId::random());
+
+        store
+            .insert(&message(1, local, alice, "to alice"))
+            .await
+            .unwrap();
+        store
+            .insert(&message(2, bob, local, "from bob"))
+            .await
+            .unwrap();
+        let mut conversations = store.conversations().await.unwrap();
+        conversations.retain(|peer_id| *peer_id != local);
+        assert_eq!(conversations, [bob, alice]);
+
+        store
+            .insert(&message(3, alice, local, "from alice"))
+            .await
+            .unwrap();
+        let mut conversations = store.conversations().await.unwrap();
+        conversations.retain(|peer_id| *peer_id != local);
+        assert_eq!(conversations, [alice, bob]);
+    }
+}
@@ -0,0 +1,264 @@
+use std::{path::Path, str::FromStr};
+
+use libp2p::PeerId;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use super::{Message, MessageId, MessageStatus, MessageStore};
+use crate::app::error::StorageError;
+
+/// Message ids sort by creation time, so the indexes on them serve paging through a
+/// conversation and there is no separate timestamp column.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS messages (
+        message_id TEXT NOT NULL PRIMARY KEY,
+        sender TEXT NOT NULL,
+        recipient TEXT NOT NULL,
+        text TEXT NOT NULL,
+        status TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS messages_sender_id ON messages (sender, message_id);
+    CREATE INDEX IF NOT EXISTS messages_recipient_id ON messages (recipient, message_id);
+    PRAGMA user_version = 1;
+";
+
+/// Message store backed by a SQLite database file.
+pub struct SqliteMessageStore {
+    connection: Connection,
+}
+
+impl SqliteMessageStore {
+    /// Opens the database at `path`, creating it if it does not exist yet.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::from_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(connection: Connection) -> Result<Self, StorageError> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Self { connection })
+    }
+}
+
+fn parse_peer_id(value: String) -> rusqlite::Result<PeerId> {
+    PeerId::from_str(&value).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(0, rusqlite::types::Type::Text, Box::new(err))
+    })
+}
+
+fn parse_message_id(value: String) -> rusqlite::Result<MessageId> {
+    MessageId::from_str(&value).map_err(|err| {
+        rusqlite::Error::FromSqlConversionFailure(1, rusqlite::types::Type::Text, Box::new(err))
+    })
+}
+
+fn message_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Message> {
+    Ok(Message {
+        from: parse_peer_id(row.get(0)?)?,
+        message_id: parse_message_id(row.get(1)?)?,
+        to: parse_peer_id(row.get(2)?)?,
+        text: row.get(3)?,
+        status: parse_status(row.get(4)?)?,
+    })
+}
+
+fn parse_status(value: String) -> rusqlite::Result<MessageStatus> {
+    MessageStatus::parse(&value).ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(
+            4,
+            rusqlite::types::Type::Text,
+            format!("unknown message status: {value}").into(),
+        )
+    })
+}
+
+#[async_trait::async_trait(?Send)]
+impl MessageStore for SqliteMessageStore {
+    async fn insert(&self, message: &Message) -> Result<(), StorageError> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO messages
+                 (sender, message_id, recipient, text, status)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                message.from.to_base58(),
+                message.message_id.to_string(),
+                message.to.to_base58(),
+                message.text,
+                message.status.as_str(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    async fn get(&self, message_id: MessageId) -> Result<Option<Message>, StorageError> {
+        let message = self
+            .connection
+            .query_row(
+                "SELECT sender, message_id, recipient, text, status FROM messages
+                 WHERE message_id = ?1",
+                params![message_id.to_string()],
+                message_from_row,
+            )
+            .optional()?;
+        Ok(message)
+    }
+
+    async fn set_status(
+        &self,
+        message_id: MessageId,
+        status: MessageStatus,
+    ) -> Result<(), StorageError> {
+        self.connection.execute(
+            "UPDATE messages SET status = ?2 WHERE message_id = ?1",
+            params![message_id.to_string(), status.as_str()],
+        )?;
+        Ok(())
+    }
+
+    async fn page(
+        &self,
+        peer_id: PeerId,
+        before: Option<MessageId>,
+        limit: usize,
+    ) -> Result<Vec<Message>, StorageError> {
+        // the text form of message ids sorts like the ids themselves, NULL compares as no bound
+        let mut statement = self.connection.prepare(
+            "SELECT sender, message_id, recipient, text, status FROM messages
+             WHERE (sender = ?1 OR recipient = ?1) AND (?2 IS NULL OR message_id < ?2)
+             ORDER BY message_id DESC LIMIT ?3",
+        )?;
+        let mut page = statement
+            .query_map(
+                params![
+                    peer_id.to_base58(),
+                    before.map(|before| before.to_string()),
+                    limit as i64
+                ],
+                message_from_row,
+            )?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        page.reverse();
+        Ok(page)
+    }
+
+    async fn conversations(&self) -> Result<Vec<PeerId>, StorageError> {
+        let mut statement = self.connection.prepare(
+            "SELECT peer FROM (
+                 SELECT sender AS peer, message_id FROM messages
+                 UNION ALL
+                 SELECT recipient AS peer, message_id FROM messages
+             ) GROUP BY peer ORDER BY MAX(message_id) DESC",
+        )?;
+        let conversations = statement
+            .query_map([], |row| parse_peer_id(row.get(0)?))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(conversations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use libp2p::identity::Keypair;
+
+    use super::*;
+
+    const TIMESTAMP: u64 = 1_700_000_000_000;
+
+    fn message(from: PeerId, to: PeerId, text: &str, timestamp: u64) -> Message {
+        Message::new(
+            MessageId::with_timestamp(timestamp).unwrap(),
+            to,
+            from,
+            text.to_string(),
+            MessageStatus::Sent,
+        )
+    }
+
+    fn peer_id() -> PeerId {
+        Keypair::generate_ed25519().public().to_peer_id()
+    }
+
+    #[test]
+    fn inserted_message_is_found_by_id() {
+        let store = SqliteMessageStore::open_in_memory().unwrap();
+        let message = message(peer_id(), peer_id(), "hello", TIMESTAMP);
+        block_on(store.insert(&message)).unwrap();
+
+        assert_eq!(
+            block_on(store.get(message.message_id())).unwrap(),
+            Some(message)
+        );
+        assert_eq!(
+            block_on(store.get(MessageId::with_timestamp(TIMESTAMP).unwrap())).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn pages_do_not_skip_messages_sharing_a_timestamp() {
+        let store = SqliteMessageStore::open_in_memory().unwrap();
+        let (local, remote) = (peer_id(), peer_id());
+        let mut messages: Vec<Message> = (0..5)
+            .map(|i| {
+                let (from, to) = if i % 2 == 0 {
+                    (local, remote)
+                } else {
+                    (remote, local)
+                };
+                message(from, to, &i.to_string(), TIMESTAMP)
+            })
+            .collect();
+        messages.push(message(local, remote, "later", TIMESTAMP + 1));
+        // another conversation stays out of the pages
+        block_on(store.insert(&message(local, peer_id(), "other", TIMESTAMP))).unwrap();
+        for message in &messages {
+            block_on(store.insert(message)).unwrap();
+        }
+        messages.sort_by_key(Message::message_id);
+
+        let mut paged = Vec::new();
+        let mut before = None;
+        loop {
+            let page = block_on(store.page(remote, before, 2)).unwrap();
+            let Some(oldest) = page.first() else {
+                break;
+            };
+            assert!(page.len() <= 2);
+            before = Some(oldest.message_id());
+            paged.splice(0..0, page);
+        }
+        assert_eq!(paged, messages);
+    }
+
+    #[test]
+    fn status_is_updated() {
+        let store = SqliteMessageStore::open_in_memory().unwrap();
+        let message = message(peer_id(), peer_id(), "hello", TIMESTAMP);
+        block_on(store.insert(&message)).unwrap();
+        block_on(store.set_status(message.message_id(), MessageStatus::Delivered)).unwrap();
+        // unknown messages are ignored
+        block_on(store.set_status(
+            MessageId::with_timestamp(TIMESTAMP).unwrap(),
+            MessageStatus::Failed,
+        ))
+        .unwrap();
+
+        let stored = block_on(store.get(message.message_id())).unwrap().unwrap();
+        assert_eq!(stored.status(), MessageStatus::Delivered);
+    }
+
+    #[test]
+    fn duplicate_insert_replaces_the_message() {
+        let store = SqliteMessageStore::open_in_memory().unwrap();
+        let remote = peer_id();
+        let mut message = message(peer_id(), remote, "hello", TIMESTAMP);
+        block_on(store.insert(&message)).unwrap();
+        message.set_status(MessageStatus::Delivered);
+        block_on(store.insert(&message)).unwrap();
+
+        assert_eq!(block_on(store.page(remote, None, 10)).unwrap(), [message]);
+    }
+}
@@ -1,56 +1,240 @@
+mod builder;
 pub(crate) mod error;
-mod messages;
-mod users;
+mod events;
+pub mod messages;
+pub mod network;
+pub mod peers;
+pub mod sessions;
+pub mod users;
 
-use std::rc::Rc;
+pub use builder::ChatAppBuilder;
+pub use error::{ChatAppError, CommandError, EncryptionError, StorageError};
+pub use events::{EventStream, Lagged};
+pub use network::{NatStatus, NetworkStatus};
 
-use libp2p::identity::Keypair;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
 
-use crate::libp2p::behaviour::InnerChatBehavior;
-pub use crate::libp2p::behaviour::ToChat;
+use futures::future::LocalBoxFuture;
+use futures_channel::mpsc;
+use libp2p::{Multiaddr, PeerId};
+
+pub use crate::libp2p::behaviour::{ChatCommands, ToChat};
+pub use crate::libp2p::config::{NetworkConfig, STREUEN_KAD_PROTOCOL};
+pub use crate::libp2p::executor::SwarmExecutor;
+use crate::libp2p::{SwarmHandle, SwarmStores};
 
 pub struct ChatApp {
     users: users::Users,
     messages: messages::Messages,
 
-    app_callbacks: Vec<AppCallback>,
+    app_callbacks: Rc<RefCell<Vec<AppCallback>>>,
+    subscribers: Rc<RefCell<Vec<events::Subscriber>>>,
+    /// Latest status reported by the swarm.
+    network_status: Rc<RefCell<NetworkStatus>>,
+    /// Latest ping results of every connected peer.
+    latencies: Rc<RefCell<HashMap<PeerId, network::Latency>>>,
 
-    chat_behavior: InnerChatBehavior,
+    app_sender: mpsc::UnboundedSender<ToApp>,
+    commands: ChatCommands,
+    network: NetworkConfig,
+    executor: SwarmExecutor,
+    /// The running swarm task, `None` once it was shut down.
+    swarm: Option<SwarmHandle>,
+    /// Stores handed back by the swarm task when it was shut down, to restart it with.
+    stores: Option<SwarmStores>,
 }
 
 impl ChatApp {
+    /// Creates a chat app with a freshly generated identity and default settings, see
+    /// [`ChatAppBuilder::build`].
     pub fn new(name: String) -> Result<Self, error::ChatAppError> {
-        let keypair = Keypair::generate_ed25519();
-        let current_user = users::User::new(name, keypair.public().to_peer_id().clone());
-        let users = users::Users::new(current_user, keypair.clone());
+        Self::builder().name(name).build()
+    }
 
-        let chat_behavior = crate::libp2p::run_swarm(keypair.clone())?;
+    pub fn builder() -> ChatAppBuilder {
+        ChatAppBuilder::default()
+    }
 
-        Ok(Self {
-            users,
-            messages: messages::Messages::new(),
+    pub fn current_user(&self) -> users::User {
+        self.users.current_user()
+    }
 
-            app_callbacks: Vec::new(),
+    /// Handle to the message history, which is updated as messages are sent and received.
+    pub fn messages(&self) -> messages::Messages {
+        self.messages.clone()
+    }
 
-            chat_behavior,
-        })
+    /// Dispatches a command to the swarm without waiting for its outcome.
+    pub fn chat_dispatch(&mut self, event: ToChat) -> Result<(), error::ChatAppError> {
+        self.commands.dispatch(event)
     }
 
-    pub fn current_user(&self) -> users::User {
-        self.users.current_user()
+    /// Handle to dispatch commands and wait for their outcome, independent of the app's lifetime.
+    ///
+    /// The handle belongs to the currently running swarm, commands sent through it fail once the
+    /// swarm was shut down, even if it was restarted since.
+    pub fn commands(&self) -> ChatCommands {
+        self.commands.clone()
+    }
+
+    /// How other peers can reach us as far as the swarm knows, which is reported with every change
+    /// through [`ToApp::NetworkStatusChanged`] as well.
+    pub fn network_status(&self) -> NetworkStatus {
+        self.network_status.borrow().clone()
     }
 
-    pub fn chat_dispatch(&mut self, event: ToChat) {
-        self.chat_behavior.send(event);
+    /// Round trip times to a connected peer, `None` before it was pinged once.
+    pub fn latency(&self, peer_id: PeerId) -> Option<network::Latency> {
+        self.latencies.borrow().get(&peer_id).cloned()
+    }
+
+    /// The settings the swarm is currently running with.
+    pub fn network_config(&self) -> &NetworkConfig {
+        &self.network
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.swarm.is_some()
+    }
+
+    /// Stops the swarm, resolving once its task exited.
+    ///
+    /// Pending direct messages get a few seconds to be acknowledged before every connection is
+    /// closed, messages still unacknowledged after that are marked as failed. The addresses of
+    /// known peers are persisted to the peer store. Does nothing if the swarm is not running.
+    pub async fn shutdown(&mut self) -> Result<(), error::ChatAppError> {
+        if let Some(swarm) = self.swarm.take() {
+            self.stores = Some(swarm.shutdown().await?);
+        }
+        Ok(())
+    }
+
+    /// Shuts the swarm down if it is running and starts it again with `config`, keeping the
+    /// identity, the stores, the executor and every event subscriber.
+    ///
+    /// Handles obtained from [`Self::commands`] before have to be replaced by a fresh one.
+    pub async fn restart(&mut self, config: NetworkConfig) -> Result<(), error::ChatAppError> {
+        self.shutdown().await?;
+        let stores = self
+            .stores
+            .take()
+            .ok_or(error::ChatAppError::SwarmStopped)?;
+        let (commands, swarm) = crate::libp2p::run_swarm(
+            self.users.keypair().clone(),
+            stores,
+            config.clone(),
+            self.app_sender.clone(),
+            &self.executor,
+        )?;
+        self.commands = commands;
+        self.network = config;
+        self.swarm = Some(swarm);
+        Ok(())
     }
 
     pub fn register_app_handler(&mut self, cb: AppCallback) {
-        self.app_callbacks.push(cb);
+        self.app_callbacks.borrow_mut().push(cb);
+    }
+
+    /// Subscribes to the events of the app, as an alternative to [`Self::register_app_handler`]
+    /// which can be moved to other tasks and threads.
+    pub fn events(&self) -> EventStream {
+        let (subscriber, stream) = events::Subscriber::new();
+        self.subscribers.borrow_mut().push(subscriber);
+        stream
     }
 }
 
+/// Records the history of direct messages and fans out every event produced by the swarm to the
+/// registered callbacks and event streams. Room messages are not recorded.
+async fn run_app_loop(
+    mut app_receiver: mpsc::UnboundedReceiver<ToApp>,
+    messages: messages::Messages,
+    app_callbacks: Rc<RefCell<Vec<AppCallback>>>,
+    subscribers: Rc<RefCell<Vec<events::Subscriber>>>,
+    network_status: Rc<RefCell<NetworkStatus>>,
+    latencies: Rc<RefCell<HashMap<PeerId, network::Latency>>>,
+) {
+    use futures::StreamExt;
+
+    while let Some(event) = app_receiver.next().await {
+        match &event {
+            ToApp::MessageReceived(message) => {
+                // a message may reach us more than once, e.g. when resent after a restart
+                if let Ok(Some(_)) = messages.get(message.message_id()).await {
+                    tracing::debug!("Dropping duplicate message [{}]", message.message_id());
+                    continue;
+                }
+                if let Err(err) = messages.record(message).await {
+                    tracing::error!("Failed to record message [{}]: {err}", message.message_id());
+                }
+            }
+            ToApp::MessageSent(message) => {
+                if let Err(err) = messages.record(message).await {
+                    tracing::error!("Failed to record message [{}]: {err}", message.message_id());
+                }
+            }
+            ToApp::MessageStatusChanged {
+                message_id, status, ..
+            } => {
+                if let Err(err) = messages.update_status(*message_id, *status).await {
+                    tracing::error!("Failed to update status of message [{message_id}]: {err}");
+                }
+            }
+            ToApp::NetworkStatusChanged(status) => *network_status.borrow_mut() = status.clone(),
+            ToApp::PeerLatency { peer_id, latency } => {
+                latencies.borrow_mut().insert(*peer_id, latency.clone());
+            }
+            ToApp::PeerDisconnected(peer_id) => {
+                latencies.borrow_mut().remove(peer_id);
+            }
+            _ => {}
+        }
+        // the callbacks are cloned out so a callback may register further handlers
+        let callbacks = app_callbacks.borrow().clone();
+        for callback in callbacks {
+            callback.emit(event.clone());
+        }
+        subscribers
+            .borrow_mut()
+            .retain_mut(|subscriber| subscriber.send(&event));
+    }
+}
+
+/// The loop recording the message history and delivering the events of a [`ChatApp`], returned
+/// by [`ChatAppBuilder::build_with_loop`]. It ends once the swarm and the app are gone.
+///
+/// The stores and callbacks are not `Send`, so it has to be polled on the thread the app was
+/// built on, by any executor.
+pub struct AppLoop(LocalBoxFuture<'static, ()>);
+
+impl Future for AppLoop {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        self.0.as_mut().poll(cx)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn spawn_app_loop(app_loop: AppLoop) {
+    wasm_bindgen_futures::spawn_local(app_loop);
+}
+
+/// Panics outside of a [`tokio::task::LocalSet`].
+#[cfg(not(target_arch = "wasm32"))]
+fn spawn_app_loop(app_loop: AppLoop) {
+    tokio::task::spawn_local(app_loop);
+}
+
 pub struct AppCallback {
-    cb: Rc<dyn Fn(ToApp) -> ()>,
+    cb: Rc<dyn Fn(ToApp)>,
 }
 
 impl AppCallback {
@@ -59,7 +243,7 @@ pub fn emit(&self, event: ToApp) {
     }
 }
 
-impl<F: Fn(ToApp) -> () + 'static> From<F> for AppCallback {
+impl<F: Fn(ToApp) + 'static> From<F> for AppCallback {
     fn from(func: F) -> Self {
         Self { cb: Rc::new(func) }
     }
@@ -75,5 +259,60 @@ fn clone(&self) -> Self {
 
 #[derive(Clone, Debug)]
 pub enum ToApp {
-    X,
+    /// A direct message was handed to the network for delivery.
+    MessageSent(messages::Message),
+    /// A direct message from another peer.
+    MessageReceived(messages::Message),
+    RoomJoined(String),
+    RoomLeft(String),
+    /// A message was published to a room, only delivered as an event as rooms are not persisted.
+    RoomMessageSent(messages::RoomMessage),
+    RoomMessageReceived(messages::RoomMessage),
+    /// The delivery state of one of our direct messages changed.
+    MessageStatusChanged {
+        message_id: messages::MessageId,
+        to: PeerId,
+        status: messages::MessageStatus,
+    },
+    /// A connect command or direct message is trying to reach the peer in another way.
+    Connecting {
+        peer_id: PeerId,
+        step: ConnectStep,
+    },
+    /// The first connection to the peer was established.
+    PeerConnected(PeerId),
+    /// The last connection to the peer was closed.
+    PeerDisconnected(PeerId),
+    /// A ping to the peer was answered or went unanswered.
+    PeerLatency {
+        peer_id: PeerId,
+        latency: network::Latency,
+    },
+    PeerDiscovered {
+        peer_id: PeerId,
+        source: DiscoverySource,
+    },
+    ListenAddressAdded(Multiaddr),
+    /// Anything about our reachability changed, holds the whole new status.
+    NetworkStatusChanged(NetworkStatus),
+    Error(String),
+}
+
+/// The ways a peer without a connection is reached, tried in this order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConnectStep {
+    /// Dialing the addresses known of the peer, including those found in the DHT.
+    Dialing,
+    /// Asking the DHT for the addresses of the peer.
+    LookingUp,
+    /// Dialing the peer through the relays we know of.
+    Relaying,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiscoverySource {
+    Mdns,
+    Kademlia,
+    /// Registered in the namespace at a rendezvous point.
+    Rendezvous(String),
 }
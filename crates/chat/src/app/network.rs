@@ -0,0 +1,178 @@
+use std::{collections::HashMap, fmt, time::Duration};
+
+use libp2p::{Multiaddr, PeerId};
+
+/// What the swarm knows about how other peers can reach us, to tell why a connection does not
+/// come about.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetworkStatus {
+    pub reachability: NatStatus,
+    /// Addresses other peers confirmed to reach us at.
+    pub external_addresses: Vec<Multiaddr>,
+    pub upnp: UpnpStatus,
+    /// Relays holding a reservation for us, with the circuit addresses we are reached at through
+    /// them.
+    pub relay_reservations: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Outcome of the latest attempt to upgrade a relayed connection to each peer to a direct one.
+    pub hole_punches: HashMap<PeerId, HolePunch>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum NatStatus {
+    /// Reachable from the public internet at the given address.
+    Public(Multiaddr),
+    Private,
+    #[default]
+    Unknown,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum UpnpStatus {
+    /// Turned off, or not available on the target.
+    #[default]
+    Disabled,
+    /// Looking for a gateway to map our listen ports on.
+    Searching,
+    GatewayNotFound,
+    /// The gateway was found, but is itself behind another NAT.
+    NonRoutableGateway,
+    /// Addresses mapped on the gateway.
+    Mapped(Vec<Multiaddr>),
+}
+
+/// Round trip times of the latest pings to a peer.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Latency {
+    /// Oldest first, only the latest few are kept.
+    pub rtts: Vec<Duration>,
+    /// Pings which failed since the last one answered.
+    pub failures: u32,
+}
+
+/// How well a connection to a peer works, judged by the latency to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConnectionQuality {
+    Good,
+    Fair,
+    Poor,
+    /// The latest ping was not answered.
+    Unresponsive,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum HolePunch {
+    Succeeded,
+    Failed(String),
+}
+
+impl fmt::Display for NatStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Public(addr) => write!(f, "public at {addr}"),
+            Self::Private => f.write_str("private"),
+            Self::Unknown => f.write_str("unknown"),
+        }
+    }
+}
+
+impl fmt::Display for UpnpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Disabled => f.write_str("disabled"),
+            Self::Searching => f.write_str("searching for a gateway"),
+            Self::GatewayNotFound => f.write_str("no gateway found"),
+            Self::NonRoutableGateway => f.write_str("gateway is not publicly routable"),
+            Self::Mapped(addrs) => write!(f, "{} addresses mapped", addrs.len()),
+        }
+    }
+}
+
+impl fmt::Display for HolePunch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Succeeded => f.write_str("succeeded"),
+            Self::Failed(err) => write!(f, "failed: {err}"),
+        }
+    }
+}
+
+impl Latency {
+    pub fn average(&self) -> Option<Duration> {
+        if self.rtts.is_empty() {
+            return None;
+        }
+        Some(self.rtts.iter().sum::<Duration>() / self.rtts.len() as u32)
+    }
+
+    /// `None` until the first ping was answered or failed.
+    pub fn quality(&self) -> Option<ConnectionQuality> {
+        if self.failures > 0 {
+            return Some(ConnectionQuality::Unresponsive);
+        }
+        Some(match self.average()? {
+            rtt if rtt < Duration::from_millis(150) => ConnectionQuality::Good,
+            rtt if rtt < Duration::from_millis(400) => ConnectionQuality::Fair,
+            _ => ConnectionQuality::Poor,
+        })
+    }
+}
+
+impl fmt::Display for ConnectionQuality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Good => "good",
+            Self::Fair => "fair",
+            Self::Poor => "poor",
+            Self::Unresponsive => "unresponsive",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn latency(millis: &[u64], failures: u32) -> Latency {
+        Latency {
+            rtts: millis.iter().copied().map(Duration::from_millis).collect(),
+            failures,
+        }
+    }
+
+    #[test]
+    fn quality_is_unknown_before_the_first_ping() {
+        assert_eq!(Latency::default().average(), None);
+        assert_eq!(Latency::default().quality(), None);
+    }
+
+    #[test]
+    fn quality_follows_the_average_round_trip_time() {
+        for (millis, quality) in [
+            (0, ConnectionQuality::Good),
+            (149, ConnectionQuality::Good),
+            (150, ConnectionQuality::Fair),
+            (399, ConnectionQuality::Fair),
+            (400, ConnectionQuality::Poor),
+            (10_000, ConnectionQuality::Poor),
+        ] {
+            assert_eq!(latency(&[millis], 0).quality(), Some(quality), "{millis}ms");
+        }
+        // a single slow ping among fast ones is averaged out
+        let latency = latency(&[100, 100, 100, 280], 0);
+        assert_eq!(latency.average(), Some(Duration::from_millis(145)));
+        assert_eq!(latency.quality(), Some(ConnectionQuality::Good));
+    }
+
+    #[test]
+    fn failed_ping_makes_a_peer_unresponsive() {
+        assert_eq!(
+            latency(&[10], 1).quality(),
+            Some(ConnectionQuality::Unresponsive)
+        );
+        // even without any answered ping
+        assert_eq!(
+            latency(&[], 1).quality(),
+            Some(ConnectionQuality::Unresponsive)
+        );
+    }
+}
@@ -0,0 +1,27 @@
+use super::error::StorageError;
+
+/// Backend persisting what the node learned about other peers, such as their addresses, so they
+/// can be reached again after a restart without rediscovering them.
+pub trait PeerStore: Send {
+    fn load(&self) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Stores the peers, replacing whatever was stored before.
+    fn save(&mut self, peers: &[u8]) -> Result<(), StorageError>;
+}
+
+/// Peer store which is lost once the app exits.
+#[derive(Default)]
+pub struct MemoryPeerStore {
+    peers: Option<Vec<u8>>,
+}
+
+impl PeerStore for MemoryPeerStore {
+    fn load(&self) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.peers.clone())
+    }
+
+    fn save(&mut self, peers: &[u8]) -> Result<(), StorageError> {
+        self.peers = Some(peers.to_vec());
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+
+use libp2p::PeerId;
+
+use super::error::StorageError;
+
+/// Backend persisting the end-to-end encryption sessions with other peers.
+///
+/// Sessions are opaque to the store but hold secret key material, so they deserve the same care
+/// as the node identity.
+pub trait SessionStore: Send {
+    fn load(&self, peer_id: PeerId) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Stores the session, replacing any session stored for the peer.
+    fn save(&mut self, peer_id: PeerId, session: &[u8]) -> Result<(), StorageError>;
+}
+
+/// Session store which is lost once the app exits.
+#[derive(Default)]
+pub struct MemorySessionStore {
+    sessions: HashMap<PeerId, Vec<u8>>,
+}
+
+impl SessionStore for MemorySessionStore {
+    fn load(&self, peer_id: PeerId) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(self.sessions.get(&peer_id).cloned())
+    }
+
+    fn save(&mut self, peer_id: PeerId, session: &[u8]) -> Result<(), StorageError> {
+        self.sessions.insert(peer_id, session.to_vec());
+        Ok(())
+    }
+}
@@ -17,8 +17,12 @@ pub fn new(name: String, peer_id: PeerId) -> Self {
         Self { name, peer_id }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn peer_id(&self) -> PeerId {
-        self.peer_id.clone()
+        self.peer_id
     }
 }
 
@@ -35,8 +39,7 @@ pub fn new(current_user: User, keypair: Keypair) -> Self {
             keypair,
         };
 
-        let mut users = Vec::new();
-        users.push(current_user.user.clone());
+        let users = vec![current_user.user.clone()];
 
         Self {
             users,
@@ -47,4 +50,12 @@ pub fn new(current_user: User, keypair: Keypair) -> Self {
     pub(crate) fn current_user(&self) -> User {
         self.current_user.user.clone()
     }
+
+    pub(crate) fn keypair(&self) -> &Keypair {
+        &self.current_user.keypair
+    }
+
+    pub fn users(&self) -> &[User] {
+        &self.users
+    }
 }
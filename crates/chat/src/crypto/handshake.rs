@@ -0,0 +1,100 @@
+use hkdf::Hkdf;
+use libp2p::{PeerId, identity::Keypair};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256, Sha512};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use crate::app::EncryptionError;
+
+const HANDSHAKE_INFO: &[u8] = b"streuen-chat handshake";
+
+/// Sent along with the messages of a new session until the responder replies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Handshake {
+    /// Ephemeral key of the initiator, which also identifies the session.
+    pub ephemeral: [u8; 32],
+}
+
+/// The X25519 form of the local ed25519 identity key.
+pub(crate) fn identity_secret(keypair: &Keypair) -> Result<StaticSecret, EncryptionError> {
+    let keypair = keypair
+        .clone()
+        .try_into_ed25519()
+        .map_err(|_| EncryptionError::UnsupportedIdentity(keypair.public().to_peer_id()))?;
+    // the scalar of an ed25519 key is derived from the first half of the hashed seed, clamping is
+    // left to x25519
+    let hash = Sha512::digest(keypair.secret().as_ref());
+    let mut scalar = [0u8; 32];
+    scalar.copy_from_slice(&hash[..32]);
+    Ok(StaticSecret::from(scalar))
+}
+
+/// The X25519 form of the ed25519 identity key embedded in the peer id.
+pub(crate) fn identity_public(peer_id: PeerId) -> Result<PublicKey, EncryptionError> {
+    const IDENTITY_MULTIHASH: u64 = 0x00;
+
+    let multihash = peer_id.as_ref();
+    if multihash.code() != IDENTITY_MULTIHASH {
+        return Err(EncryptionError::UnsupportedIdentity(peer_id));
+    }
+    let public_key = libp2p::identity::PublicKey::try_decode_protobuf(multihash.digest())
+        .ok()
+        .and_then(|key| key.try_into_ed25519().ok())
+        .ok_or(EncryptionError::UnsupportedIdentity(peer_id))?;
+    let montgomery = curve25519_dalek::edwards::CompressedEdwardsY(public_key.to_bytes())
+        .decompress()
+        .ok_or(EncryptionError::InvalidKey)?
+        .to_montgomery();
+    Ok(PublicKey::from(montgomery.to_bytes()))
+}
+
+/// Starts a session with the owner of `remote_identity`, returning the handshake to send along
+/// and the shared secret.
+pub(crate) fn initiate(
+    local_identity: &StaticSecret,
+    remote_identity: &PublicKey,
+) -> Result<(Handshake, [u8; 32]), EncryptionError> {
+    let ephemeral = super::random_secret()?;
+    let shared = shared_secret(
+        &diffie_hellman(local_identity, remote_identity)?,
+        &diffie_hellman(&ephemeral, remote_identity)?,
+    );
+    let handshake = Handshake {
+        ephemeral: PublicKey::from(&ephemeral).to_bytes(),
+    };
+    Ok((handshake, shared))
+}
+
+/// Derives the shared secret of a session started by the owner of `remote_identity`.
+pub(crate) fn respond(
+    local_identity: &StaticSecret,
+    remote_identity: &PublicKey,
+    handshake: &Handshake,
+) -> Result<[u8; 32], EncryptionError> {
+    Ok(shared_secret(
+        &diffie_hellman(local_identity, remote_identity)?,
+        &diffie_hellman(local_identity, &PublicKey::from(handshake.ephemeral))?,
+    ))
+}
+
+pub(crate) fn diffie_hellman(
+    secret: &StaticSecret,
+    public: &PublicKey,
+) -> Result<[u8; 32], EncryptionError> {
+    let shared = secret.diffie_hellman(public);
+    if !shared.was_contributory() {
+        return Err(EncryptionError::InvalidKey);
+    }
+    Ok(shared.to_bytes())
+}
+
+fn shared_secret(identity_dh: &[u8; 32], ephemeral_dh: &[u8; 32]) -> [u8; 32] {
+    let mut input = [0u8; 64];
+    input[..32].copy_from_slice(identity_dh);
+    input[32..].copy_from_slice(ephemeral_dh);
+    let mut shared = [0u8; 32];
+    Hkdf::<Sha256>::new(Some(&[0u8; 32]), &input)
+        .expand(HANDSHAKE_INFO, &mut shared)
+        .expect("32 bytes is a valid HKDF-SHA256 output length");
+    shared
+}
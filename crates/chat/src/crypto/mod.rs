@@ -0,0 +1,494 @@
+//! End-to-end encryption of direct messages.
+//!
+//! Sessions are set up with an X3DH-like handshake between the X25519 forms of both peers'
+//! ed25519 identity keys and an ephemeral key of the initiator, and then advanced with the
+//! [Double Ratchet](https://signal.org/docs/specifications/doubleratchet/).
+
+mod handshake;
+mod ratchet;
+
+use std::collections::HashMap;
+
+use libp2p::{PeerId, identity::Keypair};
+use serde::{Deserialize, Serialize};
+use x25519_dalek::StaticSecret;
+
+use crate::app::{EncryptionError, StorageError, messages::MessageId, sessions::SessionStore};
+
+pub use handshake::Handshake;
+pub use ratchet::Header;
+
+/// Ciphertext of a direct message.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct EncryptedMessage {
+    /// Set until the initiator of the session received a reply.
+    pub handshake: Option<Handshake>,
+    pub header: Header,
+    pub ciphertext: Vec<u8>,
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+struct Session {
+    /// Ephemeral key of the handshake which started the session.
+    handshake: [u8; 32],
+    /// Whether the local peer started the session.
+    initiator: bool,
+    /// Whether a message was received on the session, after which the handshake is no longer
+    /// sent along.
+    confirmed: bool,
+    ratchet: ratchet::Ratchet,
+}
+
+/// The end-to-end encryption sessions of the local peer, loaded from the [`SessionStore`] on
+/// first use.
+pub(crate) struct Sessions {
+    local_peer_id: PeerId,
+    identity: StaticSecret,
+    sessions: HashMap<PeerId, Session>,
+    store: Box<dyn SessionStore>,
+}
+
+impl Sessions {
+    pub fn new(keypair: &Keypair, store: Box<dyn SessionStore>) -> Result<Self, EncryptionError> {
+        Ok(Self {
+            local_peer_id: keypair.public().to_peer_id(),
+            identity: handshake::identity_secret(keypair)?,
+            sessions: HashMap::new(),
+            store,
+        })
+    }
+
+    /// Hands back the store, every session is persisted as soon as it changes.
+    pub fn into_store(self) -> Box<dyn SessionStore> {
+        self.store
+    }
+
+    pub fn encrypt(
+        &mut self,
+        peer_id: PeerId,
+        message_id: MessageId,
+        plaintext: &[u8],
+    ) -> Result<EncryptedMessage, EncryptionError> {
+        let associated_data = associated_data(self.local_peer_id, peer_id, message_id);
+        let mut session = match self.load(peer_id)? {
+            Some(session) => session,
+            None => {
+                let remote_identity = handshake::identity_public(peer_id)?;
+                let (handshake, shared) = handshake::initiate(&self.identity, &remote_identity)?;
+                Session {
+                    handshake: handshake.ephemeral,
+                    initiator: true,
+                    confirmed: false,
+                    ratchet: ratchet::Ratchet::initiator(shared, remote_identity)?,
+                }
+            }
+        };
+
+        let (header, ciphertext) = session.ratchet.encrypt(plaintext, &associated_data)?;
+        let handshake = (session.initiator && !session.confirmed).then_some(Handshake {
+            ephemeral: session.handshake,
+        });
+        self.save(peer_id, session);
+        Ok(EncryptedMessage {
+            handshake,
+            header,
+            ciphertext,
+        })
+    }
+
+    pub fn decrypt(
+        &mut self,
+        peer_id: PeerId,
+        message_id: MessageId,
+        message: &EncryptedMessage,
+    ) -> Result<Vec<u8>, EncryptionError> {
+        let associated_data = associated_data(peer_id, self.local_peer_id, message_id);
+        let current = self.load(peer_id)?;
+
+        if let Some(handshake) = message.handshake
+            && current
+                .as_ref()
+                .is_none_or(|session| session.handshake != handshake.ephemeral)
+        {
+            let remote_identity = handshake::identity_public(peer_id)?;
+            let shared = handshake::respond(&self.identity, &remote_identity, &handshake)?;
+            let mut session = Session {
+                handshake: handshake.ephemeral,
+                initiator: false,
+                confirmed: true,
+                ratchet: ratchet::Ratchet::responder(shared),
+            };
+            let plaintext = session.ratchet.decrypt(
+                &message.header,
+                &message.ciphertext,
+                &associated_data,
+                &self.identity,
+            )?;
+            // when both peers started a session at once, the one started by the smaller peer id
+            // is kept, while messages of the other one can still be read
+            let keep_current = current
+                .is_some_and(|session| session.initiator && !session.confirmed)
+                && self.local_peer_id < peer_id;
+            if !keep_current {
+                self.save(peer_id, session);
+            }
+            return Ok(plaintext);
+        }
+
+        let mut session = current.ok_or(EncryptionError::MissingSession(peer_id))?;
+        let plaintext = session.ratchet.decrypt(
+            &message.header,
+            &message.ciphertext,
+            &associated_data,
+            &self.identity,
+        )?;
+        session.confirmed = true;
+        self.save(peer_id, session);
+        Ok(plaintext)
+    }
+
+    fn load(&mut self, peer_id: PeerId) -> Result<Option<Session>, EncryptionError> {
+        if let Some(session) = self.sessions.get(&peer_id) {
+            return Ok(Some(session.clone()));
+        }
+        let Some(bytes) = self.store.load(peer_id)? else {
+            return Ok(None);
+        };
+        let session: Session = cbor4ii::serde::from_slice(&bytes)
+            .map_err(|err| StorageError::Serialization(err.to_string()))?;
+        self.sessions.insert(peer_id, session.clone());
+        Ok(Some(session))
+    }
+
+    /// Keeps the session in memory even if it could not be persisted, so the current run is not
+    /// affected.
+    fn save(&mut self, peer_id: PeerId, session: Session) {
+        let persisted = cbor4ii::serde::to_vec(Vec::new(), &session)
+            .map_err(|err| StorageError::Serialization(err.to_string()))
+            .and_then(|bytes| self.store.save(peer_id, &bytes));
+        if let Err(err) = persisted {
+            tracing::error!("Failed to persist session with [{peer_id}]: {err}");
+        }
+        self.sessions.insert(peer_id, session);
+    }
+}
+
+/// Binds a ciphertext to its sender, recipient and message id.
+fn associated_data(sender: PeerId, recipient: PeerId, message_id: MessageId) -> Vec<u8> {
+    [
+        sender.to_bytes(),
+        recipient.to_bytes(),
+        message_id.as_bytes().to_vec(),
+    ]
+    .concat()
+}
+
+fn random_secret() -> Result<StaticSecret, EncryptionError> {
+    let mut bytes = [0u8; 32];
+    getrandom::fill(&mut bytes)?;
+    Ok(StaticSecret::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::sessions::MemorySessionStore;
+
+    struct Peer {
+        keypair: Keypair,
+        peer_id: PeerId,
+        sessions: Sessions,
+    }
+
+    impl Peer {
+        fn new() -> Self {
+            let keypair = Keypair::generate_ed25519();
+            let sessions =
+                Sessions::new(&keypair, Box::new(MemorySessionStore::default())).unwrap();
+            Self {
+                peer_id: keypair.public().to_peer_id(),
+                keypair,
+                sessions,
+            }
+        }
+
+        fn send(&mut self, to: &Peer, text: &str) -> (MessageId, EncryptedMessage) {
+            let message_id = MessageId::generate().unwrap();
+            let message = self
+                .sessions
+                .encrypt(to.peer_id, message_id, text.as_bytes())
+                .unwrap();
+            (message_id, message)
+        }
+
+        fn receive(
+            &mut self,
+            from: &Peer,
+            (message_id, message): &(MessageId, EncryptedMessage),
+        ) -> Result<String, EncryptionError> {
+            let plaintext = self.sessions.decrypt(from.peer_id, *message_id, message)?;
+            Ok(String::from_utf8(plaintext).unwrap())
+        }
+
+        /// Reloads the sessions from the store, as after a restart.
+        fn restart(&mut self) {
+            let store = std::mem::replace(
+                &mut self.sessions,
+                Sessions::new(&self.keypair, Box::new(MemorySessionStore::default())).unwrap(),
+            )
+            .into_store();
+            self.sessions = Sessions::new(&self.keypair, store).unwrap();
+        }
+    }
+
+    /// A pair of peers with `a` sorting before `b`, which matters when both start a session.
+    fn peers() -> (Peer, Peer) {
+        let (a, b) = (Peer::new(), Peer::new());
+        if a.peer_id < b.peer_id {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    #[test]
+    fn messages_round_trip_in_both_directions() {
+        let (mut alice, mut bob) = peers();
+
+        let first = alice.send(&bob, "hello bob");
+        assert!(first.1.handshake.is_some());
+        assert_eq!(bob.receive(&alice, &first).unwrap(), "hello bob");
+
+        let reply = bob.send(&alice, "hello alice");
+        assert!(reply.1.handshake.is_none());
+        assert_eq!(alice.receive(&bob, &reply).unwrap(), "hello alice");
+
+        // the handshake is no longer sent once the responder replied
+        let second = alice.send(&bob, "how are you?");
+        assert!(second.1.handshake.is_none());
+        assert_eq!(bob.receive(&alice, &second).unwrap(), "how are you?");
+    }
+
+    #[test]
+    fn handshake_is_repeated_until_the_responder_replies() {
+        let (mut alice, mut bob) = peers();
+
+        let messages: Vec<_> = (0..3).map(|i| alice.send(&bob, &i.to_string())).collect();
+        let handshakes: Vec<_> = messages.iter().map(|(_, m)| m.handshake).collect();
+        assert!(handshakes[0].is_some());
+        assert!(
+            handshakes
+                .iter()
+                .all(|handshake| *handshake == handshakes[0])
+        );
+
+        // any of them starts the session on the responder's side
+        assert_eq!(bob.receive(&alice, &messages[2]).unwrap(), "2");
+        assert_eq!(bob.receive(&alice, &messages[0]).unwrap(), "0");
+        assert_eq!(bob.receive(&alice, &messages[1]).unwrap(), "1");
+    }
+
+    #[test]
+    fn replies_step_the_ratchet() {
+        let (mut alice, mut bob) = peers();
+
+        let first = alice.send(&bob, "1");
+        let second = alice.send(&bob, "2");
+        assert_eq!(first.1.header.ratchet_key, second.1.header.ratchet_key);
+        assert_eq!(second.1.header.message_number, 1);
+        bob.receive(&alice, &first).unwrap();
+        bob.receive(&alice, &second).unwrap();
+
+        let reply = bob.send(&alice, "3");
+        alice.receive(&bob, &reply).unwrap();
+        let third = alice.send(&bob, "4");
+        // a new sending chain with a fresh ratchet key, counting the messages of the last one
+        assert_ne!(third.1.header.ratchet_key, first.1.header.ratchet_key);
+        assert_eq!(third.1.header.message_number, 0);
+        assert_eq!(third.1.header.previous_chain_length, 2);
+        assert_eq!(bob.receive(&alice, &third).unwrap(), "4");
+
+        let second_reply = bob.send(&alice, "5");
+        assert_ne!(
+            second_reply.1.header.ratchet_key,
+            reply.1.header.ratchet_key
+        );
+        assert_eq!(alice.receive(&bob, &second_reply).unwrap(), "5");
+    }
+
+    #[test]
+    fn messages_are_decrypted_out_of_order() {
+        let (mut alice, mut bob) = peers();
+        let first = alice.send(&bob, "1");
+        bob.receive(&alice, &first).unwrap();
+        let reply = bob.send(&alice, "2");
+        alice.receive(&bob, &reply).unwrap();
+
+        let old_chain: Vec<_> = (0..3).map(|i| alice.send(&bob, &i.to_string())).collect();
+        let reply = bob.send(&alice, "reply");
+        alice.receive(&bob, &reply).unwrap();
+        let new_chain: Vec<_> = (3..5).map(|i| alice.send(&bob, &i.to_string())).collect();
+
+        // skipping over the rest of the old chain and into the new one
+        assert_eq!(bob.receive(&alice, &new_chain[1]).unwrap(), "4");
+        assert_eq!(bob.receive(&alice, &old_chain[1]).unwrap(), "1");
+        assert_eq!(bob.receive(&alice, &new_chain[0]).unwrap(), "3");
+        assert_eq!(bob.receive(&alice, &old_chain[2]).unwrap(), "2");
+        assert_eq!(bob.receive(&alice, &old_chain[0]).unwrap(), "0");
+
+        // the key of each message is used once
+        assert!(matches!(
+            bob.receive(&alice, &old_chain[0]),
+            Err(EncryptionError::Decryption)
+        ));
+        assert!(matches!(
+            bob.receive(&alice, &new_chain[1]),
+            Err(EncryptionError::Decryption)
+        ));
+    }
+
+    #[test]
+    fn too_many_skipped_messages_are_rejected() {
+        let (mut alice, mut bob) = peers();
+        let first = alice.send(&bob, "first");
+        bob.receive(&alice, &first).unwrap();
+
+        let messages: Vec<_> = (0..ratchet::MAX_SKIP + 2)
+            .map(|i| alice.send(&bob, &i.to_string()))
+            .collect();
+        let last = messages.last().unwrap();
+        assert!(matches!(
+            bob.receive(&alice, last),
+            Err(EncryptionError::TooManySkipped)
+        ));
+
+        // skipping exactly the limit is fine, and the rejection did not advance the session
+        let within_limit = &messages[ratchet::MAX_SKIP as usize];
+        assert_eq!(
+            bob.receive(&alice, within_limit).unwrap(),
+            ratchet::MAX_SKIP.to_string()
+        );
+        assert_eq!(bob.receive(&alice, &messages[0]).unwrap(), "0");
+        assert_eq!(
+            bob.receive(&alice, last).unwrap(),
+            (ratchet::MAX_SKIP + 1).to_string()
+        );
+    }
+
+    #[test]
+    fn simultaneously_started_sessions_converge() {
+        let (mut alice, mut bob) = peers();
+
+        let from_alice = alice.send(&bob, "hi bob");
+        let from_bob = bob.send(&alice, "hi alice");
+        let late = bob.send(&alice, "late");
+        assert_eq!(alice.receive(&bob, &from_bob).unwrap(), "hi alice");
+        assert_eq!(bob.receive(&alice, &from_alice).unwrap(), "hi bob");
+        // messages of the session given up are still read
+        assert_eq!(alice.receive(&bob, &late).unwrap(), "late");
+
+        // both keep the session started by the smaller peer id
+        let reply = bob.send(&alice, "reply to alice");
+        assert!(reply.1.handshake.is_none());
+        assert_eq!(alice.receive(&bob, &reply).unwrap(), "reply to alice");
+        let reply = alice.send(&bob, "reply to bob");
+        assert!(reply.1.handshake.is_none());
+        assert_eq!(bob.receive(&alice, &reply).unwrap(), "reply to bob");
+    }
+
+    #[test]
+    fn tampered_messages_are_rejected_without_touching_the_session() {
+        let (mut alice, mut bob) = peers();
+        let (message_id, message) = alice.send(&bob, "hello");
+
+        let mut tampered = message.clone();
+        tampered.ciphertext[0] ^= 1;
+        let mut tampered_header = message.clone();
+        tampered_header.header.message_number += 1;
+        for forged in [
+            (message_id, tampered),
+            (message_id, tampered_header),
+            // the message id is authenticated along with the ciphertext
+            (MessageId::generate().unwrap(), message.clone()),
+        ] {
+            assert!(matches!(
+                bob.receive(&alice, &forged),
+                Err(EncryptionError::Decryption)
+            ));
+        }
+        // a message of one peer cannot be passed off as one of another
+        let mallory = Peer::new();
+        assert!(
+            bob.receive(&mallory, &(message_id, message.clone()))
+                .is_err()
+        );
+        assert!(bob.sessions.sessions.is_empty());
+
+        assert_eq!(
+            bob.receive(&alice, &(message_id, message)).unwrap(),
+            "hello"
+        );
+        let skipped = alice.send(&bob, "skipped");
+        let next = alice.send(&bob, "next");
+        assert_eq!(bob.receive(&alice, &next).unwrap(), "next");
+
+        let mut tampered = skipped.clone();
+        tampered.1.ciphertext.push(0);
+        assert!(bob.receive(&alice, &tampered).is_err());
+        let mut tampered = alice.send(&bob, "later");
+        tampered.1.ciphertext[0] ^= 1;
+        assert!(bob.receive(&alice, &tampered).is_err());
+
+        assert_eq!(bob.receive(&alice, &skipped).unwrap(), "skipped");
+        let reply = bob.send(&alice, "reply");
+        assert_eq!(alice.receive(&bob, &reply).unwrap(), "reply");
+    }
+
+    #[test]
+    fn identity_key_is_not_stored() {
+        let (mut alice, mut bob) = peers();
+        let identity = bob.sessions.identity.to_bytes();
+        let contains_identity = |bytes: &[u8]| {
+            bytes
+                .windows(identity.len())
+                .any(|window| window == identity)
+        };
+
+        let fresh = Session {
+            handshake: [0; 32],
+            initiator: false,
+            confirmed: true,
+            ratchet: ratchet::Ratchet::responder([1; 32]),
+        };
+        let bytes = cbor4ii::serde::to_vec(Vec::new(), &fresh).unwrap();
+        assert!(!contains_identity(&bytes));
+
+        let first = alice.send(&bob, "hello");
+        bob.receive(&alice, &first).unwrap();
+        let reply = bob.send(&alice, "hi");
+        alice.receive(&bob, &reply).unwrap();
+        let stored = bob.sessions.store.load(alice.peer_id).unwrap().unwrap();
+        assert!(!contains_identity(&stored));
+    }
+
+    #[test]
+    fn sessions_survive_a_restart() {
+        let (mut alice, mut bob) = peers();
+        let first = alice.send(&bob, "1");
+        bob.receive(&alice, &first).unwrap();
+        let skipped = alice.send(&bob, "skipped");
+        let second = alice.send(&bob, "2");
+        bob.receive(&alice, &second).unwrap();
+
+        alice.restart();
+        bob.restart();
+
+        assert_eq!(bob.receive(&alice, &skipped).unwrap(), "skipped");
+        let reply = bob.send(&alice, "reply");
+        assert!(reply.1.handshake.is_none());
+        assert_eq!(alice.receive(&bob, &reply).unwrap(), "reply");
+        let third = alice.send(&bob, "3");
+        assert!(third.1.handshake.is_none());
+        assert_eq!(bob.receive(&alice, &third).unwrap(), "3");
+    }
+}
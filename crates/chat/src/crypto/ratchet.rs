@@ -0,0 +1,284 @@
+use chacha20poly1305::{ChaCha20Poly1305, KeyInit, aead::Aead, aead::Payload};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+use super::handshake::diffie_hellman;
+use crate::app::EncryptionError;
+
+const ROOT_INFO: &[u8] = b"streuen-chat ratchet";
+const MESSAGE_INFO: &[u8] = b"streuen-chat message";
+
+/// Maximum number of message keys skipped within a single receiving chain.
+pub(super) const MAX_SKIP: u32 = 1000;
+
+/// Maximum number of skipped message keys kept for out of order messages.
+const MAX_SKIPPED_KEYS: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Header {
+    /// Current ratchet key of the sender.
+    pub ratchet_key: [u8; 32],
+    /// Number of messages in the sender's previous sending chain.
+    pub previous_chain_length: u32,
+    pub message_number: u32,
+}
+
+impl Header {
+    fn to_bytes(self) -> [u8; 40] {
+        let mut bytes = [0u8; 40];
+        bytes[..32].copy_from_slice(&self.ratchet_key);
+        bytes[32..36].copy_from_slice(&self.previous_chain_length.to_be_bytes());
+        bytes[36..].copy_from_slice(&self.message_number.to_be_bytes());
+        bytes
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct SkippedKey {
+    ratchet_key: [u8; 32],
+    message_number: u32,
+    message_key: [u8; 32],
+}
+
+/// Double Ratchet state of a session with a single peer.
+#[derive(Clone, Deserialize, Serialize)]
+pub(crate) struct Ratchet {
+    root_key: [u8; 32],
+    /// `None` while the ratchet key of a responder is still its identity key, which is passed in
+    /// instead so it never ends up in the stored session.
+    ratchet_secret: Option<[u8; 32]>,
+    remote_ratchet_key: Option<[u8; 32]>,
+    sending_chain: Option<[u8; 32]>,
+    receiving_chain: Option<[u8; 32]>,
+    sent: u32,
+    received: u32,
+    previous_sent: u32,
+    skipped: Vec<SkippedKey>,
+}
+
+impl Ratchet {
+    /// State of the initiator, who knows the initial ratchet key of the responder.
+    pub fn initiator(
+        shared: [u8; 32],
+        remote_ratchet_key: PublicKey,
+    ) -> Result<Self, EncryptionError> {
+        let ratchet_secret = super::random_secret()?;
+        let (root_key, sending_chain) = kdf_root(
+            &shared,
+            &diffie_hellman(&ratchet_secret, &remote_ratchet_key)?,
+        );
+        Ok(Self {
+            root_key,
+            ratchet_secret: Some(ratchet_secret.to_bytes()),
+            remote_ratchet_key: Some(remote_ratchet_key.to_bytes()),
+            sending_chain: Some(sending_chain),
+            receiving_chain: None,
+            sent: 0,
+            received: 0,
+            previous_sent: 0,
+            skipped: Vec::new(),
+        })
+    }
+
+    /// State of the responder, whose initial ratchet key is its identity key. The responder can
+    /// only send once it received the first message.
+    pub fn responder(shared: [u8; 32]) -> Self {
+        Self {
+            root_key: shared,
+            ratchet_secret: None,
+            remote_ratchet_key: None,
+            sending_chain: None,
+            receiving_chain: None,
+            sent: 0,
+            received: 0,
+            previous_sent: 0,
+            skipped: Vec::new(),
+        }
+    }
+
+    pub fn encrypt(
+        &mut self,
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<(Header, Vec<u8>), EncryptionError> {
+        let chain = self.sending_chain.ok_or(EncryptionError::InvalidKey)?;
+        let ratchet_secret = self.ratchet_secret.ok_or(EncryptionError::InvalidKey)?;
+        let (chain, message_key) = kdf_chain(&chain);
+        let header = Header {
+            ratchet_key: PublicKey::from(&StaticSecret::from(ratchet_secret)).to_bytes(),
+            previous_chain_length: self.previous_sent,
+            message_number: self.sent,
+        };
+        let ciphertext = seal(&message_key, plaintext, associated_data, &header)?;
+        self.sending_chain = Some(chain);
+        self.sent += 1;
+        Ok((header, ciphertext))
+    }
+
+    /// Decrypts a message, leaving the state untouched if it fails. `identity` is the local
+    /// identity key, the initial ratchet key of a responder.
+    pub fn decrypt(
+        &mut self,
+        header: &Header,
+        ciphertext: &[u8],
+        associated_data: &[u8],
+        identity: &StaticSecret,
+    ) -> Result<Vec<u8>, EncryptionError> {
+        if let Some(index) = self.skipped.iter().position(|skipped| {
+            skipped.ratchet_key == header.ratchet_key
+                && skipped.message_number == header.message_number
+        }) {
+            let plaintext = open(
+                &self.skipped[index].message_key,
+                ciphertext,
+                associated_data,
+                header,
+            )?;
+            self.skipped.remove(index);
+            return Ok(plaintext);
+        }
+
+        let mut next = self.clone();
+        if next.remote_ratchet_key != Some(header.ratchet_key) {
+            next.skip_message_keys(header.previous_chain_length)?;
+            next.ratchet_step(header.ratchet_key, identity)?;
+        }
+        next.skip_message_keys(header.message_number)?;
+        let chain = next.receiving_chain.ok_or(EncryptionError::Decryption)?;
+        let (chain, message_key) = kdf_chain(&chain);
+        let plaintext = open(&message_key, ciphertext, associated_data, header)?;
+        next.receiving_chain = Some(chain);
+        next.received += 1;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    fn skip_message_keys(&mut self, until: u32) -> Result<(), EncryptionError> {
+        let (Some(mut chain), Some(ratchet_key)) = (self.receiving_chain, self.remote_ratchet_key)
+        else {
+            return Ok(());
+        };
+        if until.saturating_sub(self.received) > MAX_SKIP {
+            return Err(EncryptionError::TooManySkipped);
+        }
+        while self.received < until {
+            let (next_chain, message_key) = kdf_chain(&chain);
+            self.skipped.push(SkippedKey {
+                ratchet_key,
+                message_number: self.received,
+                message_key,
+            });
+            chain = next_chain;
+            self.received += 1;
+        }
+        self.receiving_chain = Some(chain);
+        if self.skipped.len() > MAX_SKIPPED_KEYS {
+            let excess = self.skipped.len() - MAX_SKIPPED_KEYS;
+            self.skipped.drain(..excess);
+        }
+        Ok(())
+    }
+
+    fn ratchet_step(
+        &mut self,
+        remote_ratchet_key: [u8; 32],
+        identity: &StaticSecret,
+    ) -> Result<(), EncryptionError> {
+        let remote = PublicKey::from(remote_ratchet_key);
+        self.previous_sent = self.sent;
+        self.sent = 0;
+        self.received = 0;
+        self.remote_ratchet_key = Some(remote_ratchet_key);
+
+        let ratchet_secret = match self.ratchet_secret {
+            Some(ratchet_secret) => StaticSecret::from(ratchet_secret),
+            None => identity.clone(),
+        };
+        let (root_key, receiving_chain) =
+            kdf_root(&self.root_key, &diffie_hellman(&ratchet_secret, &remote)?);
+        let ratchet_secret = super::random_secret()?;
+        let (root_key, sending_chain) =
+            kdf_root(&root_key, &diffie_hellman(&ratchet_secret, &remote)?);
+
+        self.root_key = root_key;
+        self.ratchet_secret = Some(ratchet_secret.to_bytes());
+        self.receiving_chain = Some(receiving_chain);
+        self.sending_chain = Some(sending_chain);
+        Ok(())
+    }
+}
+
+/// Advances the root chain, returning the new root key and chain key.
+fn kdf_root(root_key: &[u8; 32], dh_output: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let mut output = [0u8; 64];
+    Hkdf::<Sha256>::new(Some(root_key), dh_output)
+        .expand(ROOT_INFO, &mut output)
+        .expect("64 bytes is a valid HKDF-SHA256 output length");
+    let (root_key, chain_key) = output.split_at(32);
+    (
+        root_key.try_into().expect("split at 32 bytes"),
+        chain_key.try_into().expect("split at 32 bytes"),
+    )
+}
+
+/// Advances a sending or receiving chain, returning the next chain key and the message key.
+fn kdf_chain(chain_key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    let derive = |constant: u8| -> [u8; 32] {
+        let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(chain_key)
+            .expect("HMAC accepts keys of any length");
+        mac.update(&[constant]);
+        mac.finalize().into_bytes().into()
+    };
+    (derive(0x02), derive(0x01))
+}
+
+fn message_cipher(message_key: &[u8; 32]) -> (ChaCha20Poly1305, [u8; 12]) {
+    let mut output = [0u8; 44];
+    Hkdf::<Sha256>::new(Some(&[0u8; 32]), message_key)
+        .expand(MESSAGE_INFO, &mut output)
+        .expect("44 bytes is a valid HKDF-SHA256 output length");
+    let cipher = ChaCha20Poly1305::new_from_slice(&output[..32]).expect("key is 32 bytes");
+    let nonce = output[32..].try_into().expect("nonce is 12 bytes");
+    (cipher, nonce)
+}
+
+fn seal(
+    message_key: &[u8; 32],
+    plaintext: &[u8],
+    associated_data: &[u8],
+    header: &Header,
+) -> Result<Vec<u8>, EncryptionError> {
+    let (cipher, nonce) = message_cipher(message_key);
+    let aad = [associated_data, &header.to_bytes()].concat();
+    cipher
+        .encrypt(
+            &nonce.into(),
+            Payload {
+                msg: plaintext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| EncryptionError::Decryption)
+}
+
+fn open(
+    message_key: &[u8; 32],
+    ciphertext: &[u8],
+    associated_data: &[u8],
+    header: &Header,
+) -> Result<Vec<u8>, EncryptionError> {
+    let (cipher, nonce) = message_cipher(message_key);
+    let aad = [associated_data, &header.to_bytes()].concat();
+    cipher
+        .decrypt(
+            &nonce.into(),
+            Payload {
+                msg: ciphertext,
+                aad: &aad,
+            },
+        )
+        .map_err(|_| EncryptionError::Decryption)
+}
@@ -1,4 +1,9 @@
 pub mod app;
+mod crypto;
 mod libp2p;
 
-pub use app::ChatApp;
+pub use app::{ChatApp, ChatAppBuilder};
+#[cfg(not(target_arch = "wasm32"))]
+pub use libp2p::transport::{generate_webrtc_certificate, native_transport, websocket_tls_config};
+#[cfg(not(target_arch = "wasm32"))]
+pub use libp2p_webrtc::tokio::Certificate as WebRtcCertificate;
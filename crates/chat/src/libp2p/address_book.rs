@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+
+use libp2p::{Multiaddr, PeerId, multiaddr::Protocol};
+use serde::{Deserialize, Serialize};
+
+use crate::app::{StorageError, messages::now_millis, peers::PeerStore};
+
+/// Maximum number of addresses kept per peer, the least reliable are dropped first.
+const MAX_ADDRESSES: usize = 8;
+
+/// Maximum number of peers kept, to make room the ones seen longest ago are dropped first while
+/// contacts are never dropped.
+const MAX_PEERS: usize = 1024;
+
+/// Addresses at which other peers were seen or reached, kept across restarts in a [`PeerStore`].
+#[derive(Default, Deserialize, Serialize)]
+pub(crate) struct AddressBook {
+    peers: HashMap<PeerId, KnownPeer>,
+    /// Whether anything changed since the book was last saved.
+    #[serde(skip)]
+    dirty: bool,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+struct KnownPeer {
+    addresses: Vec<KnownAddress>,
+    /// Whether the peer takes part in the Kademlia DHT.
+    kademlia: bool,
+    /// Whether a connection to the peer is kept at all times.
+    #[serde(default)]
+    contact: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+struct KnownAddress {
+    address: Multiaddr,
+    /// Milliseconds since the unix epoch at which the address was last learned of or dialed.
+    last_seen: u64,
+    successes: u32,
+    failures: u32,
+}
+
+impl KnownPeer {
+    fn last_seen(&self) -> u64 {
+        self.addresses
+            .iter()
+            .map(|known| known.last_seen)
+            .max()
+            .unwrap_or_default()
+    }
+
+    fn address(&mut self, address: Multiaddr) -> &mut KnownAddress {
+        let address = without_peer_id(address);
+        let index = match self
+            .addresses
+            .iter()
+            .position(|known| known.address == address)
+        {
+            Some(index) => index,
+            None => {
+                if self.addresses.len() >= MAX_ADDRESSES {
+                    self.addresses.sort_by_key(KnownAddress::rank);
+                    self.addresses.remove(0);
+                }
+                self.addresses.push(KnownAddress {
+                    address,
+                    last_seen: 0,
+                    successes: 0,
+                    failures: 0,
+                });
+                self.addresses.len() - 1
+            }
+        };
+        &mut self.addresses[index]
+    }
+}
+
+impl KnownAddress {
+    /// Orders addresses from least to most likely to be reachable.
+    fn rank(&self) -> (i64, u64) {
+        (
+            i64::from(self.successes) - i64::from(self.failures),
+            self.last_seen,
+        )
+    }
+}
+
+impl AddressBook {
+    pub fn load(store: &dyn PeerStore) -> Result<Self, StorageError> {
+        match store.load()? {
+            Some(bytes) => cbor4ii::serde::from_slice(&bytes)
+                .map_err(|err| StorageError::Serialization(err.to_string())),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// Saves the book if anything changed since it was loaded or last saved.
+    pub fn save(&mut self, store: &mut dyn PeerStore) -> Result<(), StorageError> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let bytes = cbor4ii::serde::to_vec(Vec::new(), self)
+            .map_err(|err| StorageError::Serialization(err.to_string()))?;
+        store.save(&bytes)?;
+        self.dirty = false;
+        Ok(())
+    }
+
+    /// Records that `peer_id` was learned of at `address`, e.g. through identify or mDNS.
+    pub fn observed(&mut self, peer_id: PeerId, address: Multiaddr) {
+        self.peer(peer_id).address(address).last_seen = now_millis();
+    }
+
+    /// Records a successful dial of `peer_id` at `address`.
+    pub fn succeeded(&mut self, peer_id: PeerId, address: Multiaddr) {
+        let known = self.peer(peer_id).address(address);
+        known.last_seen = now_millis();
+        known.successes = known.successes.saturating_add(1);
+    }
+
+    /// Records a failed dial of `peer_id` at `address`.
+    pub fn failed(&mut self, peer_id: PeerId, address: Multiaddr) {
+        let address = without_peer_id(address);
+        if let Some(peer) = self.peers.get_mut(&peer_id)
+            && let Some(known) = peer
+                .addresses
+                .iter_mut()
+                .find(|known| known.address == address)
+        {
+            known.failures = known.failures.saturating_add(1);
+            self.dirty = true;
+        }
+    }
+
+    /// Records that `peer_id` takes part in the Kademlia DHT.
+    pub fn kademlia_peer(&mut self, peer_id: PeerId) {
+        if let Some(peer) = self.peers.get_mut(&peer_id)
+            && !peer.kademlia
+        {
+            peer.kademlia = true;
+            self.dirty = true;
+        }
+    }
+
+    /// Adds `peer_id` to the contacts, or removes it from them.
+    pub fn set_contact(&mut self, peer_id: PeerId, contact: bool) {
+        self.peer(peer_id).contact = contact;
+    }
+
+    pub fn is_contact(&self, peer_id: PeerId) -> bool {
+        self.peers.get(&peer_id).is_some_and(|peer| peer.contact)
+    }
+
+    pub fn contacts(&self) -> impl Iterator<Item = PeerId> {
+        self.peers
+            .iter()
+            .filter(|(_, peer)| peer.contact)
+            .map(|(peer_id, _)| *peer_id)
+    }
+
+    /// The addresses of `peer_id`, the most likely to be reachable first.
+    pub fn addresses(&self, peer_id: PeerId) -> Vec<Multiaddr> {
+        let Some(peer) = self.peers.get(&peer_id) else {
+            return Vec::new();
+        };
+        let mut addresses: Vec<&KnownAddress> = peer.addresses.iter().collect();
+        addresses.sort_by_key(|known| std::cmp::Reverse(known.rank()));
+        addresses
+            .into_iter()
+            .map(|known| known.address.clone())
+            .collect()
+    }
+
+    /// Every peer taking part in the Kademlia DHT, with its addresses.
+    pub fn kademlia_peers(&self) -> impl Iterator<Item = (PeerId, Vec<Multiaddr>)> {
+        self.peers
+            .iter()
+            .filter(|(_, peer)| peer.kademlia)
+            .map(|(peer_id, _)| (*peer_id, self.addresses(*peer_id)))
+    }
+
+    fn peer(&mut self, peer_id: PeerId) -> &mut KnownPeer {
+        self.dirty = true;
+        if !self.peers.contains_key(&peer_id)
+            && self.peers.len() >= MAX_PEERS
+            && let Some(oldest) = self
+                .peers
+                .iter()
+                .filter(|(_, peer)| !peer.contact)
+                .min_by_key(|(_, peer)| peer.last_seen())
+                .map(|(peer_id, _)| *peer_id)
+        {
+            self.peers.remove(&oldest);
+        }
+        self.peers.entry(peer_id).or_default()
+    }
+}
+
+/// Strips the trailing `/p2p` of an address, which the swarm appends when dialing.
+fn without_peer_id(mut address: Multiaddr) -> Multiaddr {
+    if let Some(Protocol::P2p(_)) = address.iter().last() {
+        address.pop();
+    }
+    address
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::identity::Keypair;
+
+    use super::*;
+    use crate::app::peers::MemoryPeerStore;
+
+    fn peer_id() -> PeerId {
+        Keypair::generate_ed25519().public().to_peer_id()
+    }
+
+    fn address(i: usize) -> Multiaddr {
+        format!("/ip4/10.0.{}.{}/tcp/4001", i / 256, i % 256)
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn addresses_are_ranked_by_dial_outcomes() {
+        let mut book = AddressBook::default();
+        let peer_id = peer_id();
+        for i in 0..4 {
+            book.observed(peer_id, address(i));
+        }
+        book.succeeded(peer_id, address(1));
+        book.succeeded(peer_id, address(1));
+        book.failed(peer_id, address(2));
+        book.succeeded(peer_id, address(3));
+        book.failed(peer_id, address(3));
+        // the peer id appended when dialing is stripped
+        book.succeeded(peer_id, address(0).with(Protocol::P2p(peer_id)));
+
+        assert_eq!(
+            book.addresses(peer_id),
+            [address(1), address(0), address(3), address(2)]
+        );
+    }
+
+    #[test]
+    fn ties_are_broken_by_last_seen() {
+        let mut book = AddressBook::default();
+        let peer_id = peer_id();
+        book.observed(peer_id, address(0));
+        book.observed(peer_id, address(1));
+        book.peer(peer_id).address(address(0)).last_seen = 2;
+        book.peer(peer_id).address(address(1)).last_seen = 1;
+
+        assert_eq!(book.addresses(peer_id), [address(0), address(1)]);
+    }
+
+    #[test]
+    fn least_reliable_address_is_dropped_at_capacity() {
+        let mut book = AddressBook::default();
+        let peer_id = peer_id();
+        for i in 0..MAX_ADDRESSES {
+            book.succeeded(peer_id, address(i));
+        }
+        book.failed(peer_id, address(3));
+
+        book.observed(peer_id, address(MAX_ADDRESSES));
+        let addresses = book.addresses(peer_id);
+        assert_eq!(addresses.len(), MAX_ADDRESSES);
+        assert!(!addresses.contains(&address(3)));
+        assert!(addresses.contains(&address(MAX_ADDRESSES)));
+
+        // a failed dial of an unknown address is not recorded
+        book.failed(peer_id, address(3));
+        assert!(!book.addresses(peer_id).contains(&address(3)));
+    }
+
+    #[test]
+    fn peer_seen_longest_ago_is_dropped_at_capacity() {
+        let mut book = AddressBook::default();
+        let peer_ids: Vec<PeerId> = (0..MAX_PEERS).map(|_| peer_id()).collect();
+        for (i, peer_id) in peer_ids.iter().enumerate() {
+            book.observed(*peer_id, address(i));
+            book.peer(*peer_id).address(address(i)).last_seen = i as u64 + 1;
+        }
+        // the contact seen longest ago is kept
+        book.set_contact(peer_ids[0], true);
+
+        let newcomer = peer_id();
+        book.observed(newcomer, address(0));
+        assert_eq!(book.peers.len(), MAX_PEERS);
+        assert!(book.is_contact(peer_ids[0]));
+        assert!(book.addresses(peer_ids[1]).is_empty());
+        assert!(!book.addresses(peer_ids[2]).is_empty());
+        assert_eq!(book.addresses(newcomer), [address(0)]);
+
+        // known peers make no room
+        book.observed(peer_ids[2], address(MAX_PEERS));
+        assert_eq!(book.peers.len(), MAX_PEERS);
+        assert!(!book.addresses(peer_ids[3]).is_empty());
+    }
+
+    #[test]
+    fn book_round_trips_through_the_store() {
+        let mut store = MemoryPeerStore::default();
+        let mut book = AddressBook::default();
+        let (contact, kademlia_peer) = (peer_id(), peer_id());
+        book.observed(contact, address(0));
+        book.succeeded(contact, address(1));
+        book.set_contact(contact, true);
+        book.observed(kademlia_peer, address(2));
+        book.kademlia_peer(kademlia_peer);
+        book.save(&mut store).unwrap();
+
+        let loaded = AddressBook::load(&store).unwrap();
+        assert_eq!(loaded.contacts().collect::<Vec<_>>(), [contact]);
+        assert_eq!(loaded.addresses(contact), [address(1), address(0)]);
+        assert_eq!(
+            loaded.kademlia_peers().collect::<Vec<_>>(),
+            [(kademlia_peer, vec![address(2)])]
+        );
+        assert_eq!(
+            loaded.peers[&contact].addresses[0].last_seen,
+            book.peers[&contact].addresses[0].last_seen
+        );
+    }
+
+    #[test]
+    fn unchanged_book_is_not_saved_again() {
+        let mut store = MemoryPeerStore::default();
+        let mut book = AddressBook::load(&store).unwrap();
+        book.save(&mut store).unwrap();
+        assert_eq!(store.load().unwrap(), None);
+
+        book.observed(peer_id(), address(0));
+        book.save(&mut store).unwrap();
+        assert!(store.load().unwrap().is_some());
+
+        let mut other_store = MemoryPeerStore::default();
+        book.save(&mut other_store).unwrap();
+        AddressBook::load(&store)
+            .unwrap()
+            .save(&mut other_store)
+            .unwrap();
+        assert_eq!(other_store.load().unwrap(), None);
+    }
+}
@@ -3,7 +3,7 @@
     core::{Endpoint, transport::PortUse},
     dcutr, gossipsub, identify,
     identity::Keypair,
-    kad, relay, request_response,
+    kad, multiaddr, ping, relay, rendezvous, request_response,
     swarm::{
         ConnectionDenied, ConnectionId, FromSwarm, NetworkBehaviour, THandler, THandlerInEvent,
         THandlerOutEvent, ToSwarm, behaviour::toggle::Toggle, dummy,
@@ -13,26 +13,42 @@
 use libp2p::{mdns, upnp};
 use serde::{Deserialize, Serialize};
 
+use crate::{
+    app::{ChatAppError, CommandError, messages::MessageId},
+    crypto,
+};
+
+use super::config::NetworkConfig;
+
 use std::{
     collections::VecDeque,
-    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
+use futures::StreamExt;
+use futures_channel::{mpsc, oneshot};
+
+/// Number of dispatched commands buffered before senders have to wait for the swarm.
+const COMMAND_BUFFER: usize = 64;
+
 #[derive(NetworkBehaviour)]
 pub struct ChatBehaviour {
-    // this req/rep model isn't going to work and will be replaced by either pubsub or a custom protocol
     pub request_response: request_response::cbor::Behaviour<ChatSendMessage, ChatMessageReceived>,
     relay_client: Toggle<relay::client::Behaviour>,
     pub kad: Toggle<kad::Behaviour<kad::store::MemoryStore>>,
+    /// Client of the public IPFS DHT, only there to look up peers while `kad` runs another DHT.
+    pub public_kad: Toggle<kad::Behaviour<kad::store::MemoryStore>>,
     pub gossipsub: gossipsub::Behaviour,
     identify: identify::Behaviour,
-    dcutr: dcutr::Behaviour,
+    ping: ping::Behaviour,
+    dcutr: Toggle<dcutr::Behaviour>,
     autonat: autonat::Behaviour,
+    pub rendezvous: Toggle<rendezvous::client::Behaviour>,
     #[cfg(not(target_arch = "wasm32"))]
-    upnp: upnp::tokio::Behaviour,
+    upnp: Toggle<upnp::tokio::Behaviour>,
     #[cfg(not(target_arch = "wasm32"))]
-    mdns: mdns::tokio::Behaviour,
+    mdns: Toggle<mdns::tokio::Behaviour>,
+    pub keep_alive: super::keep_alive::Behaviour,
     pub inner: InnerChatBehavior,
 }
 
@@ -40,8 +56,10 @@ impl ChatBehaviour {
     pub fn new(
         keypair: &Keypair,
         relay_client: Option<relay::client::Behaviour>,
+        config: &NetworkConfig,
     ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
         let local_peer_id = keypair.public().to_peer_id();
+        let (command_sender, commands) = mpsc::channel(COMMAND_BUFFER);
 
         let protocols = [(
             super::CHAT_PROTOCOL,
@@ -51,47 +69,97 @@ pub fn new(
         let request_response = request_response::cbor::Behaviour::<
             ChatSendMessage,
             ChatMessageReceived,
-        >::new(protocols, request_response::Config::default());
+        >::new(protocols, config.request_response.clone());
 
-        let kad = kad::Behaviour::new(local_peer_id, kad::store::MemoryStore::new(local_peer_id));
+        let kad = config.kademlia.then(|| {
+            kad::Behaviour::with_config(
+                local_peer_id,
+                kad::store::MemoryStore::new(local_peer_id),
+                config.kademlia_config.clone(),
+            )
+        });
 
-        let mut gossipsub = gossipsub::Behaviour::new(
+        let public_kad = (!config.public_dht_peers.is_empty()
+            && kad
+                .as_ref()
+                .is_some_and(|kad| !kad.protocol_names().contains(&kad::PROTOCOL_NAME)))
+        .then(|| {
+            let mut public_kad = kad::Behaviour::with_config(
+                local_peer_id,
+                kad::store::MemoryStore::new(local_peer_id),
+                kad::Config::new(kad::PROTOCOL_NAME),
+            );
+            // never answer queries of the public DHT
+            public_kad.set_mode(Some(kad::Mode::Client));
+            for addr in &config.public_dht_peers {
+                match addr.iter().last() {
+                    Some(multiaddr::Protocol::P2p(peer_id)) => {
+                        public_kad.add_address(&peer_id, addr.clone());
+                    }
+                    _ => tracing::error!("Invalid public DHT address: {addr}"),
+                }
+            }
+            // fails only without any valid address, which was logged above
+            let _ = public_kad.bootstrap();
+            public_kad
+        });
+
+        let gossipsub = gossipsub::Behaviour::new(
             gossipsub::MessageAuthenticity::Signed(keypair.clone()),
-            gossipsub::Config::default(),
+            config.gossipsub.clone(),
         )?;
 
-        let streuen_topic = gossipsub::IdentTopic::new("streuen-topic-123");
-
-        let _ = gossipsub.subscribe(&streuen_topic)?;
-
         let identify = identify::Behaviour::new(identify::Config::new(
             "streuen/chat/0.1.0".to_string(),
             keypair.public(),
         ));
 
-        let dcutr = dcutr::Behaviour::new(local_peer_id.clone());
+        let ping = ping::Behaviour::new(config.ping.clone());
+
+        // hole punching upgrades relayed connections, so it is only useful along with the relay
+        let dcutr = relay_client
+            .is_some()
+            .then(|| dcutr::Behaviour::new(local_peer_id));
 
         let autonat = autonat::Behaviour::new(local_peer_id, autonat::Config::default());
 
+        let rendezvous = config
+            .rendezvous
+            .then(|| rendezvous::client::Behaviour::new(keypair.clone()));
+
         #[cfg(not(target_arch = "wasm32"))]
-        let upnp = upnp::tokio::Behaviour::default();
+        let upnp = config.upnp.then(upnp::tokio::Behaviour::default);
         #[cfg(not(target_arch = "wasm32"))]
-        let mdns = mdns::tokio::Behaviour::new(Default::default(), local_peer_id)?;
+        let mdns = if config.mdns {
+            Some(mdns::tokio::Behaviour::new(
+                Default::default(),
+                local_peer_id,
+            )?)
+        } else {
+            None
+        };
 
         Ok(Self {
             request_response,
             relay_client: relay_client.into(),
-            kad: Some(kad).into(),
+            kad: kad.into(),
+            public_kad: public_kad.into(),
             gossipsub,
             identify,
-            dcutr,
+            ping,
+            dcutr: dcutr.into(),
             autonat,
+            rendezvous: rendezvous.into(),
             #[cfg(not(target_arch = "wasm32"))]
-            upnp,
+            upnp: upnp.into(),
             #[cfg(not(target_arch = "wasm32"))]
-            mdns,
+            mdns: mdns.into(),
+            keep_alive: Default::default(),
             inner: InnerChatBehavior {
-                queue: Arc::new(Mutex::new(VecDeque::new())),
+                commands,
+                command_sender,
+                queued: VecDeque::new(),
+                local_peer_id,
             },
         })
     }
@@ -102,23 +170,189 @@ pub enum ToChat {
     ListenOn(Multiaddr),
     AddBoostrapPeer(Multiaddr),
     Connect(PeerId),
+    /// Connects to the peer at the address, which has to end with its `/p2p` peer id. The address
+    /// is remembered for later connects.
+    Dial(Multiaddr),
+    /// Keeps a connection to the peer, reconnecting whenever it drops.
+    AddContact(PeerId),
+    RemoveContact(PeerId),
     SendMessage(PeerId, String),
+    JoinRoom(String),
+    LeaveRoom(String),
+    SendRoomMessage(String, String),
+    /// Registers at the rendezvous point at the address, which has to end with its `/p2p` peer
+    /// id, in every namespace registered in.
+    AddRendezvousPoint(Multiaddr),
+    /// Registers in the team namespace at every rendezvous point, discovering the other peers
+    /// registered in it.
+    RegisterNamespace(String),
+    UnregisterNamespace(String),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Debug)]
 pub enum ChatToSwarm {
-    ListenOn(Multiaddr),
-    AddBoostrapPeer(Multiaddr),
+    ListenOn(Multiaddr, Responder),
+    AddBoostrapPeer(Multiaddr, Responder),
+    Connect(PeerId, Responder),
+    Dial(Multiaddr, Responder),
+    AddContact(PeerId, Responder),
+    RemoveContact(PeerId, Responder),
+    SendMessage(PeerId, OutgoingMessage, Responder),
+    JoinRoom(String, Responder),
+    LeaveRoom(String, Responder),
+    SendRoomMessage(String, ChatRoomMessage, Responder),
+    AddRendezvousPoint(Multiaddr, Responder),
+    RegisterNamespace(String, Responder),
+    UnregisterNamespace(String, Responder),
+    /// The command failed before reaching the swarm.
+    Failed(CommandError, Responder),
 }
 
-#[derive(Clone)]
+/// The gossipsub topic carrying the messages of a room.
+pub fn room_topic(room_id: &str) -> gossipsub::IdentTopic {
+    gossipsub::IdentTopic::new(format!("/streuen/chat/room/{room_id}"))
+}
+
+/// A dispatched command along with whoever waits for its outcome.
+pub struct Command {
+    request: ToChat,
+    responder: Responder,
+}
+
+/// Reports the outcome of a command to the caller of [`ChatCommands::execute`].
+pub struct Responder(Option<oneshot::Sender<Result<(), CommandError>>>);
+
+impl Responder {
+    /// Reports `result`, handing back the error if nobody waits for it.
+    pub fn respond(self, result: Result<(), CommandError>) -> Option<CommandError> {
+        let Some(sender) = self.0 else {
+            return result.err();
+        };
+        sender.send(result).err().and_then(Result::err)
+    }
+
+    /// A responder whose result is received through the returned receiver.
+    #[cfg(test)]
+    pub fn channel() -> (Self, oneshot::Receiver<Result<(), CommandError>>) {
+        let (sender, receiver) = oneshot::channel();
+        (Self(Some(sender)), receiver)
+    }
+}
+
+impl std::fmt::Debug for Responder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("Responder").field(&self.0.is_some()).finish()
+    }
+}
+
+/// Turns the commands dispatched by the app into swarm events.
 pub struct InnerChatBehavior {
-    queue: Arc<Mutex<VecDeque<ToChat>>>,
+    commands: mpsc::Receiver<Command>,
+    /// Kept to hand out further senders, the channel stays open as long as the behaviour lives.
+    command_sender: mpsc::Sender<Command>,
+    /// Commands queued before the swarm runs, which bypass the bounded channel.
+    queued: VecDeque<Command>,
+    local_peer_id: PeerId,
 }
 
 impl InnerChatBehavior {
-    pub fn send(&mut self, event: ToChat) {
-        self.queue.lock().unwrap().push_back(event);
+    pub fn commands(&self) -> ChatCommands {
+        ChatCommands(self.command_sender.clone())
+    }
+
+    /// Queues a command without waiting for room in the channel.
+    pub fn queue(&mut self, request: ToChat) {
+        self.queued.push_back(Command {
+            request,
+            responder: Responder(None),
+        });
+    }
+
+    fn handle_command(&self, command: Command) -> ToSwarm<ChatToSwarm, THandlerInEvent<Self>> {
+        let Command { request, responder } = command;
+        ToSwarm::GenerateEvent(match request {
+            ToChat::ListenOn(addr) => ChatToSwarm::ListenOn(addr, responder),
+            ToChat::AddBoostrapPeer(addr) => ChatToSwarm::AddBoostrapPeer(addr, responder),
+            ToChat::Connect(peer_id) => ChatToSwarm::Connect(peer_id, responder),
+            ToChat::Dial(addr) => ChatToSwarm::Dial(addr, responder),
+            ToChat::AddContact(peer_id) => ChatToSwarm::AddContact(peer_id, responder),
+            ToChat::RemoveContact(peer_id) => ChatToSwarm::RemoveContact(peer_id, responder),
+            ToChat::SendMessage(peer_id, message) => match MessageId::generate() {
+                Ok(message_id) => {
+                    let outgoing = OutgoingMessage {
+                        message_id,
+                        sender: self.local_peer_id,
+                        message,
+                    };
+                    ChatToSwarm::SendMessage(peer_id, outgoing, responder)
+                }
+                Err(err) => ChatToSwarm::Failed(CommandError::MessageId(err), responder),
+            },
+            ToChat::JoinRoom(room_id) => ChatToSwarm::JoinRoom(room_id, responder),
+            ToChat::LeaveRoom(room_id) => ChatToSwarm::LeaveRoom(room_id, responder),
+            ToChat::SendRoomMessage(room_id, message) => match MessageId::generate() {
+                Ok(message_id) => {
+                    let room_message = ChatRoomMessage {
+                        message_id,
+                        message,
+                    };
+                    ChatToSwarm::SendRoomMessage(room_id, room_message, responder)
+                }
+                Err(err) => ChatToSwarm::Failed(CommandError::MessageId(err), responder),
+            },
+            ToChat::AddRendezvousPoint(addr) => ChatToSwarm::AddRendezvousPoint(addr, responder),
+            ToChat::RegisterNamespace(namespace) => {
+                ChatToSwarm::RegisterNamespace(namespace, responder)
+            }
+            ToChat::UnregisterNamespace(namespace) => {
+                ChatToSwarm::UnregisterNamespace(namespace, responder)
+            }
+        })
+    }
+}
+
+/// Dispatches commands to the swarm, waking its task as soon as a command is sent.
+///
+/// Every handle has its own slot in the channel, so callers should keep cloned handles around
+/// instead of cloning one per command.
+#[derive(Clone)]
+pub struct ChatCommands(mpsc::Sender<Command>);
+
+impl ChatCommands {
+    /// Sends a command without waiting for its outcome, failing if the channel is full or the
+    /// swarm stopped. Failures of the command itself are reported as [`crate::app::ToApp::Error`].
+    pub fn dispatch(&mut self, request: ToChat) -> Result<(), ChatAppError> {
+        let command = Command {
+            request,
+            responder: Responder(None),
+        };
+        Ok(self
+            .0
+            .try_send(command)
+            .map_err(|err| err.into_send_error())?)
+    }
+
+    /// Sends a command and waits until the swarm carried it out.
+    ///
+    /// Listening and joining or leaving a room complete right away, a bootstrap once the query
+    /// started, a connect once the connection is established and a direct message once the
+    /// recipient acknowledged it.
+    pub async fn execute(&mut self, request: ToChat) -> Result<(), ChatAppError> {
+        use futures::SinkExt;
+
+        let (sender, receiver) = oneshot::channel();
+        let command = Command {
+            request,
+            responder: Responder(Some(sender)),
+        };
+        self.0.send(command).await?;
+        Ok(receiver.await.map_err(|_| ChatAppError::SwarmStopped)??)
+    }
+}
+
+impl PartialEq for ChatCommands {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.same_receiver(&other.0)
     }
 }
 
@@ -157,48 +391,47 @@ fn on_connection_handler_event(
     ) {
     }
 
+    /// Hands out one command per call, the swarm keeps polling until every buffered command is
+    /// handled.
     fn poll(
         &mut self,
-        _cx: &mut Context<'_>,
+        cx: &mut Context<'_>,
     ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
-        let mut queue = self.queue.lock().unwrap();
-        if let Some(event) = queue.pop_front() {
-            match event {
-                ToChat::ListenOn(addr) => {
-                    return Poll::Ready(ToSwarm::GenerateEvent(ChatToSwarm::ListenOn(addr)));
-                }
-                ToChat::AddBoostrapPeer(addr) => {
-                    return Poll::Ready(ToSwarm::GenerateEvent(ChatToSwarm::AddBoostrapPeer(addr)));
-                }
-                ToChat::Connect(peer_id) => {
-                    return Poll::Ready(ToSwarm::Dial {
-                        opts: peer_id.into(),
-                    });
-                }
-                ToChat::SendMessage(peer_id, message) => {
-                    // let request = behaviour::ChatSendMessage {
-                    //     message_id: 1,
-                    //     message,
-                    // };
-                    // swarm
-                    //     .behaviour_mut()
-                    //     .request_response
-                    //     .send_request(&peer_id, request);
-                    tracing::debug!("Sending message to peer [{peer_id}]: {message}")
-                }
-            }
+        if let Some(command) = self.queued.pop_front() {
+            return Poll::Ready(self.handle_command(command));
+        }
+        match self.commands.poll_next_unpin(cx) {
+            Poll::Ready(Some(command)) => Poll::Ready(self.handle_command(command)),
+            // the sender kept by the behaviour means the channel never closes
+            Poll::Ready(None) | Poll::Pending => Poll::Pending,
         }
-        Poll::Pending
     }
 }
 
+/// A direct message before it is encrypted for the recipient.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutgoingMessage {
+    pub message_id: MessageId,
+    pub sender: PeerId,
+    pub message: String,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ChatSendMessage {
-    pub message_id: u8,
-    pub message: String,
+    pub message_id: MessageId,
+    pub sender: PeerId,
+    /// The UTF-8 message text, encrypted end to end.
+    pub payload: crypto::EncryptedMessage,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
 pub struct ChatMessageReceived {
-    message_id: u8,
+    pub message_id: MessageId,
+}
+
+/// Payload of a gossipsub message published to a room, the sender is the signed message source.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ChatRoomMessage {
+    pub message_id: MessageId,
+    pub message: String,
 }
@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use libp2p::{Multiaddr, StreamProtocol, gossipsub, kad, ping, request_response};
+
+/// Time after which a direct message without acknowledgement counts as failed.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time a connection without any open streams is kept alive.
+const IDLE_CONNECTION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Protocol name of the Kademlia DHT made up of streuen nodes only, kept apart from the public IPFS
+/// DHT running under [`kad::PROTOCOL_NAME`].
+pub const STREUEN_KAD_PROTOCOL: StreamProtocol = StreamProtocol::new("/streuen/kad/1.0.0");
+
+/// Everything about the swarm a [`crate::app::ChatAppBuilder`] can change, which can also be
+/// handed to [`crate::app::ChatApp::restart`].
+#[derive(Clone)]
+pub struct NetworkConfig {
+    pub listen_addresses: Vec<Multiaddr>,
+    /// Addresses we are known to be reachable at, e.g. through a forwarded port, besides those
+    /// confirmed by autonat. Registrations at rendezvous points need at least one of them.
+    pub external_addresses: Vec<Multiaddr>,
+    pub bootstrap_peers: Vec<Multiaddr>,
+    /// Only available on native targets, browsers cannot use multicast.
+    pub mdns: bool,
+    /// Only available on native targets, browsers cannot map ports.
+    pub upnp: bool,
+    pub kademlia: bool,
+    pub relay: bool,
+    pub rendezvous: bool,
+    /// Rendezvous points to register at, each address has to end with its `/p2p` peer id.
+    pub rendezvous_points: Vec<Multiaddr>,
+    /// Team namespaces to register in at every rendezvous point, discovering the other peers
+    /// registered there.
+    pub rendezvous_namespaces: Vec<String>,
+    pub idle_connection_timeout: Duration,
+    pub gossipsub: gossipsub::Config,
+    /// Decides which DHT is joined through its protocol name, the public IPFS DHT by default.
+    pub kademlia_config: kad::Config,
+    /// Bootstrap peers of the public IPFS DHT, in which peers are looked up as well while
+    /// `kademlia_config` names another protocol. Records are only stored in our own DHT.
+    pub public_dht_peers: Vec<Multiaddr>,
+    pub request_response: request_response::Config,
+    /// Interval and timeout of the pings measuring the latency to every connected peer.
+    pub ping: ping::Config,
+    /// Restricts TCP connections to the peers knowing the key, QUIC is not available then.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub pre_shared_key: Option<libp2p::pnet::PreSharedKey>,
+    /// Certificate of the `/webrtc-direct` listeners, whose hash is part of their addresses. A new
+    /// one is generated on every start if unset, changing the addresses along with it.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub webrtc_certificate: Option<libp2p_webrtc::tokio::Certificate>,
+    /// Certificate chain and key served on `/tls/ws` listeners, browsers only dial those if the
+    /// certificate is trusted for the host name of the address.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub websocket_tls: Option<libp2p::websocket::tls::Config>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            listen_addresses: Vec::new(),
+            external_addresses: Vec::new(),
+            bootstrap_peers: Vec::new(),
+            mdns: true,
+            upnp: true,
+            kademlia: true,
+            relay: true,
+            rendezvous: true,
+            rendezvous_points: Vec::new(),
+            rendezvous_namespaces: Vec::new(),
+            idle_connection_timeout: IDLE_CONNECTION_TIMEOUT,
+            gossipsub: gossipsub::Config::default(),
+            kademlia_config: kad::Config::new(kad::PROTOCOL_NAME),
+            public_dht_peers: Vec::new(),
+            request_response: request_response::Config::default()
+                .with_request_timeout(DELIVERY_TIMEOUT),
+            ping: ping::Config::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            pre_shared_key: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            webrtc_certificate: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            websocket_tls: None,
+        }
+    }
+}
@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+
+use futures::channel::mpsc;
+use libp2p::{
+    Multiaddr, PeerId, kad,
+    multiaddr::Protocol,
+    swarm::dial_opts::{DialOpts, PeerCondition},
+};
+
+use super::{OutboundMessage, SwarmTask, behaviour, respond, send_to_app};
+use crate::app::{self, ConnectStep};
+
+/// Maximum number of relays remembered to reach peers through.
+const MAX_RELAYS: usize = 8;
+
+/// The DHT a lookup runs in.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum Dht {
+    /// The DHT joined through the Kademlia config, which may be the public one.
+    Own,
+    /// The public IPFS DHT, next to a private one.
+    Public,
+}
+
+/// A peer being reached on behalf of connect commands and direct messages.
+pub(super) struct PendingConnect {
+    step: ConnectStep,
+    /// Whether the DHT was already asked for the peer's addresses.
+    looked_up: bool,
+    responders: Vec<behaviour::Responder>,
+    /// Direct messages sent once the connection is established.
+    messages: Vec<(behaviour::ChatSendMessage, OutboundMessage)>,
+}
+
+impl PendingConnect {
+    /// Fails everything that waited for the connect to `peer_id`.
+    fn fail(self, peer_id: PeerId, app_sender: &mpsc::UnboundedSender<app::ToApp>) {
+        for responder in self.responders {
+            respond(
+                app_sender,
+                responder,
+                Err(app::CommandError::Unreachable(peer_id)),
+            );
+        }
+        for (_, message) in self.messages {
+            send_to_app(
+                app_sender,
+                app::ToApp::MessageStatusChanged {
+                    message_id: message.message_id,
+                    to: peer_id,
+                    status: app::messages::MessageStatus::Failed,
+                },
+            );
+            respond(
+                app_sender,
+                message.responder,
+                Err(app::CommandError::Unreachable(peer_id)),
+            );
+        }
+    }
+}
+
+/// What a connect goes on with after its current step failed.
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum Fallback {
+    Step(ConnectStep),
+    GiveUp,
+}
+
+/// The peers being reached and the step each connect is at.
+#[derive(Default)]
+pub(super) struct Connects {
+    pending: HashMap<PeerId, PendingConnect>,
+}
+
+impl Connects {
+    pub fn contains(&self, peer_id: PeerId) -> bool {
+        self.pending.contains_key(&peer_id)
+    }
+
+    pub fn peers(&self) -> Vec<PeerId> {
+        self.pending.keys().copied().collect()
+    }
+
+    /// Adds a waiter to the connect to `peer_id`, returning whether it started the connect, which
+    /// then has to enter [`ConnectStep::Dialing`].
+    pub fn wait_for(&mut self, peer_id: PeerId, add: impl FnOnce(&mut PendingConnect)) -> bool {
+        let started = !self.pending.contains_key(&peer_id);
+        add(self
+            .pending
+            .entry(peer_id)
+            .or_insert_with(|| PendingConnect {
+                step: ConnectStep::Dialing,
+                looked_up: false,
+                responders: Vec::new(),
+                messages: Vec::new(),
+            }));
+        started
+    }
+
+    /// Moves the connect to `peer_id` on to `step`, returning whether there is one.
+    pub fn enter(&mut self, peer_id: PeerId, step: ConnectStep) -> bool {
+        let Some(connect) = self.pending.get_mut(&peer_id) else {
+            return false;
+        };
+        connect.step = step;
+        connect.looked_up |= step == ConnectStep::LookingUp;
+        true
+    }
+
+    /// The step following the failed current one of the connect to `peer_id`. The DHT is only
+    /// looked up once, so dialing the addresses it found falls back to the relays.
+    pub fn fallback(&self, peer_id: PeerId) -> Option<Fallback> {
+        let connect = self.pending.get(&peer_id)?;
+        Some(match connect.step {
+            ConnectStep::Dialing if !connect.looked_up => Fallback::Step(ConnectStep::LookingUp),
+            ConnectStep::Dialing | ConnectStep::LookingUp => Fallback::Step(ConnectStep::Relaying),
+            ConnectStep::Relaying => Fallback::GiveUp,
+        })
+    }
+
+    /// Ends the connect to `peer_id`, once it succeeded or was given up.
+    pub fn remove(&mut self, peer_id: PeerId) -> Option<PendingConnect> {
+        self.pending.remove(&peer_id)
+    }
+}
+
+impl SwarmTask {
+    /// Reaches `peer_id` by dialing the addresses known of it, then those found in the DHT and
+    /// last through the known relays.
+    pub(super) fn connect(&mut self, peer_id: PeerId, responder: behaviour::Responder) {
+        if self.swarm.is_connected(&peer_id) {
+            respond(&self.app_sender, responder, Ok(()));
+        } else {
+            self.wait_for(peer_id, |connect| connect.responders.push(responder));
+        }
+    }
+
+    /// Sends a direct message once its recipient was reached.
+    pub(super) fn send_when_connected(
+        &mut self,
+        request: behaviour::ChatSendMessage,
+        message: OutboundMessage,
+    ) {
+        if self.swarm.is_connected(&message.peer_id) {
+            self.send_request(request, message);
+        } else {
+            self.wait_for(message.peer_id, |connect| {
+                connect.messages.push((request, message))
+            });
+        }
+    }
+
+    /// Makes another attempt to reach a contact, directly if it is only connected through a relay.
+    pub(super) fn reconnect(&mut self, peer_id: PeerId) {
+        if !self.address_book.is_contact(peer_id) {
+            self.reconnects.cancel(peer_id);
+        } else if self.connects.contains(peer_id) {
+            // the attempt in progress goes on
+        } else if self.swarm.is_connected(&peer_id) {
+            let addresses = self
+                .address_book
+                .addresses(peer_id)
+                .into_iter()
+                .filter(|addr| !addr.iter().any(|protocol| protocol == Protocol::P2pCircuit))
+                .collect::<Vec<_>>();
+            if !addresses.is_empty() {
+                tracing::debug!("Dialing relayed contact [{peer_id}] directly");
+                self.dial(peer_id, addresses, PeerCondition::Always);
+            }
+        } else {
+            tracing::debug!("Reconnecting to contact [{peer_id}]");
+            self.wait_for(peer_id, |_| {});
+        }
+    }
+
+    /// Reaches `peer_id` again after its connections were closed for not answering pings.
+    pub(super) fn redial(&mut self, peer_id: PeerId) {
+        if !self.connects.contains(peer_id) && !self.swarm.is_connected(&peer_id) {
+            tracing::debug!("Redialing peer [{peer_id}]");
+            self.wait_for(peer_id, |_| {});
+        }
+    }
+
+    /// Completes the connect to `peer_id`, if any, once a connection was established.
+    pub(super) fn connected(&mut self, peer_id: PeerId) {
+        let Some(connect) = self.connects.remove(peer_id) else {
+            return;
+        };
+        for responder in connect.responders {
+            respond(&self.app_sender, responder, Ok(()));
+        }
+        for (request, message) in connect.messages {
+            self.send_request(request, message);
+        }
+    }
+
+    /// Moves on to the next step after a dial started by a connect failed.
+    pub(super) fn dial_failed(&mut self, peer_id: PeerId) {
+        match self.connects.fallback(peer_id) {
+            Some(Fallback::Step(step)) => self.enter_step(peer_id, step, Vec::new()),
+            Some(Fallback::GiveUp) => self.connect_failed(peer_id),
+            None => {}
+        }
+    }
+
+    /// Dials the addresses a lookup found, or relays the connect once the lookups in every DHT
+    /// ended without finding any.
+    pub(super) fn lookup_progressed(
+        &mut self,
+        dht: Dht,
+        query_id: kad::QueryId,
+        peers: Vec<kad::PeerInfo>,
+        last: bool,
+    ) {
+        let Some(&peer_id) = self.lookups.get(&(dht, query_id)) else {
+            return;
+        };
+        let addresses = peers
+            .into_iter()
+            .find(|info| info.peer_id == peer_id)
+            .map(|info| info.addrs)
+            .unwrap_or_default();
+        if addresses.is_empty() && !last {
+            return;
+        }
+        self.lookups.remove(&(dht, query_id));
+        if !addresses.is_empty() {
+            let mut unfinished: Vec<(Dht, kad::QueryId)> = self
+                .lookups
+                .iter()
+                .filter(|(_, lookup)| **lookup == peer_id)
+                .map(|(key, _)| *key)
+                .collect();
+            if !last {
+                unfinished.push((dht, query_id));
+            }
+            for key in unfinished {
+                self.lookups.remove(&key);
+                self.finish_lookup(key);
+            }
+        } else if self.lookups.values().any(|lookup| *lookup == peer_id) {
+            // the lookup in the other DHT may still find the peer
+            return;
+        }
+        if !self.connects.contains(peer_id) {
+            return;
+        }
+        if addresses.is_empty() {
+            tracing::debug!("Peer [{peer_id}] not found in the DHT");
+            self.enter_step(peer_id, ConnectStep::Relaying, Vec::new());
+        } else {
+            self.enter_step(peer_id, ConnectStep::Dialing, addresses);
+        }
+    }
+
+    /// Remembers a peer offering to relay connections.
+    pub(super) fn relay_discovered(&mut self, peer_id: PeerId, listen_addrs: Vec<Multiaddr>) {
+        if !self.relay || (self.relays.len() >= MAX_RELAYS && !self.relays.contains_key(&peer_id)) {
+            return;
+        }
+        let addresses: Vec<Multiaddr> = listen_addrs
+            .into_iter()
+            .filter(|addr| !addr.iter().any(|protocol| protocol == Protocol::P2pCircuit))
+            .collect();
+        if !addresses.is_empty() {
+            tracing::debug!("Relay discovered: {peer_id}");
+            self.relays.insert(peer_id, addresses);
+            self.reserve_relays();
+        }
+    }
+
+    /// Gives up on a connect, failing everything that waited for it.
+    pub(super) fn connect_failed(&mut self, peer_id: PeerId) {
+        let Some(connect) = self.connects.remove(peer_id) else {
+            return;
+        };
+        tracing::warn!("Failed to reach peer [{peer_id}]");
+        connect.fail(peer_id, &self.app_sender);
+    }
+
+    /// Adds a waiter to the connect to `peer_id`, starting it if none is in progress.
+    fn wait_for(&mut self, peer_id: PeerId, add: impl FnOnce(&mut PendingConnect)) {
+        if self.connects.wait_for(peer_id, add) {
+            let addresses = self.address_book.addresses(peer_id);
+            self.enter_step(peer_id, ConnectStep::Dialing, addresses);
+        }
+    }
+
+    /// Starts `step` of the connect to `peer_id`, skipping it if it has nothing to try.
+    fn enter_step(&mut self, peer_id: PeerId, step: ConnectStep, addresses: Vec<Multiaddr>) {
+        if !self.connects.enter(peer_id, step) {
+            return;
+        }
+        let started = match step {
+            ConnectStep::Dialing => self.dial(peer_id, addresses, PeerCondition::Disconnected),
+            ConnectStep::LookingUp => {
+                let behaviour = self.swarm.behaviour_mut();
+                let queries = [
+                    (Dht::Own, behaviour.kad.as_mut()),
+                    (Dht::Public, behaviour.public_kad.as_mut()),
+                ]
+                .into_iter()
+                .filter_map(|(dht, kad)| Some((dht, kad?.get_closest_peers(peer_id))))
+                .collect::<Vec<_>>();
+                let started = !queries.is_empty();
+                for key in queries {
+                    self.lookups.insert(key, peer_id);
+                }
+                started
+            }
+            ConnectStep::Relaying => {
+                let circuits = self
+                    .relays
+                    .iter()
+                    .filter(|(relay, _)| **relay != peer_id)
+                    .flat_map(|(relay, addresses)| {
+                        addresses.iter().map(move |addr| {
+                            addr.clone()
+                                .with_p2p(*relay)
+                                .unwrap_or_else(|addr| addr)
+                                .with(Protocol::P2pCircuit)
+                                .with(Protocol::P2p(peer_id))
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                !circuits.is_empty() && self.dial(peer_id, circuits, PeerCondition::Disconnected)
+            }
+        };
+        if started {
+            send_to_app(&self.app_sender, app::ToApp::Connecting { peer_id, step });
+        } else {
+            self.dial_failed(peer_id);
+        }
+    }
+
+    /// Stops a lookup whose result is no longer needed.
+    fn finish_lookup(&mut self, (dht, query_id): (Dht, kad::QueryId)) {
+        let behaviour = self.swarm.behaviour_mut();
+        let kad = match dht {
+            Dht::Own => behaviour.kad.as_mut(),
+            Dht::Public => behaviour.public_kad.as_mut(),
+        };
+        if let Some(kad) = kad
+            && let Some(mut query) = kad.query_mut(&query_id)
+        {
+            query.finish();
+        }
+    }
+
+    /// Dials `peer_id` at `addresses` along with those the behaviours know of, returning whether
+    /// the dial started.
+    fn dial(
+        &mut self,
+        peer_id: PeerId,
+        addresses: Vec<Multiaddr>,
+        condition: PeerCondition,
+    ) -> bool {
+        // another dial may be in flight, e.g. for a bootstrap, but only this one reports back
+        let opts = DialOpts::peer_id(peer_id)
+            .addresses(addresses)
+            .extend_addresses_through_behaviour()
+            .condition(condition)
+            .build();
+        let connection_id = opts.connection_id();
+        match self.swarm.dial(opts) {
+            Ok(()) => {
+                self.dials.insert(connection_id, peer_id);
+                true
+            }
+            Err(err) => {
+                tracing::debug!("Failed to dial peer [{peer_id}]: {err}");
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::{app::messages::MessageId, crypto};
+
+    fn message(
+        peer_id: PeerId,
+        responder: behaviour::Responder,
+    ) -> (behaviour::ChatSendMessage, OutboundMessage) {
+        let message_id = MessageId::generate().unwrap();
+        let request = behaviour::ChatSendMessage {
+            message_id,
+            sender: PeerId::random(),
+            payload: crypto::EncryptedMessage {
+                handshake: None,
+                header: crypto::Header {
+                    ratchet_key: [0; 32],
+                    previous_chain_length: 0,
+                    message_number: 0,
+                },
+                ciphertext: Vec::new(),
+            },
+        };
+        let outbound = OutboundMessage {
+            peer_id,
+            message_id,
+            status: app::messages::MessageStatus::Queued,
+            responder,
+        };
+        (request, outbound)
+    }
+
+    #[test]
+    fn steps_fall_back_in_order() {
+        let mut connects = Connects::default();
+        let peer_id = PeerId::random();
+        assert!(connects.wait_for(peer_id, |_| {}));
+        assert!(!connects.wait_for(peer_id, |_| {}));
+
+        assert_eq!(
+            connects.fallback(peer_id),
+            Some(Fallback::Step(ConnectStep::LookingUp))
+        );
+        assert!(connects.enter(peer_id, ConnectStep::LookingUp));
+        assert_eq!(
+            connects.fallback(peer_id),
+            Some(Fallback::Step(ConnectStep::Relaying))
+        );
+        assert!(connects.enter(peer_id, ConnectStep::Relaying));
+        assert_eq!(connects.fallback(peer_id), Some(Fallback::GiveUp));
+    }
+
+    #[test]
+    fn addresses_found_in_the_dht_fall_back_to_relaying() {
+        let mut connects = Connects::default();
+        let peer_id = PeerId::random();
+        connects.wait_for(peer_id, |_| {});
+        connects.enter(peer_id, ConnectStep::LookingUp);
+
+        assert!(connects.enter(peer_id, ConnectStep::Dialing));
+        assert_eq!(
+            connects.fallback(peer_id),
+            Some(Fallback::Step(ConnectStep::Relaying))
+        );
+    }
+
+    #[test]
+    fn only_pending_connects_move_on() {
+        let mut connects = Connects::default();
+        let peer_id = PeerId::random();
+        assert_eq!(connects.fallback(peer_id), None);
+        assert!(!connects.enter(peer_id, ConnectStep::LookingUp));
+
+        connects.wait_for(peer_id, |_| {});
+        assert_eq!(connects.peers(), [peer_id]);
+        assert!(connects.remove(peer_id).is_some());
+        assert!(!connects.contains(peer_id));
+        assert_eq!(connects.fallback(peer_id), None);
+    }
+
+    #[test]
+    fn giving_up_fails_every_waiter() {
+        let mut connects = Connects::default();
+        let peer_id = PeerId::random();
+        let (responder, mut connect_result) = behaviour::Responder::channel();
+        let (message_responder, mut message_result) = behaviour::Responder::channel();
+        let (request, outbound) = message(peer_id, message_responder);
+        let message_id = outbound.message_id;
+        connects.wait_for(peer_id, |connect| connect.responders.push(responder));
+        connects.wait_for(peer_id, |connect| {
+            connect.messages.push((request, outbound))
+        });
+
+        let (app_sender, app_receiver) = mpsc::unbounded();
+        connects.remove(peer_id).unwrap().fail(peer_id, &app_sender);
+
+        for result in [&mut connect_result, &mut message_result] {
+            assert!(matches!(
+                result.try_recv(),
+                Ok(Some(Err(app::CommandError::Unreachable(unreachable)))) if unreachable == peer_id
+            ));
+        }
+        drop(app_sender);
+        let events: Vec<_> = futures::executor::block_on(app_receiver.collect());
+        assert!(matches!(
+            events.as_slice(),
+            [app::ToApp::MessageStatusChanged {
+                message_id: failed,
+                to,
+                status: app::messages::MessageStatus::Failed,
+            }] if *failed == message_id && *to == peer_id
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+
+use crate::app::error::ChatAppError;
+
+/// Name of the thread running the swarm with [`SwarmExecutor::Thread`], or driving its sockets
+/// with [`SwarmExecutor::Spawner`].
+#[cfg(not(target_arch = "wasm32"))]
+const THREAD_NAME: &str = "streuen-swarm";
+
+/// Where the task driving the swarm runs.
+#[derive(Clone, Default)]
+pub enum SwarmExecutor {
+    /// Spawned onto the tokio runtime the app is built in, or the browser's event loop.
+    #[default]
+    Current,
+    /// A dedicated thread with its own single threaded tokio runtime, so the app can be built
+    /// outside of any runtime. The thread exits once the swarm was shut down.
+    #[cfg(not(target_arch = "wasm32"))]
+    Thread,
+    /// Hands the task to a custom spawner.
+    ///
+    /// On native targets the swarm uses tokio's sockets and timers. When the app is built outside
+    /// of a tokio runtime, a runtime with a single background thread is started to drive them,
+    /// while the task is still polled by the spawner.
+    Spawner(Arc<dyn Fn(BoxFuture<'static, ()>) + Send + Sync>),
+}
+
+impl SwarmExecutor {
+    /// Wraps a function spawning the swarm task.
+    pub fn spawner(spawn: impl Fn(BoxFuture<'static, ()>) + Send + Sync + 'static) -> Self {
+        Self::Spawner(Arc::new(spawn))
+    }
+
+    /// Builds the swarm task with `build` and spawns it, returning the rest of what was built.
+    ///
+    /// `build` runs within the runtime the task is spawned onto, as the transports need one.
+    pub(crate) fn spawn<T, F>(
+        &self,
+        build: impl FnOnce() -> Result<(T, F), ChatAppError>,
+    ) -> Result<T, ChatAppError>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self {
+            Self::Current => {
+                #[cfg(not(target_arch = "wasm32"))]
+                let runtime =
+                    tokio::runtime::Handle::try_current().map_err(|_| ChatAppError::NoRuntime)?;
+                let (built, task) = build()?;
+                #[cfg(not(target_arch = "wasm32"))]
+                runtime.spawn(task);
+                #[cfg(target_arch = "wasm32")]
+                wasm_bindgen_futures::spawn_local(task);
+                Ok(built)
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Thread => {
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                let (built, task) = {
+                    let _guard = runtime.enter();
+                    build()?
+                };
+                std::thread::Builder::new()
+                    .name(THREAD_NAME.to_string())
+                    .spawn(move || runtime.block_on(task))?;
+                Ok(built)
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Spawner(spawn) if tokio::runtime::Handle::try_current().is_err() => {
+                let runtime = tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(1)
+                    .thread_name(THREAD_NAME)
+                    .enable_all()
+                    .build()?;
+                let (built, task) = {
+                    let _guard = runtime.enter();
+                    build()?
+                };
+                spawn(Box::pin(WithRuntime {
+                    runtime: Some(runtime),
+                    task: Box::pin(task),
+                }));
+                Ok(built)
+            }
+            Self::Spawner(spawn) => {
+                let (built, task) = build()?;
+                spawn(Box::pin(task));
+                Ok(built)
+            }
+        }
+    }
+}
+
+/// Polls a task within a tokio runtime it owns, whose thread drives the sockets and timers of the
+/// task while it is polled by another executor.
+#[cfg(not(target_arch = "wasm32"))]
+struct WithRuntime {
+    runtime: Option<tokio::runtime::Runtime>,
+    task: BoxFuture<'static, ()>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Future for WithRuntime {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        let _guard = this.runtime.as_ref().map(tokio::runtime::Runtime::enter);
+        this.task.as_mut().poll(cx)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for WithRuntime {
+    fn drop(&mut self) {
+        // the spawner may be running within another runtime, where blocking is not allowed
+        if let Some(runtime) = self.runtime.take() {
+            runtime.shutdown_background();
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    convert::Infallible,
+    task::{Context, Poll},
+};
+
+use libp2p::{
+    Multiaddr, PeerId,
+    core::{Endpoint, transport::PortUse, upgrade::DeniedUpgrade},
+    swarm::{
+        ConnectionClosed, ConnectionDenied, ConnectionHandler, ConnectionHandlerEvent,
+        ConnectionId, FromSwarm, NetworkBehaviour, NotifyHandler, SubstreamProtocol, THandler,
+        THandlerInEvent, THandlerOutEvent, ToSwarm, handler::ConnectionEvent,
+    },
+};
+
+/// Keeps the connections to selected peers open while they are idle, which the swarm would close
+/// otherwise.
+#[derive(Default)]
+pub struct Behaviour {
+    peers: HashSet<PeerId>,
+    connections: HashMap<PeerId, Vec<ConnectionId>>,
+    events: VecDeque<ToSwarm<Infallible, bool>>,
+}
+
+impl Behaviour {
+    /// Starts or stops keeping the connections to `peer_id` alive.
+    pub fn keep_alive(&mut self, peer_id: PeerId, keep_alive: bool) {
+        let changed = if keep_alive {
+            self.peers.insert(peer_id)
+        } else {
+            self.peers.remove(&peer_id)
+        };
+        if !changed {
+            return;
+        }
+        for connection_id in self.connections.get(&peer_id).into_iter().flatten() {
+            self.events.push_back(ToSwarm::NotifyHandler {
+                peer_id,
+                handler: NotifyHandler::One(*connection_id),
+                event: keep_alive,
+            });
+        }
+    }
+
+    fn handler(&mut self, connection_id: ConnectionId, peer_id: PeerId) -> Handler {
+        self.connections
+            .entry(peer_id)
+            .or_default()
+            .push(connection_id);
+        Handler {
+            keep_alive: self.peers.contains(&peer_id),
+        }
+    }
+}
+
+impl NetworkBehaviour for Behaviour {
+    type ConnectionHandler = Handler;
+    type ToSwarm = Infallible;
+
+    fn handle_established_inbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _local_addr: &Multiaddr,
+        _remote_addr: &Multiaddr,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(connection_id, peer))
+    }
+
+    fn handle_established_outbound_connection(
+        &mut self,
+        connection_id: ConnectionId,
+        peer: PeerId,
+        _addr: &Multiaddr,
+        _role_override: Endpoint,
+        _port_use: PortUse,
+    ) -> Result<THandler<Self>, ConnectionDenied> {
+        Ok(self.handler(connection_id, peer))
+    }
+
+    fn on_swarm_event(&mut self, event: FromSwarm) {
+        if let FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            connection_id,
+            ..
+        }) = event
+            && let Some(connections) = self.connections.get_mut(&peer_id)
+        {
+            connections.retain(|id| *id != connection_id);
+            if connections.is_empty() {
+                self.connections.remove(&peer_id);
+            }
+        }
+    }
+
+    fn on_connection_handler_event(
+        &mut self,
+        _peer_id: PeerId,
+        _connection_id: ConnectionId,
+        event: THandlerOutEvent<Self>,
+    ) {
+        match event {}
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ToSwarm<Self::ToSwarm, THandlerInEvent<Self>>> {
+        match self.events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Connection handler without any protocol, which only decides whether the connection is kept
+/// alive.
+pub struct Handler {
+    keep_alive: bool,
+}
+
+impl ConnectionHandler for Handler {
+    type FromBehaviour = bool;
+    type ToBehaviour = Infallible;
+    type InboundProtocol = DeniedUpgrade;
+    type OutboundProtocol = DeniedUpgrade;
+    type InboundOpenInfo = ();
+    type OutboundOpenInfo = ();
+
+    fn listen_protocol(&self) -> SubstreamProtocol<Self::InboundProtocol> {
+        SubstreamProtocol::new(DeniedUpgrade, ())
+    }
+
+    fn connection_keep_alive(&self) -> bool {
+        self.keep_alive
+    }
+
+    fn on_behaviour_event(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
+
+    fn poll(
+        &mut self,
+        _cx: &mut Context<'_>,
+    ) -> Poll<ConnectionHandlerEvent<Self::OutboundProtocol, (), Self::ToBehaviour>> {
+        Poll::Pending
+    }
+
+    fn on_connection_event(
+        &mut self,
+        _event: ConnectionEvent<Self::InboundProtocol, Self::OutboundProtocol>,
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use libp2p::core::ConnectedPoint;
+
+    use super::*;
+
+    fn notified(behaviour: &mut Behaviour) -> Vec<(ConnectionId, bool)> {
+        behaviour
+            .events
+            .drain(..)
+            .filter_map(|event| match event {
+                ToSwarm::NotifyHandler {
+                    handler: NotifyHandler::One(connection_id),
+                    event,
+                    ..
+                } => Some((connection_id, event)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_connections_to_contacts_are_kept_alive() {
+        let mut behaviour = Behaviour::default();
+        let contact = PeerId::random();
+        let stranger = PeerId::random();
+        behaviour.keep_alive(contact, true);
+
+        let handler = behaviour.handler(ConnectionId::new_unchecked(1), contact);
+        assert!(handler.connection_keep_alive());
+        let handler = behaviour.handler(ConnectionId::new_unchecked(2), stranger);
+        assert!(!handler.connection_keep_alive());
+    }
+
+    #[test]
+    fn changes_are_sent_to_open_connections() {
+        let mut behaviour = Behaviour::default();
+        let peer_id = PeerId::random();
+        let connection = ConnectionId::new_unchecked(1);
+        let mut handler = behaviour.handler(connection, peer_id);
+        assert!(notified(&mut behaviour).is_empty());
+
+        behaviour.keep_alive(peer_id, true);
+        let events = notified(&mut behaviour);
+        assert_eq!(events, vec![(connection, true)]);
+        handler.on_behaviour_event(events[0].1);
+        assert!(handler.connection_keep_alive());
+
+        // unchanged settings are not sent again
+        behaviour.keep_alive(peer_id, true);
+        assert!(notified(&mut behaviour).is_empty());
+
+        behaviour.keep_alive(peer_id, false);
+        let events = notified(&mut behaviour);
+        assert_eq!(events, vec![(connection, false)]);
+        handler.on_behaviour_event(events[0].1);
+        assert!(!handler.connection_keep_alive());
+    }
+
+    #[test]
+    fn closed_connections_are_not_notified() {
+        let mut behaviour = Behaviour::default();
+        let peer_id = PeerId::random();
+        let closed = ConnectionId::new_unchecked(1);
+        let open = ConnectionId::new_unchecked(2);
+        behaviour.handler(closed, peer_id);
+        behaviour.handler(open, peer_id);
+
+        let endpoint = ConnectedPoint::Listener {
+            local_addr: Multiaddr::empty(),
+            send_back_addr: Multiaddr::empty(),
+        };
+        behaviour.on_swarm_event(FromSwarm::ConnectionClosed(ConnectionClosed {
+            peer_id,
+            connection_id: closed,
+            endpoint: &endpoint,
+            cause: None,
+            remaining_established: 1,
+        }));
+
+        behaviour.keep_alive(peer_id, true);
+        assert_eq!(notified(&mut behaviour), vec![(open, true)]);
+    }
+}
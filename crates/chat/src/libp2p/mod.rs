@@ -1,93 +1,1027 @@
+mod address_book;
 pub(crate) mod behaviour;
+pub(crate) mod config;
+mod connect;
+pub(crate) mod executor;
+mod keep_alive;
+mod network_status;
+mod pings;
+#[cfg(not(target_arch = "wasm32"))]
+mod private_network;
+mod reconnect;
+mod registrations;
+mod reservations;
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) mod transport;
 
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use futures::future::{self, Either};
+use futures_channel::{mpsc, oneshot};
 #[cfg(not(target_arch = "wasm32"))]
 use libp2p::mdns;
 use libp2p::{
-    StreamProtocol, Swarm, SwarmBuilder, multiaddr, noise, request_response, swarm::SwarmEvent,
+    Multiaddr, PeerId, StreamProtocol, Swarm, SwarmBuilder, autonat,
+    core::ConnectedPoint,
+    gossipsub, identify, kad, multiaddr, noise, relay, request_response,
+    swarm::{ConnectionId, DialError, SwarmEvent},
     yamux,
 };
+use web_time::Instant;
 
 use crate::app;
+use crate::crypto::Sessions;
+use crate::libp2p::address_book::AddressBook;
 use crate::libp2p::behaviour::ChatBehaviourEvent;
 
-const CHAT_PROTOCOL: StreamProtocol = StreamProtocol::new("/streuen/chat/0.1.0");
+const CHAT_PROTOCOL: StreamProtocol = StreamProtocol::new("/streuen/chat/0.4.0");
+
+/// Number of received message ids remembered to drop messages arriving twice.
+const RECENT_MESSAGES: usize = 4096;
+
+/// Interval at which the known peers are persisted, besides when shutting down.
+const SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Interval at which periodic work like reconnecting to contacts is checked for.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Time given to pending messages and closing connections when shutting down.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The stores handed back by the swarm task once it exited, to restart it later on.
+pub(crate) struct SwarmStores {
+    pub sessions: Box<dyn app::sessions::SessionStore>,
+    pub peers: Box<dyn app::peers::PeerStore>,
+}
+
+/// Handle to stop the running swarm task.
+pub(crate) struct SwarmHandle(oneshot::Sender<oneshot::Sender<SwarmStores>>);
+
+impl SwarmHandle {
+    /// Stops the swarm task, resolving to its stores once it exited.
+    pub async fn shutdown(self) -> Result<SwarmStores, app::error::ChatAppError> {
+        let (sender, receiver) = oneshot::channel();
+        self.0
+            .send(sender)
+            .map_err(|_| app::error::ChatAppError::SwarmStopped)?;
+        receiver
+            .await
+            .map_err(|_| app::error::ChatAppError::SwarmStopped)
+    }
+}
 
 pub(crate) fn run_swarm(
     keypair: libp2p::identity::Keypair,
-) -> Result<behaviour::InnerChatBehavior, app::error::ChatAppError> {
-    let swarm = build_swarm(keypair)?;
+    stores: SwarmStores,
+    config: config::NetworkConfig,
+    app_sender: mpsc::UnboundedSender<app::ToApp>,
+    executor: &executor::SwarmExecutor,
+) -> Result<(behaviour::ChatCommands, SwarmHandle), app::error::ChatAppError> {
+    executor.spawn(|| {
+        let sessions = Sessions::new(&keypair, stores.sessions)?;
+        let address_book = match AddressBook::load(stores.peers.as_ref()) {
+            Ok(address_book) => address_book,
+            Err(err) => {
+                tracing::error!("Failed to load known peers: {err}");
+                AddressBook::default()
+            }
+        };
+        let mut swarm = build_swarm(keypair, &config)?;
+        for addr in config.external_addresses {
+            swarm.add_external_address(addr);
+        }
+
+        // the DHT is joined through the peers known from previous runs along with the bootstrap
+        // peers
+        if let Some(kad) = swarm.behaviour_mut().kad.as_mut() {
+            for (peer_id, addresses) in address_book.kademlia_peers() {
+                for addr in addresses {
+                    kad.add_address(&peer_id, addr);
+                }
+            }
+        }
 
-    let inner_behavior = swarm.behaviour().inner.clone();
+        // handled by the swarm loop like any dispatched command, so failures reach the app
+        let inner_behavior = &mut swarm.behaviour_mut().inner;
+        for addr in config.listen_addresses {
+            inner_behavior.queue(app::ToChat::ListenOn(addr));
+        }
+        for addr in config.bootstrap_peers {
+            inner_behavior.queue(app::ToChat::AddBoostrapPeer(addr));
+        }
+        for addr in config.rendezvous_points {
+            inner_behavior.queue(app::ToChat::AddRendezvousPoint(addr));
+        }
+        for namespace in config.rendezvous_namespaces {
+            inner_behavior.queue(app::ToChat::RegisterNamespace(namespace));
+        }
+        let commands = inner_behavior.commands();
 
-    spawn_swarm_loop(run_swarm_loop(swarm));
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+        // the configured ones are confirmed without an event
+        let external_addresses = swarm.external_addresses().cloned().collect();
+        let mut task = SwarmTask {
+            swarm,
+            sessions,
+            peer_store: stores.peers,
+            address_book,
+            app_sender,
+            rooms: HashMap::new(),
+            outbound: HashMap::new(),
+            received: RecentMessages::default(),
+            connects: connect::Connects::default(),
+            dials: HashMap::new(),
+            lookups: HashMap::new(),
+            relay: config.relay,
+            relays: HashMap::new(),
+            direct_connections: HashMap::new(),
+            reconnects: reconnect::Reconnects::default(),
+            registrations: registrations::Registrations::default(),
+            reservations: reservations::Reservations::default(),
+            pings: pings::Pings::default(),
+            network_status: network_status::Status::new(app::NetworkStatus {
+                upnp: if cfg!(not(target_arch = "wasm32")) && config.upnp {
+                    app::network::UpnpStatus::Searching
+                } else {
+                    app::network::UpnpStatus::Disabled
+                },
+                external_addresses,
+                ..Default::default()
+            }),
+            last_save: Instant::now(),
+        };
+        for peer_id in task.address_book.contacts().collect::<Vec<_>>() {
+            task.keep_contact(peer_id);
+        }
 
-    Ok(inner_behavior)
+        Ok((
+            (commands, SwarmHandle(shutdown)),
+            task.run(shutdown_receiver),
+        ))
+    })
 }
 
-async fn run_swarm_loop(mut swarm: Swarm<behaviour::ChatBehaviour>) {
-    use libp2p::futures::StreamExt;
-
-    loop {
-        if let Some(event) = swarm.next().await {
-            match event {
-                SwarmEvent::Behaviour(ref behavior_event) => match behavior_event {
-                    ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::ListenOn(addr)) => {
-                        if let Err(err) = swarm.listen_on(addr.clone()) {
-                            tracing::error!("Error listening to address [{addr}]: {err:?}")
-                        } else {
-                            tracing::info!("Listening to address: {addr}")
-                        }
-                    }
-                    ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::AddBoostrapPeer(addr)) => {
-                        if let Some(multiaddr::Protocol::P2p(peer_id)) = addr.iter().last() {
-                            swarm.behaviour_mut().kad.as_mut().map(|k| {
-                                let _ = k.add_address(&peer_id, addr.clone());
-                                let _ = k.bootstrap();
-                            });
-                        } else {
-                            tracing::error!("Invalid bootstrap address: {addr}")
-                        }
-                    }
-                    ChatBehaviourEvent::RequestResponse(request_response::Event::Message {
-                        peer,
-                        connection_id: _,
-                        message,
-                    }) => {
-                        tracing::debug!("Received message: {peer} {message:?}")
-                    }
-                    #[cfg(not(target_arch = "wasm32"))]
-                    ChatBehaviourEvent::Mdns(mdns::Event::Discovered(peers)) => {
-                        for (peer_id, _addr) in peers {
-                            tracing::info!("Peer discovered from mDNS: {peer_id}");
-                            // swarm.behaviour_mut().gossipsub.add_explicit_peer(peer_id);
-                            if swarm.is_connected(peer_id) {
-                                continue;
+/// State of the task driving the swarm.
+struct SwarmTask {
+    swarm: Swarm<behaviour::ChatBehaviour>,
+    sessions: Sessions,
+    peer_store: Box<dyn app::peers::PeerStore>,
+    address_book: AddressBook,
+    app_sender: mpsc::UnboundedSender<app::ToApp>,
+    /// Rooms joined by this node, keyed by the hash of their gossipsub topic.
+    rooms: HashMap<gossipsub::TopicHash, String>,
+    /// Direct messages awaiting an acknowledgement.
+    outbound: HashMap<request_response::OutboundRequestId, OutboundMessage>,
+    /// Messages received lately, direct and in rooms.
+    received: RecentMessages,
+    connects: connect::Connects,
+    /// Dials started to reach a peer.
+    dials: HashMap<ConnectionId, PeerId>,
+    /// DHT lookups started to reach a peer.
+    lookups: HashMap<(connect::Dht, kad::QueryId), PeerId>,
+    relay: bool,
+    /// Peers offering to relay connections, with their listen addresses.
+    relays: HashMap<PeerId, Vec<Multiaddr>>,
+    /// Number of connections to each peer which are not relayed.
+    direct_connections: HashMap<PeerId, usize>,
+    reconnects: reconnect::Reconnects,
+    registrations: registrations::Registrations,
+    reservations: reservations::Reservations,
+    pings: pings::Pings,
+    /// Reported to the app whenever it changes.
+    network_status: network_status::Status,
+    last_save: Instant,
+}
+
+impl SwarmTask {
+    /// Drives the swarm until a shutdown is requested or the app is gone.
+    async fn run(mut self, mut shutdown: oneshot::Receiver<oneshot::Sender<SwarmStores>>) {
+        use libp2p::futures::StreamExt;
+
+        send_to_app(
+            &self.app_sender,
+            app::ToApp::NetworkStatusChanged(self.network_status.get().clone()),
+        );
+        let mut tick = futures_timer::Delay::new(TICK_INTERVAL);
+        let reply = loop {
+            match future::select(future::select(self.swarm.next(), &mut tick), &mut shutdown).await
+            {
+                Either::Left((Either::Left((Some(event), _)), _)) => self.handle_event(event),
+                Either::Left((Either::Left((None, _)), _)) => break None,
+                Either::Left((Either::Right(((), _)), _)) => {
+                    self.tick();
+                    tick.reset(TICK_INTERVAL);
+                }
+                Either::Right((reply, _)) => break reply.ok(),
+            }
+        };
+        let stores = self.shutdown().await;
+        if let Some(reply) = reply {
+            let _ = reply.send(stores);
+        }
+    }
+
+    /// Gives pending direct messages a chance to be delivered, closes every connection and
+    /// persists the known peers.
+    async fn shutdown(mut self) -> SwarmStores {
+        use libp2p::futures::StreamExt;
+
+        tracing::info!("Shutting down the swarm");
+        let mut deadline = futures_timer::Delay::new(SHUTDOWN_TIMEOUT);
+        while !self.outbound.is_empty() {
+            match future::select(self.swarm.next(), &mut deadline).await {
+                Either::Left((Some(event), _)) => self.handle_event(event),
+                _ => break,
+            }
+        }
+
+        let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        for peer_id in peers {
+            let _ = self.swarm.disconnect_peer_id(peer_id);
+        }
+        while self.swarm.connected_peers().next().is_some() {
+            match future::select(self.swarm.next(), &mut deadline).await {
+                Either::Left((Some(event), _)) => self.handle_event(event),
+                _ => break,
+            }
+        }
+
+        for peer_id in self.connects.peers() {
+            self.connect_failed(peer_id);
+        }
+        for message in std::mem::take(&mut self.outbound).into_values() {
+            send_to_app(
+                &self.app_sender,
+                app::ToApp::MessageStatusChanged {
+                    message_id: message.message_id,
+                    to: message.peer_id,
+                    status: app::messages::MessageStatus::Failed,
+                },
+            );
+        }
+
+        if let Some(kad) = self.swarm.behaviour_mut().kad.as_mut() {
+            for bucket in kad.kbuckets() {
+                for entry in bucket.iter() {
+                    let peer_id = *entry.node.key.preimage();
+                    for addr in entry.node.value.iter() {
+                        self.address_book.observed(peer_id, addr.clone());
+                    }
+                    self.address_book.kademlia_peer(peer_id);
+                }
+            }
+        }
+        self.save_peers();
+        send_to_app(
+            &self.app_sender,
+            app::ToApp::NetworkStatusChanged(app::NetworkStatus::default()),
+        );
+
+        SwarmStores {
+            sessions: self.sessions.into_store(),
+            peers: self.peer_store,
+        }
+    }
+
+    /// Runs the work due periodically.
+    fn tick(&mut self) {
+        for peer_id in self.reconnects.due() {
+            self.reconnect(peer_id);
+        }
+        self.rendezvous_tick();
+        self.reserve_relays();
+        if self.last_save.elapsed() >= SAVE_INTERVAL {
+            self.save_peers();
+        }
+    }
+
+    /// Keeps the connections to a contact alive and reconnects to it whenever they drop.
+    fn keep_contact(&mut self, peer_id: PeerId) {
+        self.swarm
+            .behaviour_mut()
+            .keep_alive
+            .keep_alive(peer_id, true);
+        if !self.direct_connections.contains_key(&peer_id) {
+            self.reconnects.schedule(peer_id);
+        }
+    }
+
+    fn save_peers(&mut self) {
+        self.last_save = Instant::now();
+        if let Err(err) = self.address_book.save(self.peer_store.as_mut()) {
+            tracing::error!("Failed to persist known peers: {err}");
+        }
+    }
+
+    /// Hands a direct message to the request-response protocol.
+    fn send_request(&mut self, request: behaviour::ChatSendMessage, mut message: OutboundMessage) {
+        let request_id = self
+            .swarm
+            .behaviour_mut()
+            .request_response
+            .send_request(&message.peer_id, request);
+        if message.status == app::messages::MessageStatus::Queued
+            && self.swarm.is_connected(&message.peer_id)
+        {
+            message.status = app::messages::MessageStatus::Sent;
+            send_to_app(
+                &self.app_sender,
+                app::ToApp::MessageStatusChanged {
+                    message_id: message.message_id,
+                    to: message.peer_id,
+                    status: message.status,
+                },
+            );
+        }
+        self.outbound.insert(request_id, message);
+    }
+
+    fn handle_event(&mut self, event: SwarmEvent<ChatBehaviourEvent>) {
+        match event {
+            SwarmEvent::Behaviour(behavior_event) => match behavior_event {
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::ListenOn(addr, responder)) => {
+                    let result = match self.swarm.listen_on(addr.clone()) {
+                        Ok(_) => {
+                            tracing::info!("Listening to address: {addr}");
+                            Ok(())
+                        }
+                        Err(err) => {
+                            tracing::error!("Error listening to address [{addr}]: {err:?}");
+                            Err(app::CommandError::Listen(addr, err))
+                        }
+                    };
+                    respond(&self.app_sender, responder, result);
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::AddBoostrapPeer(
+                    addr,
+                    responder,
+                )) => {
+                    let result = match (addr.iter().last(), self.swarm.behaviour_mut().kad.as_mut())
+                    {
+                        (Some(multiaddr::Protocol::P2p(peer_id)), Some(kad)) => {
+                            kad.add_address(&peer_id, addr.clone());
+                            // cannot fail as the routing table holds the peer just added
+                            let _ = kad.bootstrap();
+                            Ok(())
+                        }
+                        (Some(multiaddr::Protocol::P2p(_)), None) => {
+                            Err(app::CommandError::KademliaDisabled)
+                        }
+                        _ => {
+                            tracing::error!("Invalid bootstrap address: {addr}");
+                            Err(app::CommandError::InvalidBootstrapAddress(addr))
+                        }
+                    };
+                    respond(&self.app_sender, responder, result);
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::Connect(peer_id, responder)) => {
+                    self.connect(peer_id, responder);
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::Dial(addr, responder)) => {
+                    if let Some(multiaddr::Protocol::P2p(peer_id)) = addr.iter().last() {
+                        self.address_book.observed(peer_id, addr);
+                        self.connect(peer_id, responder);
+                    } else {
+                        respond(
+                            &self.app_sender,
+                            responder,
+                            Err(app::CommandError::InvalidPeerAddress(addr)),
+                        );
+                    }
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::AddContact(
+                    peer_id,
+                    responder,
+                )) => {
+                    self.address_book.set_contact(peer_id, true);
+                    self.keep_contact(peer_id);
+                    respond(&self.app_sender, responder, Ok(()));
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::RemoveContact(
+                    peer_id,
+                    responder,
+                )) => {
+                    self.address_book.set_contact(peer_id, false);
+                    self.swarm
+                        .behaviour_mut()
+                        .keep_alive
+                        .keep_alive(peer_id, false);
+                    self.reconnects.cancel(peer_id);
+                    respond(&self.app_sender, responder, Ok(()));
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::SendMessage(
+                    peer_id,
+                    outgoing,
+                    responder,
+                )) => {
+                    tracing::debug!(
+                        "Sending message [{}] to peer [{peer_id}]",
+                        outgoing.message_id
+                    );
+                    let mut message = app::messages::Message::new(
+                        outgoing.message_id,
+                        peer_id,
+                        outgoing.sender,
+                        outgoing.message,
+                        app::messages::MessageStatus::Queued,
+                    );
+                    match self.sessions.encrypt(
+                        peer_id,
+                        outgoing.message_id,
+                        message.text().as_bytes(),
+                    ) {
+                        Ok(payload) => {
+                            let request = behaviour::ChatSendMessage {
+                                message_id: outgoing.message_id,
+                                sender: outgoing.sender,
+                                payload,
+                            };
+                            // without a connection the request waits until the peer was reached
+                            if self.swarm.is_connected(&peer_id) {
+                                message.set_status(app::messages::MessageStatus::Sent);
                             }
-                            let _ = swarm.dial(peer_id.clone());
+                            let outbound = OutboundMessage {
+                                peer_id,
+                                message_id: outgoing.message_id,
+                                status: message.status(),
+                                responder,
+                            };
+                            send_to_app(&self.app_sender, app::ToApp::MessageSent(message));
+                            self.send_when_connected(request, outbound);
+                        }
+                        Err(err) => {
+                            tracing::error!(
+                                "Failed to encrypt message [{}] for peer [{peer_id}]: {err}",
+                                outgoing.message_id
+                            );
+                            message.set_status(app::messages::MessageStatus::Failed);
+                            send_to_app(&self.app_sender, app::ToApp::MessageSent(message));
+                            respond(
+                                &self.app_sender,
+                                responder,
+                                Err(app::CommandError::Encryption(peer_id, Box::new(err))),
+                            );
                         }
                     }
-                    #[cfg(not(target_arch = "wasm32"))]
-                    ChatBehaviourEvent::Mdns(mdns::Event::Expired(peers)) => {
-                        for (peer_id, _addr) in peers {
-                            tracing::info!("Peer discovered from mDNS: {peer_id}");
-                            // swarm.behaviour_mut().gossipsub.remove_explicit_peer(peer_id);
-                            if !swarm.is_connected(peer_id) {
-                                continue;
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::JoinRoom(room_id, responder)) => {
+                    let topic = behaviour::room_topic(&room_id);
+                    let result = match self.swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+                        Ok(_) => {
+                            tracing::info!("Joined room: {room_id}");
+                            self.rooms.insert(topic.hash(), room_id.clone());
+                            send_to_app(&self.app_sender, app::ToApp::RoomJoined(room_id));
+                            Ok(())
+                        }
+                        Err(err) => {
+                            tracing::error!("Failed to join room [{room_id}]: {err}");
+                            Err(app::CommandError::JoinRoom(room_id, err))
+                        }
+                    };
+                    respond(&self.app_sender, responder, result);
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::LeaveRoom(
+                    room_id,
+                    responder,
+                )) => {
+                    let topic = behaviour::room_topic(&room_id);
+                    self.swarm.behaviour_mut().gossipsub.unsubscribe(&topic);
+                    let result = if self.rooms.remove(&topic.hash()).is_some() {
+                        tracing::info!("Left room: {room_id}");
+                        send_to_app(&self.app_sender, app::ToApp::RoomLeft(room_id));
+                        Ok(())
+                    } else {
+                        Err(app::CommandError::NotInRoom(room_id))
+                    };
+                    respond(&self.app_sender, responder, result);
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::SendRoomMessage(
+                    room_id,
+                    room_message,
+                    responder,
+                )) => {
+                    let topic = behaviour::room_topic(&room_id);
+                    let published = cbor4ii::serde::to_vec(Vec::new(), &room_message)
+                        .map_err(|err| {
+                            app::CommandError::Encoding(room_id.clone(), err.to_string())
+                        })
+                        .and_then(|data| {
+                            self.swarm
+                                .behaviour_mut()
+                                .gossipsub
+                                .publish(topic, data)
+                                .map_err(|err| app::CommandError::Publish(room_id.clone(), err))
+                        });
+                    let result = match published {
+                        Ok(_) => {
+                            let message = app::messages::RoomMessage::new(
+                                room_id,
+                                room_message.message_id,
+                                *self.swarm.local_peer_id(),
+                                room_message.message,
+                            );
+                            send_to_app(&self.app_sender, app::ToApp::RoomMessageSent(message));
+                            Ok(())
+                        }
+                        Err(err) => {
+                            tracing::warn!("Failed to publish to room [{room_id}]: {err}");
+                            Err(err)
+                        }
+                    };
+                    respond(&self.app_sender, responder, result);
+                }
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::AddRendezvousPoint(
+                    addr,
+                    responder,
+                )) => self.add_rendezvous_point(addr, responder),
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::RegisterNamespace(
+                    namespace,
+                    responder,
+                )) => self.register_namespace(namespace, responder),
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::UnregisterNamespace(
+                    namespace,
+                    responder,
+                )) => self.unregister_namespace(namespace, responder),
+                ChatBehaviourEvent::Inner(behaviour::ChatToSwarm::Failed(err, responder)) => {
+                    tracing::error!("{err}");
+                    respond(&self.app_sender, responder, Err(err));
+                }
+                ChatBehaviourEvent::Gossipsub(gossipsub::Event::Message { message, .. }) => {
+                    let Some(room_id) = self.rooms.get(&message.topic) else {
+                        return;
+                    };
+                    let Some(source) = message.source else {
+                        tracing::warn!("Dropping unsigned message in room [{room_id}]");
+                        return;
+                    };
+                    match cbor4ii::serde::from_slice::<behaviour::ChatRoomMessage>(&message.data) {
+                        Ok(room_message) => {
+                            if !self.received.insert(room_message.message_id) {
+                                tracing::debug!(
+                                    "Dropping duplicate message [{}] in room [{room_id}]",
+                                    room_message.message_id
+                                );
+                                return;
                             }
-                            let _ = swarm.disconnect_peer_id(peer_id.clone());
+                            let message = app::messages::RoomMessage::new(
+                                room_id.clone(),
+                                room_message.message_id,
+                                source,
+                                room_message.message,
+                            );
+                            send_to_app(&self.app_sender, app::ToApp::RoomMessageReceived(message));
                         }
+                        Err(err) => tracing::warn!(
+                            "Dropping malformed message in room [{room_id}] from [{source}]: {err}"
+                        ),
                     }
-                    _ => tracing::debug!("{event:?}"),
-                },
-                event => tracing::debug!("Swarm Event: {event:?}"),
+                }
+                ChatBehaviourEvent::RequestResponse(request_response::Event::Message {
+                    peer,
+                    connection_id: _,
+                    message:
+                        request_response::Message::Request {
+                            request, channel, ..
+                        },
+                }) => {
+                    if request.sender != peer {
+                        tracing::warn!(
+                            "Dropping message [{}] from [{peer}] claiming to be from [{}]",
+                            request.message_id,
+                            request.sender
+                        );
+                        return;
+                    }
+                    let response = behaviour::ChatMessageReceived {
+                        message_id: request.message_id,
+                    };
+                    // the keys of a message are consumed on decryption, so a message arriving
+                    // twice is acknowledged again without decrypting it
+                    if self.received.contains(&request.message_id) {
+                        tracing::debug!(
+                            "Dropping duplicate message [{}] from peer [{peer}]",
+                            request.message_id
+                        );
+                        let _ = self
+                            .swarm
+                            .behaviour_mut()
+                            .request_response
+                            .send_response(channel, response);
+                        return;
+                    }
+                    tracing::debug!(
+                        "Received message [{}] from peer [{peer}]",
+                        request.message_id
+                    );
+                    // undecryptable messages are left unacknowledged
+                    let text = match self
+                        .sessions
+                        .decrypt(peer, request.message_id, &request.payload)
+                        .map(String::from_utf8)
+                    {
+                        Ok(Ok(text)) => text,
+                        Ok(Err(_)) => {
+                            tracing::warn!(
+                                "Dropping message [{}] from [{peer}] which is not UTF-8",
+                                request.message_id
+                            );
+                            return;
+                        }
+                        Err(err) => {
+                            tracing::warn!(
+                                "Failed to decrypt message [{}] from [{peer}]: {err}",
+                                request.message_id
+                            );
+                            send_to_app(
+                                &self.app_sender,
+                                app::ToApp::Error(format!(
+                                    "Failed to decrypt message from peer [{peer}]: {err}"
+                                )),
+                            );
+                            return;
+                        }
+                    };
+                    self.received.insert(request.message_id);
+                    if self
+                        .swarm
+                        .behaviour_mut()
+                        .request_response
+                        .send_response(channel, response)
+                        .is_err()
+                    {
+                        tracing::warn!(
+                            "Failed to acknowledge message [{}] from peer [{peer}]",
+                            request.message_id
+                        );
+                    }
+                    let message = app::messages::Message::new(
+                        request.message_id,
+                        *self.swarm.local_peer_id(),
+                        request.sender,
+                        text,
+                        app::messages::MessageStatus::Delivered,
+                    );
+                    send_to_app(&self.app_sender, app::ToApp::MessageReceived(message));
+                }
+                ChatBehaviourEvent::RequestResponse(request_response::Event::Message {
+                    peer,
+                    connection_id: _,
+                    message:
+                        request_response::Message::Response {
+                            request_id,
+                            response,
+                        },
+                }) => {
+                    let Some(message) = self.outbound.remove(&request_id) else {
+                        tracing::warn!(
+                            "Dropping unexpected acknowledgement of message [{}] from [{peer}]",
+                            response.message_id
+                        );
+                        return;
+                    };
+                    // only the message the request carried can be acknowledged by it
+                    let (status, result) = if response.message_id == message.message_id {
+                        (app::messages::MessageStatus::Delivered, Ok(()))
+                    } else {
+                        tracing::warn!(
+                            "Peer [{peer}] acknowledged message [{}] in reply to message [{}]",
+                            response.message_id,
+                            message.message_id
+                        );
+                        (
+                            app::messages::MessageStatus::Failed,
+                            Err(app::CommandError::MismatchedAcknowledgement(
+                                peer,
+                                message.message_id,
+                                response.message_id,
+                            )),
+                        )
+                    };
+                    send_to_app(
+                        &self.app_sender,
+                        app::ToApp::MessageStatusChanged {
+                            message_id: message.message_id,
+                            to: peer,
+                            status,
+                        },
+                    );
+                    respond(&self.app_sender, message.responder, result);
+                }
+                ChatBehaviourEvent::RequestResponse(request_response::Event::OutboundFailure {
+                    peer,
+                    request_id,
+                    error,
+                    ..
+                }) => {
+                    tracing::warn!("Failed to send message to peer [{peer}]: {error}");
+                    let error = app::CommandError::Delivery(peer, error);
+                    match self.outbound.remove(&request_id) {
+                        Some(message) => {
+                            send_to_app(
+                                &self.app_sender,
+                                app::ToApp::MessageStatusChanged {
+                                    message_id: message.message_id,
+                                    to: peer,
+                                    status: app::messages::MessageStatus::Failed,
+                                },
+                            );
+                            respond(&self.app_sender, message.responder, Err(error));
+                        }
+                        None => send_to_app(&self.app_sender, app::ToApp::Error(error.to_string())),
+                    }
+                }
+                ChatBehaviourEvent::Kad(kad::Event::RoutingUpdated {
+                    peer,
+                    is_new_peer,
+                    addresses,
+                    ..
+                }) => {
+                    for addr in addresses.iter() {
+                        self.address_book.observed(peer, addr.clone());
+                    }
+                    self.address_book.kademlia_peer(peer);
+                    if is_new_peer {
+                        send_to_app(
+                            &self.app_sender,
+                            app::ToApp::PeerDiscovered {
+                                peer_id: peer,
+                                source: app::DiscoverySource::Kademlia,
+                            },
+                        );
+                    }
+                }
+                ChatBehaviourEvent::Kad(kad::Event::OutboundQueryProgressed {
+                    id,
+                    result: kad::QueryResult::GetClosestPeers(result),
+                    step,
+                    ..
+                }) => {
+                    let peers = match result {
+                        Ok(kad::GetClosestPeersOk { peers, .. })
+                        | Err(kad::GetClosestPeersError::Timeout { peers, .. }) => peers,
+                    };
+                    self.lookup_progressed(connect::Dht::Own, id, peers, step.last);
+                }
+                ChatBehaviourEvent::PublicKad(kad::Event::OutboundQueryProgressed {
+                    id,
+                    result: kad::QueryResult::GetClosestPeers(result),
+                    step,
+                    ..
+                }) => {
+                    let peers = match result {
+                        Ok(kad::GetClosestPeersOk { peers, .. })
+                        | Err(kad::GetClosestPeersError::Timeout { peers, .. }) => peers,
+                    };
+                    self.lookup_progressed(connect::Dht::Public, id, peers, step.last);
+                }
+                ChatBehaviourEvent::Identify(identify::Event::Received {
+                    peer_id, info, ..
+                }) => {
+                    if info.protocols.contains(&relay::HOP_PROTOCOL_NAME) {
+                        self.relay_discovered(peer_id, info.listen_addrs);
+                    }
+                }
+                ChatBehaviourEvent::Rendezvous(event) => self.handle_rendezvous_event(event),
+                ChatBehaviourEvent::Autonat(autonat::Event::StatusChanged { old, new }) => {
+                    self.reachability_changed(old, new)
+                }
+                ChatBehaviourEvent::RelayClient(event) => self.handle_relay_client_event(event),
+                ChatBehaviourEvent::Dcutr(event) => self.handle_dcutr_event(event),
+                ChatBehaviourEvent::Ping(event) => self.handle_ping_event(event),
+                #[cfg(not(target_arch = "wasm32"))]
+                ChatBehaviourEvent::Upnp(event) => self.handle_upnp_event(event),
+                #[cfg(not(target_arch = "wasm32"))]
+                ChatBehaviourEvent::Mdns(mdns::Event::Discovered(peers)) => {
+                    let mut discovered = Vec::new();
+                    for (peer_id, _addr) in peers {
+                        tracing::info!("Peer discovered from mDNS: {peer_id}");
+                        if !discovered.contains(&peer_id) {
+                            discovered.push(peer_id);
+                        }
+                        // swarm.behaviour_mut().gossipsub.add_explicit_peer(peer_id);
+                        if self.swarm.is_connected(&peer_id) {
+                            continue;
+                        }
+                        let _ = self.swarm.dial(peer_id);
+                    }
+                    for peer_id in discovered {
+                        send_to_app(
+                            &self.app_sender,
+                            app::ToApp::PeerDiscovered {
+                                peer_id,
+                                source: app::DiscoverySource::Mdns,
+                            },
+                        );
+                    }
+                }
+                #[cfg(not(target_arch = "wasm32"))]
+                ChatBehaviourEvent::Mdns(mdns::Event::Expired(peers)) => {
+                    // connections over the expired address fail on their own, any other path to
+                    // the peer stays open
+                    for (peer_id, addr) in peers {
+                        tracing::debug!("mDNS record of peer [{peer_id}] at [{addr}] expired");
+                    }
+                }
+                behavior_event => tracing::debug!("{behavior_event:?}"),
+            },
+            SwarmEvent::NewListenAddr { address, .. } => {
+                tracing::info!("New listen address: {address}");
+                send_to_app(&self.app_sender, app::ToApp::ListenAddressAdded(address));
+            }
+            SwarmEvent::ConnectionEstablished {
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                self.dials.remove(&connection_id);
+                if endpoint.is_relayed() {
+                    // keep trying to reach contacts directly, unless hole punching gets there first
+                    if self.address_book.is_contact(peer_id)
+                        && !self.direct_connections.contains_key(&peer_id)
+                    {
+                        self.reconnects.schedule(peer_id);
+                    }
+                } else {
+                    *self.direct_connections.entry(peer_id).or_default() += 1;
+                    self.reconnects.cancel(peer_id);
+                }
+                if let ConnectedPoint::Dialer { address, .. } = endpoint {
+                    self.address_book.succeeded(peer_id, address);
+                }
+                if num_established.get() == 1 {
+                    send_to_app(&self.app_sender, app::ToApp::PeerConnected(peer_id));
+                }
+                self.connected(peer_id);
+                for message in self.outbound.values_mut().filter(|message| {
+                    message.peer_id == peer_id
+                        && message.status == app::messages::MessageStatus::Queued
+                }) {
+                    message.status = app::messages::MessageStatus::Sent;
+                    send_to_app(
+                        &self.app_sender,
+                        app::ToApp::MessageStatusChanged {
+                            message_id: message.message_id,
+                            to: peer_id,
+                            status: message.status,
+                        },
+                    );
+                }
+            }
+            SwarmEvent::ConnectionClosed {
+                peer_id,
+                connection_id,
+                endpoint,
+                num_established,
+                ..
+            } => {
+                if !endpoint.is_relayed()
+                    && let Some(direct) = self.direct_connections.get_mut(&peer_id)
+                {
+                    *direct -= 1;
+                    if *direct == 0 {
+                        self.direct_connections.remove(&peer_id);
+                    }
+                }
+                if self.address_book.is_contact(peer_id)
+                    && !self.direct_connections.contains_key(&peer_id)
+                {
+                    self.reconnects.schedule(peer_id);
+                }
+                if num_established == 0 {
+                    send_to_app(&self.app_sender, app::ToApp::PeerDisconnected(peer_id));
+                }
+                self.ping_connection_closed(peer_id, connection_id, num_established);
+            }
+            SwarmEvent::OutgoingConnectionError {
+                peer_id: Some(peer_id),
+                connection_id,
+                error,
+            } => {
+                tracing::warn!("Failed to dial peer [{peer_id}]: {error}");
+                if let DialError::Transport(errors) = &error {
+                    for (addr, _) in errors {
+                        self.address_book.failed(peer_id, addr.clone());
+                    }
+                }
+                match self.dials.remove(&connection_id) {
+                    Some(peer_id) => {
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if let DialError::Transport(errors) = &error {
+                            for (addr, err) in errors {
+                                if let Some(err) = private_network::private_network_error(err) {
+                                    send_to_app(
+                                        &self.app_sender,
+                                        app::ToApp::Error(format!(
+                                            "Failed to connect to peer [{peer_id}] at [{addr}]: {err}"
+                                        )),
+                                    );
+                                }
+                            }
+                        }
+                        self.dial_failed(peer_id);
+                    }
+                    None => send_to_app(&self.app_sender, app::ToApp::Error(error.to_string())),
+                }
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            SwarmEvent::IncomingConnectionError {
+                send_back_addr,
+                error,
+                ..
+            } => match private_network::private_network_error(&error) {
+                Some(err) => send_to_app(
+                    &self.app_sender,
+                    app::ToApp::Error(format!(
+                        "Rejected connection from [{send_back_addr}]: {err}"
+                    )),
+                ),
+                None => {
+                    tracing::debug!("Incoming connection from [{send_back_addr}] failed: {error}")
+                }
+            },
+            SwarmEvent::ExternalAddrConfirmed { address } => {
+                tracing::info!("External address confirmed: {address}");
+                self.external_address_added(address);
+                self.external_address_confirmed();
             }
+            SwarmEvent::ExternalAddrExpired { address } => self.external_address_expired(address),
+            SwarmEvent::ListenerClosed {
+                listener_id,
+                addresses,
+                ..
+            } => {
+                self.reservation_closed(listener_id);
+                self.listener_closed(&addresses);
+            }
+            SwarmEvent::NewExternalAddrOfPeer { peer_id, address } => {
+                self.address_book.observed(peer_id, address);
+            }
+            event => tracing::debug!("Swarm Event: {event:?}"),
         }
     }
 }
 
+/// A direct message handed to the request-response protocol.
+struct OutboundMessage {
+    peer_id: PeerId,
+    message_id: app::messages::MessageId,
+    status: app::messages::MessageStatus,
+    responder: behaviour::Responder,
+}
+
+/// Bounded set of the most recently received message ids.
+#[derive(Default)]
+struct RecentMessages {
+    ids: HashSet<app::messages::MessageId>,
+    order: VecDeque<app::messages::MessageId>,
+}
+
+impl RecentMessages {
+    fn contains(&self, message_id: &app::messages::MessageId) -> bool {
+        self.ids.contains(message_id)
+    }
+
+    /// Remembers `message_id`, returning whether it was new.
+    fn insert(&mut self, message_id: app::messages::MessageId) -> bool {
+        if !self.ids.insert(message_id) {
+            return false;
+        }
+        self.order.push_back(message_id);
+        if self.order.len() > RECENT_MESSAGES
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.ids.remove(&oldest);
+        }
+        true
+    }
+}
+
+/// Reports the outcome of a command, falling back to an error event if nobody waits for it.
+fn respond(
+    app_sender: &mpsc::UnboundedSender<app::ToApp>,
+    responder: behaviour::Responder,
+    result: Result<(), app::CommandError>,
+) {
+    if let Some(err) = responder.respond(result) {
+        send_to_app(app_sender, app::ToApp::Error(err.to_string()));
+    }
+}
+
+fn send_to_app(app_sender: &mpsc::UnboundedSender<app::ToApp>, event: app::ToApp) {
+    if app_sender.unbounded_send(event).is_err() {
+        tracing::debug!("Dropping app event, the chat app is gone");
+    }
+}
+
 #[cfg(target_arch = "wasm32")]
 fn build_swarm(
     keypair: libp2p::identity::Keypair,
+    config: &config::NetworkConfig,
 ) -> Result<Swarm<behaviour::ChatBehaviour>, app::error::ChatAppError> {
     use libp2p::Transport;
     use libp2p::webrtc_websys;
@@ -95,49 +1029,110 @@ fn build_swarm(
 
     let builder = SwarmBuilder::with_existing_identity(keypair)
         .with_wasm_bindgen()
-        .with_other_transport(|key| webrtc_websys::Transport::new(webrtc_websys::Config::new(&key)))
+        .with_other_transport(|key| webrtc_websys::Transport::new(webrtc_websys::Config::new(key)))
         .unwrap() // this is Infallible so this is safe
         .with_other_transport(|key| {
             websocket_websys::Transport::default()
                 .upgrade(libp2p::core::upgrade::Version::V1)
-                .authenticate(noise::Config::new(&key).unwrap())
+                .authenticate(noise::Config::new(key).unwrap())
                 .multiplex(yamux::Config::default())
                 .boxed()
         })
         .unwrap() // this is Infallible so this is safe
         .with_relay_client(noise::Config::new, yamux::Config::default)?
-        .with_behaviour(|keypair, relay| behaviour::ChatBehaviour::new(keypair, Some(relay)))
-        .unwrap(); // this is Infallible so this is safe
+        .with_behaviour(|keypair, relay| {
+            behaviour::ChatBehaviour::new(keypair, config.relay.then_some(relay), config)
+        })?
+        .with_swarm_config(|swarm_config| {
+            swarm_config.with_idle_connection_timeout(config.idle_connection_timeout)
+        });
 
     Ok(builder.build())
 }
 
-#[cfg(target_arch = "wasm32")]
-fn spawn_swarm_loop<F: Future<Output = ()> + Send + 'static>(future: F) {
-    wasm_bindgen_futures::spawn_local(future);
-}
-
 #[cfg(not(target_arch = "wasm32"))]
 fn build_swarm(
     keypair: libp2p::identity::Keypair,
+    config: &config::NetworkConfig,
 ) -> Result<Swarm<behaviour::ChatBehaviour>, app::error::ChatAppError> {
+    let noise = noise::Config::new(&keypair)?;
+    let webrtc_certificate = config
+        .webrtc_certificate
+        .clone()
+        .unwrap_or_else(transport::generate_webrtc_certificate);
+    let transport = transport::native_transport(
+        &keypair,
+        noise,
+        config.pre_shared_key,
+        config.websocket_tls.clone(),
+        webrtc_certificate,
+    )?;
     let builder = SwarmBuilder::with_existing_identity(keypair)
         .with_tokio()
-        .with_tcp(
-            libp2p::tcp::Config::new(),
-            noise::Config::new,
-            yamux::Config::default,
-        )?
-        .with_quic()
+        .with_other_transport(|_| transport)
+        .unwrap() // this is Infallible so this is safe
         .with_dns()?
         .with_relay_client(noise::Config::new, yamux::Config::default)?
-        .with_behaviour(|keypair, relay| behaviour::ChatBehaviour::new(keypair, Some(relay)))
-        .unwrap(); // this is Infallible so this is safe
+        .with_behaviour(|keypair, relay| {
+            behaviour::ChatBehaviour::new(keypair, config.relay.then_some(relay), config)
+        })?
+        .with_swarm_config(|swarm_config| {
+            swarm_config.with_idle_connection_timeout(config.idle_connection_timeout)
+        });
 
     Ok(builder.build())
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-fn spawn_swarm_loop<F: Future<Output = ()> + Send + 'static>(future: F) {
-    tokio::task::spawn(future);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::messages::MessageId;
+
+    fn message_ids(count: usize) -> Vec<MessageId> {
+        (0..count as u64)
+            .map(|timestamp| MessageId::with_timestamp(timestamp).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn recent_messages_detect_duplicates() {
+        let mut received = RecentMessages::default();
+        let [first, second] = message_ids(2).try_into().unwrap();
+
+        assert!(!received.contains(&first));
+        assert!(received.insert(first));
+        assert!(received.contains(&first));
+        assert!(!received.insert(first));
+        assert!(!received.contains(&second));
+        assert!(received.insert(second));
+        assert_eq!(received.order.len(), 2);
+    }
+
+    #[test]
+    fn recent_messages_forget_the_oldest_at_capacity() {
+        let mut received = RecentMessages::default();
+        let ids = message_ids(RECENT_MESSAGES + 2);
+        for message_id in &ids[..RECENT_MESSAGES] {
+            assert!(received.insert(*message_id));
+        }
+        assert!(
+            ids[..RECENT_MESSAGES]
+                .iter()
+                .all(|id| received.contains(id))
+        );
+
+        assert!(received.insert(ids[RECENT_MESSAGES]));
+        assert!(!received.contains(&ids[0]));
+        assert!(received.contains(&ids[1]));
+        // a duplicate does not push anything out
+        assert!(!received.insert(ids[1]));
+        assert!(received.contains(&ids[2]));
+
+        assert!(received.insert(ids[RECENT_MESSAGES + 1]));
+        assert!(!received.contains(&ids[1]));
+        assert_eq!(received.ids.len(), RECENT_MESSAGES);
+        assert_eq!(received.order.len(), RECENT_MESSAGES);
+        // an evicted message counts as new again
+        assert!(received.insert(ids[0]));
+    }
 }
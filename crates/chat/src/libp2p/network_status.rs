@@ -0,0 +1,330 @@
+#[cfg(not(target_arch = "wasm32"))]
+use libp2p::upnp;
+use libp2p::{Multiaddr, PeerId, autonat, dcutr, multiaddr::Protocol, relay};
+
+use super::{SwarmTask, send_to_app};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::app::network::UpnpStatus;
+use crate::app::{
+    self,
+    network::{HolePunch, NatStatus, NetworkStatus},
+};
+
+/// The network status reported to the app. Every update returns whether it changed the status,
+/// as only real changes are reported.
+pub(super) struct Status(NetworkStatus);
+
+impl Status {
+    pub fn new(status: NetworkStatus) -> Self {
+        Self(status)
+    }
+
+    pub fn get(&self) -> &NetworkStatus {
+        &self.0
+    }
+
+    fn update(&mut self, update: impl FnOnce(&mut NetworkStatus)) -> bool {
+        let mut status = self.0.clone();
+        update(&mut status);
+        let changed = status != self.0;
+        self.0 = status;
+        changed
+    }
+
+    fn reachability_changed(&mut self, new: autonat::NatStatus) -> bool {
+        let reachability = match new {
+            autonat::NatStatus::Public(addr) => NatStatus::Public(addr),
+            autonat::NatStatus::Private => NatStatus::Private,
+            autonat::NatStatus::Unknown => NatStatus::Unknown,
+        };
+        self.update(|status| status.reachability = reachability)
+    }
+
+    /// Circuit addresses belong to the reservation at their relay, all others are external
+    /// addresses.
+    fn external_address_added(&mut self, address: Multiaddr) -> bool {
+        self.update(|status| match circuit_relay(&address) {
+            Some(relay) => {
+                let addresses = status.relay_reservations.entry(relay).or_default();
+                if !addresses.contains(&address) {
+                    addresses.push(address);
+                }
+            }
+            None if !status.external_addresses.contains(&address) => {
+                status.external_addresses.push(address);
+            }
+            None => {}
+        })
+    }
+
+    fn external_address_expired(&mut self, address: &Multiaddr) -> bool {
+        self.update(|status| match circuit_relay(address) {
+            Some(relay) => {
+                if let Some(addresses) = status.relay_reservations.get_mut(&relay) {
+                    addresses.retain(|addr| addr != address);
+                    if addresses.is_empty() {
+                        status.relay_reservations.remove(&relay);
+                    }
+                }
+            }
+            None => status.external_addresses.retain(|addr| addr != address),
+        })
+    }
+
+    /// A closed circuit listener ends the reservation at its relay, even one that was never
+    /// confirmed.
+    fn listener_closed(&mut self, addresses: &[Multiaddr]) -> bool {
+        self.update(|status| {
+            for relay in addresses.iter().filter_map(circuit_relay) {
+                status.relay_reservations.remove(&relay);
+            }
+        })
+    }
+
+    fn reservation_accepted(&mut self, relay: PeerId) -> bool {
+        self.update(|status| {
+            status.relay_reservations.entry(relay).or_default();
+        })
+    }
+
+    fn hole_punched(&mut self, peer_id: PeerId, hole_punch: HolePunch) -> bool {
+        self.update(|status| {
+            status.hole_punches.insert(peer_id, hole_punch);
+        })
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn upnp_event(&mut self, event: upnp::Event) -> bool {
+        self.update(|status| {
+            status.upnp = match (event, std::mem::take(&mut status.upnp)) {
+                (upnp::Event::NewExternalAddr(addr), UpnpStatus::Mapped(mut addrs)) => {
+                    if !addrs.contains(&addr) {
+                        addrs.push(addr);
+                    }
+                    UpnpStatus::Mapped(addrs)
+                }
+                (upnp::Event::NewExternalAddr(addr), _) => UpnpStatus::Mapped(vec![addr]),
+                (upnp::Event::ExpiredExternalAddr(addr), UpnpStatus::Mapped(mut addrs)) => {
+                    addrs.retain(|mapped| *mapped != addr);
+                    if addrs.is_empty() {
+                        // the mapping is retried until the gateway is gone
+                        UpnpStatus::Searching
+                    } else {
+                        UpnpStatus::Mapped(addrs)
+                    }
+                }
+                (upnp::Event::ExpiredExternalAddr(_), upnp) => upnp,
+                (upnp::Event::GatewayNotFound, _) => UpnpStatus::GatewayNotFound,
+                (upnp::Event::NonRoutableGateway, _) => UpnpStatus::NonRoutableGateway,
+            };
+        })
+    }
+}
+
+impl SwarmTask {
+    /// Reports the network status to the app, after an update `changed` it.
+    fn network_status_updated(&self, changed: bool) {
+        if changed {
+            send_to_app(
+                &self.app_sender,
+                app::ToApp::NetworkStatusChanged(self.network_status.get().clone()),
+            );
+        }
+    }
+
+    pub(super) fn reachability_changed(
+        &mut self,
+        old: autonat::NatStatus,
+        new: autonat::NatStatus,
+    ) {
+        tracing::info!("NAT status changed from {old:?} to {new:?}");
+        let changed = self.network_status.reachability_changed(new);
+        self.network_status_updated(changed);
+        self.reserve_relays();
+    }
+
+    /// Circuit addresses are confirmed by the relay client once a reservation was accepted, all
+    /// others by autonat, UPnP or the config.
+    pub(super) fn external_address_added(&mut self, address: Multiaddr) {
+        let changed = self.network_status.external_address_added(address);
+        self.network_status_updated(changed);
+    }
+
+    pub(super) fn external_address_expired(&mut self, address: Multiaddr) {
+        tracing::info!("External address expired: {address}");
+        let changed = self.network_status.external_address_expired(&address);
+        self.network_status_updated(changed);
+    }
+
+    pub(super) fn listener_closed(&mut self, addresses: &[Multiaddr]) {
+        let changed = self.network_status.listener_closed(addresses);
+        self.network_status_updated(changed);
+    }
+
+    pub(super) fn handle_relay_client_event(&mut self, event: relay::client::Event) {
+        match event {
+            relay::client::Event::ReservationReqAccepted {
+                relay_peer_id,
+                renewal,
+                ..
+            } => {
+                if renewal {
+                    tracing::debug!("Relay reservation at [{relay_peer_id}] renewed");
+                } else {
+                    tracing::info!("Relay reservation at [{relay_peer_id}] accepted");
+                }
+                let changed = self.network_status.reservation_accepted(relay_peer_id);
+                self.network_status_updated(changed);
+            }
+            relay::client::Event::OutboundCircuitEstablished { relay_peer_id, .. } => {
+                tracing::debug!("Circuit through relay [{relay_peer_id}] established");
+            }
+            relay::client::Event::InboundCircuitEstablished { src_peer_id, .. } => {
+                tracing::debug!("Circuit from [{src_peer_id}] established");
+            }
+        }
+    }
+
+    pub(super) fn handle_dcutr_event(&mut self, event: dcutr::Event) {
+        let peer_id = event.remote_peer_id;
+        let hole_punch = match event.result {
+            Ok(_) => {
+                tracing::info!("Hole punched to [{peer_id}]");
+                HolePunch::Succeeded
+            }
+            Err(err) => {
+                tracing::info!("Hole punching to [{peer_id}] failed: {err}");
+                HolePunch::Failed(err.to_string())
+            }
+        };
+        let changed = self.network_status.hole_punched(peer_id, hole_punch);
+        self.network_status_updated(changed);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(super) fn handle_upnp_event(&mut self, event: upnp::Event) {
+        match &event {
+            upnp::Event::NewExternalAddr(addr) => tracing::info!("UPnP mapped [{addr}]"),
+            upnp::Event::ExpiredExternalAddr(addr) => {
+                tracing::info!("UPnP mapping of [{addr}] expired")
+            }
+            upnp::Event::GatewayNotFound => tracing::info!("No UPnP gateway found"),
+            upnp::Event::NonRoutableGateway => {
+                tracing::info!("UPnP gateway is not publicly routable")
+            }
+        }
+        let changed = self.network_status.upnp_event(event);
+        self.network_status_updated(changed);
+    }
+}
+
+/// The relay a circuit address leads through, `None` for any other address.
+fn circuit_relay(addr: &Multiaddr) -> Option<PeerId> {
+    let mut relay = None;
+    for protocol in addr.iter() {
+        match protocol {
+            Protocol::P2p(peer_id) => relay = Some(peer_id),
+            Protocol::P2pCircuit => return relay,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address(port: u16) -> Multiaddr {
+        format!("/ip4/203.0.113.7/tcp/{port}").parse().unwrap()
+    }
+
+    fn circuit(relay: PeerId) -> Multiaddr {
+        address(4001)
+            .with(Protocol::P2p(relay))
+            .with(Protocol::P2pCircuit)
+            .with(Protocol::P2p(PeerId::random()))
+    }
+
+    #[test]
+    fn reachability_changes_once() {
+        let mut status = Status::new(NetworkStatus::default());
+        assert!(!status.reachability_changed(autonat::NatStatus::Unknown));
+        assert!(status.reachability_changed(autonat::NatStatus::Private));
+        assert!(!status.reachability_changed(autonat::NatStatus::Private));
+        assert!(status.reachability_changed(autonat::NatStatus::Public(address(4001))));
+        assert!(!status.reachability_changed(autonat::NatStatus::Public(address(4001))));
+        assert!(status.reachability_changed(autonat::NatStatus::Public(address(4002))));
+        assert_eq!(status.get().reachability, NatStatus::Public(address(4002)));
+    }
+
+    #[test]
+    fn external_addresses_change_once() {
+        let mut status = Status::new(NetworkStatus::default());
+        assert!(status.external_address_added(address(4001)));
+        assert!(!status.external_address_added(address(4001)));
+        assert!(status.external_address_added(address(4002)));
+        assert_eq!(
+            status.get().external_addresses,
+            [address(4001), address(4002)]
+        );
+
+        assert!(status.external_address_expired(&address(4001)));
+        assert!(!status.external_address_expired(&address(4001)));
+        assert_eq!(status.get().external_addresses, [address(4002)]);
+        assert!(status.get().relay_reservations.is_empty());
+    }
+
+    #[test]
+    fn circuit_addresses_belong_to_their_reservation() {
+        let mut status = Status::new(NetworkStatus::default());
+        let relay = PeerId::random();
+        assert!(status.reservation_accepted(relay));
+        assert!(!status.reservation_accepted(relay));
+
+        let circuit = circuit(relay);
+        assert!(status.external_address_added(circuit.clone()));
+        assert!(!status.external_address_added(circuit.clone()));
+        assert!(status.get().external_addresses.is_empty());
+        assert_eq!(
+            status.get().relay_reservations[&relay],
+            vec![circuit.clone()]
+        );
+
+        // the reservation ends with its last circuit address or its listener
+        assert!(status.external_address_expired(&circuit));
+        assert!(status.get().relay_reservations.is_empty());
+        assert!(status.reservation_accepted(relay));
+        assert!(!status.listener_closed(&[address(4001)]));
+        assert!(status.listener_closed(std::slice::from_ref(&circuit)));
+        assert!(!status.listener_closed(&[circuit]));
+    }
+
+    #[test]
+    fn hole_punches_change_once_per_outcome() {
+        let mut status = Status::new(NetworkStatus::default());
+        let peer_id = PeerId::random();
+        assert!(status.hole_punched(peer_id, HolePunch::Succeeded));
+        assert!(!status.hole_punched(peer_id, HolePunch::Succeeded));
+        assert!(status.hole_punched(peer_id, HolePunch::Failed("timeout".to_string())));
+        assert!(!status.hole_punched(peer_id, HolePunch::Failed("timeout".to_string())));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn upnp_mappings_change_once() {
+        let mut status = Status::new(NetworkStatus {
+            upnp: UpnpStatus::Searching,
+            ..Default::default()
+        });
+        assert!(status.upnp_event(upnp::Event::NewExternalAddr(address(4001))));
+        assert!(!status.upnp_event(upnp::Event::NewExternalAddr(address(4001))));
+        assert!(status.upnp_event(upnp::Event::NewExternalAddr(address(4002))));
+        assert!(status.upnp_event(upnp::Event::ExpiredExternalAddr(address(4001))));
+        assert!(!status.upnp_event(upnp::Event::ExpiredExternalAddr(address(4001))));
+        assert!(status.upnp_event(upnp::Event::ExpiredExternalAddr(address(4002))));
+        assert_eq!(status.get().upnp, UpnpStatus::Searching);
+        assert!(status.upnp_event(upnp::Event::GatewayNotFound));
+        assert!(!status.upnp_event(upnp::Event::GatewayNotFound));
+    }
+}
@@ -0,0 +1,247 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use libp2p::{PeerId, ping, swarm::ConnectionId};
+
+use super::{SwarmTask, send_to_app};
+use crate::app::{self, network::Latency};
+
+/// Number of round trip times kept per peer.
+const RTT_HISTORY: usize = 10;
+
+/// Pings in a row going unanswered after which a connection counts as dead, even though the
+/// transport did not notice yet.
+const MAX_PING_FAILURES: u32 = 3;
+
+/// Latency to the connected peers and the liveness of each connection to them.
+#[derive(Default)]
+pub(super) struct Pings {
+    latencies: HashMap<PeerId, Latency>,
+    /// Pings in a row that failed on each connection.
+    failures: HashMap<ConnectionId, u32>,
+    /// Connections closed for being dead, whose peer is reached again if no other connection to
+    /// it is left once they are closed.
+    redials: HashSet<ConnectionId>,
+}
+
+impl Pings {
+    /// Records the outcome of a ping on `connection`, returning the latency to the peer and
+    /// whether the connection is dead. `None` if the peer does not answer pings at all, which is
+    /// no sign of a dead connection.
+    fn record(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        result: &Result<Duration, ping::Failure>,
+    ) -> Option<(&Latency, bool)> {
+        let latency = self.latencies.entry(peer_id).or_default();
+        let dead = match result {
+            Ok(rtt) => {
+                self.failures.remove(&connection);
+                latency.failures = 0;
+                latency.rtts.push(*rtt);
+                if latency.rtts.len() > RTT_HISTORY {
+                    latency.rtts.remove(0);
+                }
+                false
+            }
+            Err(ping::Failure::Unsupported) => return None,
+            Err(_) => {
+                latency.failures += 1;
+                let failures = self.failures.entry(connection).or_default();
+                *failures += 1;
+                let dead = *failures >= MAX_PING_FAILURES;
+                if dead {
+                    self.failures.remove(&connection);
+                    self.redials.insert(connection);
+                }
+                dead
+            }
+        };
+        Some((latency, dead))
+    }
+
+    /// Forgets about a closed connection, returning whether its peer is to be redialed as it was
+    /// closed for being dead and no other connection to it is left.
+    fn closed(&mut self, peer_id: PeerId, connection: ConnectionId, remaining: u32) -> bool {
+        self.failures.remove(&connection);
+        let dead = self.redials.remove(&connection);
+        if remaining > 0 {
+            return false;
+        }
+        self.latencies.remove(&peer_id);
+        dead
+    }
+}
+
+impl SwarmTask {
+    pub(super) fn handle_ping_event(&mut self, event: ping::Event) {
+        let ping::Event {
+            peer: peer_id,
+            connection,
+            result,
+        } = event;
+        match &result {
+            Ok(rtt) => tracing::trace!("Ping to [{peer_id}] took {rtt:?}"),
+            Err(err) => tracing::debug!("Ping to [{peer_id}] failed: {err}"),
+        }
+        let Some((latency, dead)) = self.pings.record(peer_id, connection, &result) else {
+            return;
+        };
+        let latency = latency.clone();
+        if dead {
+            tracing::warn!("Connection to [{peer_id}] stopped answering pings, closing it");
+            self.swarm.close_connection(connection);
+        }
+        send_to_app(
+            &self.app_sender,
+            app::ToApp::PeerLatency { peer_id, latency },
+        );
+    }
+
+    /// Forgets about a closed connection, redialing its peer if it was closed for being dead and
+    /// no other connection to it is left.
+    pub(super) fn ping_connection_closed(
+        &mut self,
+        peer_id: PeerId,
+        connection: ConnectionId,
+        remaining: u32,
+    ) {
+        // contacts are reconnected to anyway
+        if self.pings.closed(peer_id, connection, remaining)
+            && !self.address_book.is_contact(peer_id)
+        {
+            self.redial(peer_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn answered(millis: u64) -> Result<Duration, ping::Failure> {
+        Ok(Duration::from_millis(millis))
+    }
+
+    #[test]
+    fn connection_is_dead_after_failures_in_a_row() {
+        let mut pings = Pings::default();
+        let peer_id = PeerId::random();
+        let connection = ConnectionId::new_unchecked(1);
+
+        for failures in 1..MAX_PING_FAILURES {
+            let (latency, dead) = pings
+                .record(peer_id, connection, &Err(ping::Failure::Timeout))
+                .unwrap();
+            assert_eq!(latency.failures, failures);
+            assert!(!dead);
+        }
+        let (_, dead) = pings
+            .record(peer_id, connection, &Err(ping::Failure::Timeout))
+            .unwrap();
+        assert!(dead);
+
+        assert!(pings.closed(peer_id, connection, 0));
+        assert!(pings.latencies.is_empty());
+        assert!(pings.redials.is_empty());
+    }
+
+    #[test]
+    fn dead_connection_is_not_redialed_while_another_is_left() {
+        let mut pings = Pings::default();
+        let peer_id = PeerId::random();
+        let (dead, alive) = (
+            ConnectionId::new_unchecked(1),
+            ConnectionId::new_unchecked(2),
+        );
+
+        for _ in 0..MAX_PING_FAILURES {
+            pings.record(peer_id, dead, &Err(ping::Failure::Timeout));
+        }
+        assert!(!pings.closed(peer_id, dead, 1));
+        assert!(pings.redials.is_empty());
+
+        // the other connection closing later for another reason does not redial
+        assert!(!pings.closed(peer_id, alive, 0));
+    }
+
+    #[test]
+    fn answered_ping_resets_the_failures() {
+        let mut pings = Pings::default();
+        let peer_id = PeerId::random();
+        let connection = ConnectionId::new_unchecked(1);
+
+        for _ in 1..MAX_PING_FAILURES {
+            pings.record(peer_id, connection, &Err(ping::Failure::Timeout));
+        }
+        let (latency, dead) = pings.record(peer_id, connection, &answered(20)).unwrap();
+        assert_eq!(latency.failures, 0);
+        assert!(!dead);
+        for _ in 1..MAX_PING_FAILURES {
+            let (_, dead) = pings
+                .record(peer_id, connection, &Err(ping::Failure::Timeout))
+                .unwrap();
+            assert!(!dead);
+        }
+
+        // closed for another reason, so not redialed
+        assert!(!pings.closed(peer_id, connection, 0));
+    }
+
+    #[test]
+    fn failures_are_counted_per_connection() {
+        let mut pings = Pings::default();
+        let peer_id = PeerId::random();
+        let (first, second) = (
+            ConnectionId::new_unchecked(1),
+            ConnectionId::new_unchecked(2),
+        );
+
+        for _ in 1..MAX_PING_FAILURES {
+            pings.record(peer_id, first, &Err(ping::Failure::Timeout));
+        }
+        let (latency, dead) = pings
+            .record(peer_id, second, &Err(ping::Failure::Timeout))
+            .unwrap();
+        assert!(!dead);
+        assert_eq!(latency.failures, MAX_PING_FAILURES);
+    }
+
+    #[test]
+    fn unsupported_pings_are_ignored() {
+        let mut pings = Pings::default();
+        let peer_id = PeerId::random();
+        let connection = ConnectionId::new_unchecked(1);
+
+        for _ in 0..MAX_PING_FAILURES {
+            assert!(
+                pings
+                    .record(peer_id, connection, &Err(ping::Failure::Unsupported))
+                    .is_none()
+            );
+        }
+        assert!(pings.failures.is_empty());
+        assert!(!pings.closed(peer_id, connection, 0));
+    }
+
+    #[test]
+    fn only_the_latest_round_trip_times_are_kept() {
+        let mut pings = Pings::default();
+        let peer_id = PeerId::random();
+        let connection = ConnectionId::new_unchecked(1);
+
+        for millis in 0..RTT_HISTORY as u64 + 2 {
+            pings.record(peer_id, connection, &answered(millis));
+        }
+        let rtts = &pings.latencies[&peer_id].rtts;
+        assert_eq!(rtts.len(), RTT_HISTORY);
+        assert_eq!(rtts[0], Duration::from_millis(2));
+        assert_eq!(
+            rtts[RTT_HISTORY - 1],
+            Duration::from_millis(RTT_HISTORY as u64 + 1)
+        );
+    }
+}
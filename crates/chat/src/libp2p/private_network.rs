@@ -0,0 +1,93 @@
+use std::{io, pin::pin, time::Duration};
+
+use futures::{
+    AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt,
+    future::{self, Either},
+};
+use libp2p::pnet::{PnetConfig, PnetError, PnetOutput, PreSharedKey};
+
+/// Time a peer has to complete the handshake, peers outside of any private network never do.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sent by both sides right after the pnet handshake, the other side only reads it back if it
+/// uses the same key.
+const KEY_CHECK: &[u8; 16] = b"/streuen/pnet/1\n";
+
+/// Why a connection was kept out of the private network.
+#[derive(Debug, thiserror::Error)]
+pub enum PrivateNetworkError {
+    #[error("Pre-shared key handshake failed, the peer is probably not part of the network: {0}")]
+    Handshake(#[source] io::Error),
+    #[error("The peer uses another pre-shared key or none.")]
+    KeyMismatch,
+    #[error("The peer did not complete the pre-shared key handshake, it does not use one.")]
+    Timeout,
+}
+
+/// Carries a [`PrivateNetworkError`] as its source, so the error is still found behind wrappers
+/// which skip to the source of the error they wrap, like [`io::Error`] does.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+struct Rejected(#[source] PrivateNetworkError);
+
+/// Encrypts `socket` with `key` and makes sure the other side uses the same key.
+pub(super) async fn handshake<S>(socket: S, key: PreSharedKey) -> io::Result<PnetOutput<S>>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let handshake = async {
+        let mut output = PnetConfig::new(key)
+            .handshake(socket)
+            .await
+            .map_err(|err| match err {
+                PnetError::HandshakeError(err) | PnetError::IoError(err) => {
+                    PrivateNetworkError::Handshake(err)
+                }
+            })?;
+        output
+            .write_all(KEY_CHECK)
+            .await
+            .map_err(PrivateNetworkError::Handshake)?;
+        output
+            .flush()
+            .await
+            .map_err(PrivateNetworkError::Handshake)?;
+        let mut check = [0; KEY_CHECK.len()];
+        output
+            .read_exact(&mut check)
+            .await
+            .map_err(PrivateNetworkError::Handshake)?;
+        if &check != KEY_CHECK {
+            return Err(PrivateNetworkError::KeyMismatch);
+        }
+        Ok(output)
+    };
+    let result = match future::select(
+        pin!(handshake),
+        futures_timer::Delay::new(HANDSHAKE_TIMEOUT),
+    )
+    .await
+    {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(PrivateNetworkError::Timeout),
+    };
+    result.map_err(|err| io::Error::other(Rejected(err)))
+}
+
+/// Finds the reason a connection was kept out of the private network among the causes of `error`.
+pub(super) fn private_network_error<'a>(
+    error: &'a (dyn std::error::Error + 'static),
+) -> Option<&'a PrivateNetworkError> {
+    let mut error = Some(error);
+    while let Some(err) = error {
+        if let Some(err) = err.downcast_ref::<PrivateNetworkError>() {
+            return Some(err);
+        }
+        // the source of an io error skips the error it wraps
+        error = match err.downcast_ref::<io::Error>() {
+            Some(err) => err.get_ref().map(|err| err as _),
+            None => err.source(),
+        };
+    }
+    None
+}
@@ -0,0 +1,111 @@
+use std::{collections::HashMap, time::Duration};
+
+use libp2p::PeerId;
+use web_time::Instant;
+
+/// Delay before the first attempt to reconnect, doubled with every further attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+const MAX_BACKOFF: Duration = Duration::from_secs(10 * 60);
+
+/// Schedules the attempts to reconnect to contacts without a direct connection.
+#[derive(Default)]
+pub(super) struct Reconnects {
+    scheduled: HashMap<PeerId, Schedule>,
+}
+
+struct Schedule {
+    attempts: u32,
+    next: Instant,
+}
+
+impl Reconnects {
+    /// Schedules attempts to reach `peer_id`, unless they are scheduled already.
+    pub fn schedule(&mut self, peer_id: PeerId) {
+        self.scheduled.entry(peer_id).or_insert_with(|| Schedule {
+            attempts: 0,
+            next: Instant::now() + backoff(0),
+        });
+    }
+
+    pub fn cancel(&mut self, peer_id: PeerId) {
+        self.scheduled.remove(&peer_id);
+    }
+
+    /// The peers due for an attempt, whose next attempt is scheduled after a longer delay.
+    pub fn due(&mut self) -> Vec<PeerId> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (peer_id, schedule) in &mut self.scheduled {
+            if schedule.next <= now {
+                schedule.attempts = schedule.attempts.saturating_add(1);
+                schedule.next = now + backoff(schedule.attempts);
+                due.push(*peer_id);
+            }
+        }
+        due
+    }
+}
+
+/// Delay before the attempt following `attempts` earlier ones, randomized between half and all of
+/// the exponential backoff so contacts dropped at once are not all dialed at once again.
+fn backoff(attempts: u32) -> Duration {
+    let delay = INITIAL_BACKOFF
+        .saturating_mul(1 << attempts.min(16))
+        .min(MAX_BACKOFF);
+    let jitter = getrandom::u64().unwrap_or_default() % (delay.as_millis() as u64 / 2 + 1);
+    delay / 2 + Duration::from_millis(jitter)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples the delay a few times, as the jitter puts each one anywhere in its range.
+    fn backoffs(attempts: u32) -> impl Iterator<Item = Duration> {
+        (0..200).map(move |_| backoff(attempts))
+    }
+
+    #[test]
+    fn backoff_doubles_with_every_attempt() {
+        for attempts in 0..10 {
+            let full = INITIAL_BACKOFF * 2u32.pow(attempts);
+            for delay in backoffs(attempts) {
+                assert!(
+                    full / 2 <= delay && delay <= full,
+                    "{delay:?} after {attempts} attempts"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        // the first attempt whose doubled delay exceeds the maximum
+        let capped = (0..).find(|n| INITIAL_BACKOFF * 2u32.pow(*n) > MAX_BACKOFF);
+        for attempts in [capped.unwrap(), 16, 17, 32, 64, u32::MAX - 1, u32::MAX] {
+            for delay in backoffs(attempts) {
+                assert!(
+                    MAX_BACKOFF / 2 <= delay && delay <= MAX_BACKOFF,
+                    "{delay:?} after {attempts} attempts"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn first_attempt_is_not_due_right_away() {
+        let mut reconnects = Reconnects::default();
+        let peer_id = PeerId::random();
+        reconnects.schedule(peer_id);
+        assert!(reconnects.due().is_empty());
+
+        reconnects.scheduled.get_mut(&peer_id).unwrap().next = Instant::now();
+        assert_eq!(reconnects.due(), [peer_id]);
+        assert!(reconnects.due().is_empty());
+        assert_eq!(reconnects.scheduled[&peer_id].attempts, 1);
+
+        reconnects.cancel(peer_id);
+        assert!(reconnects.scheduled.is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+
+use libp2p::{
+    Multiaddr, PeerId,
+    multiaddr::Protocol,
+    rendezvous::{Cookie, Namespace, client},
+    swarm::dial_opts::{DialOpts, PeerCondition},
+};
+use web_time::Instant;
+
+use super::{SwarmTask, behaviour, respond, send_to_app};
+use crate::app::{self, CommandError, DiscoverySource};
+
+/// Interval at which each rendezvous point is asked for the peers newly registered in our
+/// namespaces.
+const DISCOVER_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delay before a failed registration or an unreachable rendezvous point is tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Our registrations in team namespaces at rendezvous points, along with the discovery of the
+/// other peers registered there.
+#[derive(Default)]
+pub(super) struct Registrations {
+    /// Rendezvous points with the address they are dialed at.
+    points: HashMap<PeerId, Multiaddr>,
+    namespaces: HashSet<Namespace>,
+    /// When each rendezvous point may be dialed again.
+    next_dial: HashMap<PeerId, Instant>,
+    schedules: HashMap<(PeerId, Namespace), Schedule>,
+}
+
+struct Schedule {
+    register_at: Instant,
+    discover_at: Instant,
+    /// Handed back on the next discovery to only learn of newer registrations.
+    cookie: Option<Cookie>,
+}
+
+impl Registrations {
+    fn add_point(&mut self, point: PeerId, address: Multiaddr) {
+        self.points.insert(point, address);
+        for namespace in &self.namespaces {
+            self.schedules
+                .entry((point, namespace.clone()))
+                .or_insert_with(Schedule::due);
+        }
+    }
+
+    /// Adds `namespace`, returning whether it is new.
+    fn add_namespace(&mut self, namespace: Namespace) -> bool {
+        for point in self.points.keys() {
+            self.schedules
+                .entry((*point, namespace.clone()))
+                .or_insert_with(Schedule::due);
+        }
+        self.namespaces.insert(namespace)
+    }
+
+    /// Removes `namespace`, returning the rendezvous points it has to be unregistered from.
+    fn remove_namespace(&mut self, namespace: &Namespace) -> Option<Vec<PeerId>> {
+        if !self.namespaces.remove(namespace) {
+            return None;
+        }
+        self.schedules.retain(|(_, ns), _| ns != namespace);
+        Some(self.points.keys().copied().collect())
+    }
+
+    /// Registers again everywhere, e.g. once we have an address to register with.
+    fn register_all(&mut self) {
+        let now = Instant::now();
+        for schedule in self.schedules.values_mut() {
+            schedule.register_at = now;
+        }
+    }
+
+    fn rendezvous_points(&self) -> impl Iterator<Item = PeerId> + '_ {
+        self.points.keys().copied()
+    }
+
+    /// The rendezvous points not in `connected` which were not dialed lately, along with their
+    /// address. They are not dialed again before [`RETRY_INTERVAL`] passed.
+    fn dials_due(&mut self, connected: &[PeerId]) -> Vec<(PeerId, Multiaddr)> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for (point, address) in &self.points {
+            if !connected.contains(point)
+                && self.next_dial.get(point).is_none_or(|next| *next <= now)
+            {
+                self.next_dial.insert(*point, now + RETRY_INTERVAL);
+                due.push((*point, address.clone()));
+            }
+        }
+        due
+    }
+
+    /// The registrations and discoveries due at the rendezvous points in `connected`. A
+    /// registration is retried after [`RETRY_INTERVAL`] unless it succeeds, a discovery repeated
+    /// after [`DISCOVER_INTERVAL`].
+    fn due(&mut self, connected: &[PeerId]) -> Vec<Due> {
+        let now = Instant::now();
+        let mut due = Vec::new();
+        for ((point, namespace), schedule) in self
+            .schedules
+            .iter_mut()
+            .filter(|((point, _), _)| connected.contains(point))
+        {
+            if schedule.register_at <= now {
+                schedule.register_at = now + RETRY_INTERVAL;
+                due.push(Due::Register(*point, namespace.clone()));
+            }
+            if schedule.discover_at <= now {
+                schedule.discover_at = now + DISCOVER_INTERVAL;
+                due.push(Due::Discover(
+                    *point,
+                    namespace.clone(),
+                    schedule.cookie.clone(),
+                ));
+            }
+        }
+        due
+    }
+
+    /// Refreshes a successful registration once half of its `ttl` in seconds passed.
+    fn registered(&mut self, point: PeerId, namespace: &Namespace, ttl: u64) {
+        if let Some(schedule) = self.schedules.get_mut(&(point, namespace.clone())) {
+            schedule.register_at = Instant::now() + Duration::from_secs(ttl / 2);
+        }
+    }
+
+    /// Keeps the cookie of a discovery to only learn of newer registrations the next time.
+    fn discovered(&mut self, point: PeerId, cookie: Cookie) {
+        if let Some(namespace) = cookie.namespace().cloned()
+            && let Some(schedule) = self.schedules.get_mut(&(point, namespace))
+        {
+            schedule.cookie = Some(cookie);
+        }
+    }
+}
+
+/// Work due at a connected rendezvous point.
+#[derive(Debug, PartialEq)]
+enum Due {
+    Register(PeerId, Namespace),
+    Discover(PeerId, Namespace, Option<Cookie>),
+}
+
+impl Schedule {
+    fn due() -> Self {
+        let now = Instant::now();
+        Self {
+            register_at: now,
+            discover_at: now,
+            cookie: None,
+        }
+    }
+}
+
+impl SwarmTask {
+    /// Registers in our namespaces at the rendezvous point at `addr`, which has to end with its
+    /// `/p2p` peer id.
+    pub(super) fn add_rendezvous_point(
+        &mut self,
+        addr: Multiaddr,
+        responder: behaviour::Responder,
+    ) {
+        let result = if self.swarm.behaviour().rendezvous.as_ref().is_none() {
+            Err(CommandError::RendezvousDisabled)
+        } else if let Some(Protocol::P2p(point)) = addr.iter().last() {
+            tracing::info!("Added rendezvous point [{point}] at [{addr}]");
+            // kept connected rather than dialed again for every discovery
+            self.swarm
+                .behaviour_mut()
+                .keep_alive
+                .keep_alive(point, true);
+            self.registrations.add_point(point, addr);
+            self.registrations.next_dial.remove(&point);
+            self.rendezvous_tick();
+            Ok(())
+        } else {
+            Err(CommandError::InvalidRendezvousPoint(addr))
+        };
+        respond(&self.app_sender, responder, result);
+    }
+
+    /// Registers in `namespace` at every rendezvous point and discovers the peers registered
+    /// there.
+    pub(super) fn register_namespace(
+        &mut self,
+        namespace: String,
+        responder: behaviour::Responder,
+    ) {
+        let result = if self.swarm.behaviour().rendezvous.as_ref().is_none() {
+            Err(CommandError::RendezvousDisabled)
+        } else {
+            match Namespace::new(namespace.clone()) {
+                Ok(ns) => {
+                    if self.registrations.add_namespace(ns) {
+                        tracing::info!("Registering in namespace [{namespace}]");
+                    }
+                    self.rendezvous_tick();
+                    Ok(())
+                }
+                Err(_) => Err(CommandError::InvalidNamespace(namespace)),
+            }
+        };
+        respond(&self.app_sender, responder, result);
+    }
+
+    pub(super) fn unregister_namespace(
+        &mut self,
+        namespace: String,
+        responder: behaviour::Responder,
+    ) {
+        let points = Namespace::new(namespace.clone())
+            .ok()
+            .and_then(|ns| Some((self.registrations.remove_namespace(&ns)?, ns)));
+        let result = match (points, self.swarm.behaviour_mut().rendezvous.as_mut()) {
+            (Some((points, ns)), Some(rendezvous)) => {
+                for point in points {
+                    rendezvous.unregister(ns.clone(), point);
+                }
+                Ok(())
+            }
+            _ => Err(CommandError::NotRegistered(namespace)),
+        };
+        respond(&self.app_sender, responder, result);
+    }
+
+    /// Registers and discovers wherever it is due, dialing the rendezvous points needed for it.
+    pub(super) fn rendezvous_tick(&mut self) {
+        let connected: Vec<PeerId> = self
+            .registrations
+            .rendezvous_points()
+            .filter(|point| self.swarm.is_connected(point))
+            .collect();
+        for (point, address) in self.registrations.dials_due(&connected) {
+            let opts = DialOpts::peer_id(point)
+                .addresses(vec![address])
+                .condition(PeerCondition::DisconnectedAndNotDialing)
+                .build();
+            if let Err(err) = self.swarm.dial(opts) {
+                tracing::debug!("Failed to dial rendezvous point [{point}]: {err}");
+            }
+        }
+        let due = self.registrations.due(&connected);
+        let Some(rendezvous) = self.swarm.behaviour_mut().rendezvous.as_mut() else {
+            return;
+        };
+        for due in due {
+            match due {
+                Due::Register(point, namespace) => {
+                    if let Err(err) = rendezvous.register(namespace.clone(), point, None) {
+                        tracing::debug!("Not registering in namespace [{namespace}] yet: {err}");
+                    }
+                }
+                Due::Discover(point, namespace, cookie) => {
+                    rendezvous.discover(Some(namespace), cookie, None, point);
+                }
+            }
+        }
+    }
+
+    /// Registration might have failed for the lack of an external address so far.
+    pub(super) fn external_address_confirmed(&mut self) {
+        self.registrations.register_all();
+    }
+
+    pub(super) fn handle_rendezvous_event(&mut self, event: client::Event) {
+        match event {
+            client::Event::Registered {
+                rendezvous_node,
+                ttl,
+                namespace,
+            } => {
+                tracing::info!(
+                    "Registered in namespace [{namespace}] at [{rendezvous_node}] for {ttl}s"
+                );
+                self.registrations
+                    .registered(rendezvous_node, &namespace, ttl);
+            }
+            client::Event::RegisterFailed {
+                rendezvous_node,
+                namespace,
+                error,
+            } => {
+                tracing::warn!(
+                    "Failed to register in namespace [{namespace}] at [{rendezvous_node}]: {error:?}"
+                );
+            }
+            client::Event::Discovered {
+                rendezvous_node,
+                registrations,
+                cookie,
+            } => {
+                self.registrations.discovered(rendezvous_node, cookie);
+                let local_peer_id = *self.swarm.local_peer_id();
+                for registration in registrations {
+                    let peer_id = registration.record.peer_id();
+                    if peer_id == local_peer_id {
+                        continue;
+                    }
+                    tracing::info!(
+                        "Peer discovered in namespace [{}]: {peer_id}",
+                        registration.namespace
+                    );
+                    // the addresses were recorded through the swarm already
+                    if !self.swarm.is_connected(&peer_id) {
+                        let _ = self.swarm.dial(
+                            DialOpts::peer_id(peer_id)
+                                .addresses(registration.record.addresses().to_vec())
+                                .build(),
+                        );
+                    }
+                    send_to_app(
+                        &self.app_sender,
+                        app::ToApp::PeerDiscovered {
+                            peer_id,
+                            source: DiscoverySource::Rendezvous(registration.namespace.into()),
+                        },
+                    );
+                }
+            }
+            client::Event::DiscoverFailed {
+                rendezvous_node,
+                namespace,
+                error,
+            } => {
+                tracing::warn!(
+                    "Failed to discover peers in namespace [{}] at [{rendezvous_node}]: {error:?}",
+                    namespace.map(String::from).unwrap_or_default()
+                );
+            }
+            client::Event::Expired { peer } => {
+                tracing::debug!("Rendezvous registration of peer [{peer}] expired");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point() -> (PeerId, Multiaddr) {
+        let point = PeerId::random();
+        let address = "/ip4/127.0.0.1/tcp/62649".parse::<Multiaddr>().unwrap();
+        (point, address.with_p2p(point).unwrap())
+    }
+
+    /// Makes everything scheduled at `point` due right away.
+    fn make_due(registrations: &mut Registrations, point: PeerId) {
+        let now = Instant::now();
+        for ((p, _), schedule) in &mut registrations.schedules {
+            if *p == point {
+                schedule.register_at = now;
+                schedule.discover_at = now;
+            }
+        }
+    }
+
+    #[test]
+    fn namespaces_are_registered_at_every_point() {
+        let mut registrations = Registrations::default();
+        let (first, address) = point();
+        registrations.add_point(first, address);
+        let namespace = Namespace::from_static("team");
+        assert!(registrations.add_namespace(namespace.clone()));
+        assert!(!registrations.add_namespace(namespace.clone()));
+        let (second, address) = point();
+        registrations.add_point(second, address);
+
+        // nothing happens at points we are not connected to
+        assert!(registrations.due(&[]).is_empty());
+        let due = registrations.due(&[first, second]);
+        assert_eq!(due.len(), 4);
+        for point in [first, second] {
+            assert!(due.contains(&Due::Register(point, namespace.clone())));
+            assert!(due.contains(&Due::Discover(point, namespace.clone(), None)));
+        }
+        assert!(registrations.due(&[first, second]).is_empty());
+    }
+
+    #[test]
+    fn registrations_are_refreshed_at_half_their_ttl() {
+        let mut registrations = Registrations::default();
+        let (point, address) = point();
+        let namespace = Namespace::from_static("team");
+        registrations.add_point(point, address);
+        registrations.add_namespace(namespace.clone());
+        registrations.due(&[point]);
+
+        // retried unless it succeeded
+        let key = (point, namespace.clone());
+        let retry_at = registrations.schedules[&key].register_at;
+        assert!(retry_at <= Instant::now() + RETRY_INTERVAL);
+        assert!(retry_at > Instant::now() + RETRY_INTERVAL / 2);
+
+        registrations.registered(point, &namespace, 2 * 60 * 60);
+        let refresh_at = registrations.schedules[&key].register_at;
+        assert!(refresh_at > Instant::now() + Duration::from_secs(59 * 60));
+        assert!(refresh_at <= Instant::now() + Duration::from_secs(60 * 60));
+
+        // an address to register with makes every registration due again
+        registrations.register_all();
+        assert_eq!(
+            registrations.due(&[point]),
+            [Due::Register(point, namespace)]
+        );
+    }
+
+    #[test]
+    fn discoveries_hand_back_the_last_cookie() {
+        let mut registrations = Registrations::default();
+        let (point, address) = point();
+        let namespace = Namespace::from_static("team");
+        registrations.add_point(point, address);
+        registrations.add_namespace(namespace.clone());
+        registrations.due(&[point]);
+
+        let cookie = Cookie::for_namespace(namespace.clone());
+        registrations.discovered(point, cookie.clone());
+        // a cookie of another namespace or point is not ours
+        registrations.discovered(
+            point,
+            Cookie::for_namespace(Namespace::from_static("other")),
+        );
+        registrations.discovered(PeerId::random(), Cookie::for_all_namespaces());
+
+        make_due(&mut registrations, point);
+        assert!(registrations.due(&[point]).contains(&Due::Discover(
+            point,
+            namespace,
+            Some(cookie)
+        )));
+    }
+
+    #[test]
+    fn unregistering_forgets_the_namespace() {
+        let mut registrations = Registrations::default();
+        let (point, address) = point();
+        let namespace = Namespace::from_static("team");
+        registrations.add_point(point, address);
+        registrations.add_namespace(namespace.clone());
+        registrations.add_namespace(Namespace::from_static("other"));
+
+        assert_eq!(
+            registrations.remove_namespace(&namespace),
+            Some(vec![point])
+        );
+        assert_eq!(registrations.remove_namespace(&namespace), None);
+        make_due(&mut registrations, point);
+        assert!(registrations.due(&[point]).iter().all(
+            |due| !matches!(due, Due::Register(_, ns) | Due::Discover(_, ns, _) if *ns == namespace)
+        ));
+    }
+
+    #[test]
+    fn points_are_not_dialed_again_right_away() {
+        let mut registrations = Registrations::default();
+        let (point, address) = point();
+        registrations.add_point(point, address.clone());
+
+        assert!(registrations.dials_due(&[point]).is_empty());
+        assert_eq!(registrations.dials_due(&[]), [(point, address.clone())]);
+        assert!(registrations.dials_due(&[]).is_empty());
+
+        registrations.next_dial.insert(point, Instant::now());
+        assert_eq!(registrations.dials_due(&[]), [(point, address)]);
+    }
+}
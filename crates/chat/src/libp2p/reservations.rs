@@ -0,0 +1,208 @@
+use std::{collections::HashMap, time::Duration};
+
+use libp2p::{Multiaddr, PeerId, core::transport::ListenerId, multiaddr::Protocol};
+use web_time::Instant;
+
+use super::SwarmTask;
+use crate::app::NatStatus;
+
+/// Number of relays a reservation is held at while we cannot be reached directly, so we stay
+/// reachable when one of them goes away.
+const RESERVATIONS: usize = 2;
+
+/// Delay before a relay whose reservation failed or ended is asked again.
+const RESERVATION_RETRY: Duration = Duration::from_secs(60);
+
+/// Reservations at relays, which let other peers reach us through circuit addresses while we are
+/// behind a NAT. The relay client renews them for as long as the connection to the relay lasts,
+/// and peers connecting through them get upgraded to a direct connection by DCUtR where possible.
+#[derive(Default)]
+pub(super) struct Reservations {
+    /// Circuit listeners, keyed by the relay they hold a reservation at.
+    listeners: HashMap<PeerId, ListenerId>,
+    /// When each relay may be asked for a reservation again.
+    next_attempt: HashMap<PeerId, Instant>,
+}
+
+impl Reservations {
+    /// The relays among `relays` to ask for a reservation, those without one whose last attempt
+    /// is long enough ago.
+    fn candidates(&self, relays: impl IntoIterator<Item = PeerId>) -> Vec<PeerId> {
+        let now = Instant::now();
+        relays
+            .into_iter()
+            .filter(|relay| {
+                !self.listeners.contains_key(relay)
+                    && self.next_attempt.get(relay).is_none_or(|next| *next <= now)
+            })
+            .collect()
+    }
+
+    fn is_full(&self) -> bool {
+        self.listeners.len() >= RESERVATIONS
+    }
+
+    /// Records an attempt to obtain a reservation at `relay`, which holds it through `listener_id`
+    /// if listening on the circuit address started.
+    fn attempted(&mut self, relay: PeerId, listener_id: Option<ListenerId>) {
+        self.next_attempt
+            .insert(relay, Instant::now() + RESERVATION_RETRY);
+        if let Some(listener_id) = listener_id {
+            self.listeners.insert(relay, listener_id);
+        }
+    }
+
+    /// Forgets the reservation held through `listener_id`, returning its relay.
+    fn closed(&mut self, listener_id: ListenerId) -> Option<PeerId> {
+        let relay = self
+            .listeners
+            .iter()
+            .find(|(_, listener)| **listener == listener_id)
+            .map(|(relay, _)| *relay)?;
+        self.listeners.remove(&relay);
+        Some(relay)
+    }
+
+    /// Forgets every reservation, returning the listeners to close.
+    fn release(&mut self) -> HashMap<PeerId, ListenerId> {
+        std::mem::take(&mut self.listeners)
+    }
+}
+
+/// Browsers cannot be dialed at all, everyone else only while autonat found them to be private.
+/// An unknown status keeps the reservations held so far.
+fn reservations_needed(reachability: &NatStatus) -> Option<bool> {
+    if cfg!(target_arch = "wasm32") {
+        return Some(true);
+    }
+    match reachability {
+        NatStatus::Private => Some(true),
+        NatStatus::Public(_) => Some(false),
+        NatStatus::Unknown => None,
+    }
+}
+
+impl SwarmTask {
+    /// Obtains reservations at the known relays until enough are held, or releases them all once
+    /// we are reachable directly.
+    pub(super) fn reserve_relays(&mut self) {
+        if !self.relay {
+            return;
+        }
+        match reservations_needed(&self.network_status.get().reachability) {
+            Some(true) => {}
+            Some(false) => return self.release_relays(),
+            None => return,
+        }
+        let mut candidates = self.reservations.candidates(self.relays.keys().copied());
+        // reserving over an existing connection spares the dial
+        candidates.sort_by_key(|relay| !self.swarm.is_connected(relay));
+        for relay in candidates {
+            if self.reservations.is_full() {
+                break;
+            }
+            let Some(addr) = self.relay_address(relay) else {
+                continue;
+            };
+            let circuit = addr.with(Protocol::P2p(relay)).with(Protocol::P2pCircuit);
+            let listener_id = match self.swarm.listen_on(circuit) {
+                Ok(listener_id) => {
+                    tracing::info!("Requesting a reservation at relay [{relay}]");
+                    Some(listener_id)
+                }
+                Err(err) => {
+                    tracing::debug!("Failed to listen through relay [{relay}]: {err}");
+                    None
+                }
+            };
+            self.reservations.attempted(relay, listener_id);
+        }
+    }
+
+    /// The address a relay is asked for a reservation at, which other peers dial it at as part
+    /// of our circuit address.
+    fn relay_address(&self, relay: PeerId) -> Option<Multiaddr> {
+        self.address_book
+            .addresses(relay)
+            .into_iter()
+            .chain(self.relays.get(&relay).into_iter().flatten().cloned())
+            .find(|addr| !addr.iter().any(|protocol| protocol == Protocol::P2pCircuit))
+    }
+
+    fn release_relays(&mut self) {
+        for (relay, listener_id) in self.reservations.release() {
+            tracing::info!("Releasing the reservation at relay [{relay}]");
+            self.swarm.remove_listener(listener_id);
+        }
+    }
+
+    /// A circuit listener closed as its reservation was denied or its relay went away, another
+    /// relay is asked on the next tick.
+    pub(super) fn reservation_closed(&mut self, listener_id: ListenerId) {
+        self.reservations.closed(listener_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relays_are_asked_until_enough_reservations_are_held() {
+        // two reservations are held at most
+        assert_eq!(RESERVATIONS, 2);
+        let mut reservations = Reservations::default();
+        let relays: Vec<PeerId> = (0..3).map(|_| PeerId::random()).collect();
+        assert_eq!(reservations.candidates(relays.clone()), relays);
+
+        reservations.attempted(relays[0], Some(ListenerId::next()));
+        assert!(!reservations.is_full());
+        // a failed attempt is not repeated right away either
+        reservations.attempted(relays[1], None);
+        assert_eq!(reservations.candidates(relays.clone()), [relays[2]]);
+        reservations.attempted(relays[2], Some(ListenerId::next()));
+        assert!(reservations.is_full());
+        assert!(reservations.candidates(relays).is_empty());
+    }
+
+    #[test]
+    fn lost_reservations_are_requested_again_later() {
+        let mut reservations = Reservations::default();
+        let relay = PeerId::random();
+        let listener_id = ListenerId::next();
+        reservations.attempted(relay, Some(listener_id));
+
+        assert_eq!(reservations.closed(ListenerId::next()), None);
+        assert_eq!(reservations.closed(listener_id), Some(relay));
+        assert_eq!(reservations.closed(listener_id), None);
+        assert!(reservations.candidates([relay]).is_empty());
+
+        reservations.next_attempt.insert(relay, Instant::now());
+        assert_eq!(reservations.candidates([relay]), [relay]);
+    }
+
+    #[test]
+    fn releasing_forgets_every_reservation() {
+        let mut reservations = Reservations::default();
+        let relays = [PeerId::random(), PeerId::random()];
+        let listeners = [ListenerId::next(), ListenerId::next()];
+        for (relay, listener_id) in relays.into_iter().zip(listeners) {
+            reservations.attempted(relay, Some(listener_id));
+        }
+
+        let released = reservations.release();
+        assert_eq!(released.len(), 2);
+        assert_eq!(released[&relays[0]], listeners[0]);
+        assert!(!reservations.is_full());
+        assert!(reservations.release().is_empty());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn reservations_follow_the_reachability() {
+        let addr: Multiaddr = "/ip4/203.0.113.7/tcp/4001".parse().unwrap();
+        assert_eq!(reservations_needed(&NatStatus::Private), Some(true));
+        assert_eq!(reservations_needed(&NatStatus::Public(addr)), Some(false));
+        assert_eq!(reservations_needed(&NatStatus::Unknown), None);
+    }
+}
@@ -0,0 +1,100 @@
+use std::io;
+
+use libp2p::{
+    PeerId, Transport,
+    core::{muxing::StreamMuxerBox, transport::Boxed, upgrade::Version},
+    dns,
+    identity::Keypair,
+    noise,
+    pnet::PreSharedKey,
+    quic, tcp, websocket, yamux,
+};
+use libp2p_webrtc::tokio::Certificate;
+use rustls_pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject};
+
+use super::private_network;
+
+/// TCP, WebSocket, QUIC and WebRTC-direct, which one is used depends on the address dialed or
+/// listened on. Browsers can only reach us over WebSocket and WebRTC-direct, and WebRTC-direct
+/// addresses can only be dialed from here while listening on one, as its socket is shared.
+///
+/// With a pre-shared key only TCP is left, restricted to the peers knowing the key, as the
+/// others would bypass it. WebSocket addresses ending with `/tls/ws` are only listened on with
+/// `websocket_tls`.
+pub fn native_transport(
+    key: &Keypair,
+    noise: noise::Config,
+    pre_shared_key: Option<PreSharedKey>,
+    websocket_tls: Option<websocket::tls::Config>,
+    webrtc_certificate: Certificate,
+) -> io::Result<Boxed<(PeerId, StreamMuxerBox)>> {
+    let tcp = tcp::tokio::Transport::new(tcp::Config::new());
+    if let Some(pre_shared_key) = pre_shared_key {
+        return Ok(tcp
+            .and_then(move |socket, _| private_network::handshake(socket, pre_shared_key))
+            .map_err(|err| err.into_inner())
+            .upgrade(Version::V1Lazy)
+            .authenticate(noise)
+            .multiplex(yamux::Config::default())
+            .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)))
+            .boxed());
+    }
+
+    let tcp = tcp
+        .upgrade(Version::V1Lazy)
+        .authenticate(noise.clone())
+        .multiplex(yamux::Config::default())
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+    // resolves host names itself, as the TLS handshake needs the name rather than the address
+    let mut websocket = websocket::Config::new(dns::tokio::Transport::system(
+        tcp::tokio::Transport::new(tcp::Config::new()),
+    )?);
+    if let Some(tls) = websocket_tls {
+        websocket.set_tls_config(tls);
+    }
+    let websocket = websocket
+        .upgrade(Version::V1Lazy)
+        .authenticate(noise)
+        .multiplex(yamux::Config::default())
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+    let quic = quic::tokio::Transport::new(quic::Config::new(key))
+        .map(|(peer_id, muxer), _| (peer_id, StreamMuxerBox::new(muxer)));
+    let webrtc = libp2p_webrtc::tokio::Transport::new(key.clone(), webrtc_certificate)
+        .map(|(peer_id, connection), _| (peer_id, StreamMuxerBox::new(connection)));
+    Ok(websocket
+        .or_transport(tcp)
+        .map(|output, _| output.into_inner())
+        .or_transport(quic)
+        .map(|output, _| output.into_inner())
+        .or_transport(webrtc)
+        .map(|output, _| output.into_inner())
+        .boxed())
+}
+
+/// A new certificate for the WebRTC-direct listeners. Its hash is part of their addresses, so it
+/// should be kept with [`Certificate::serialize_pem`] for peers to reach us at the same addresses
+/// after a restart.
+pub fn generate_webrtc_certificate() -> Certificate {
+    // the rng is not used for now, the key is generated by the certificate library
+    Certificate::generate(&mut rand::thread_rng()).expect("certificate generation is infallible")
+}
+
+/// Reads the PEM encoded certificate chain and private key served on `/tls/ws` listeners.
+pub fn websocket_tls_config(
+    certificate_chain: &[u8],
+    private_key: &[u8],
+) -> io::Result<websocket::tls::Config> {
+    let certificates = CertificateDer::pem_slice_iter(certificate_chain)
+        .map(|certificate| {
+            certificate.map(|certificate| websocket::tls::Certificate::new(certificate.to_vec()))
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let private_key = PrivateKeyDer::from_pem_slice(private_key)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    websocket::tls::Config::new(
+        websocket::tls::PrivateKey::new(private_key.secret_der().to_vec()),
+        certificates,
+    )
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
@@ -0,0 +1,49 @@
+use libp2p::{
+    autonat, connection_limits, identify, identity::Keypair, kad, relay, rendezvous,
+    swarm::NetworkBehaviour,
+};
+use streuen_chat::app::STREUEN_KAD_PROTOCOL;
+
+use crate::config::NodeConfig;
+
+/// Everything the node offers to other peers.
+#[derive(NetworkBehaviour)]
+pub struct NodeBehaviour {
+    limits: connection_limits::Behaviour,
+    pub relay: relay::Behaviour,
+    pub kad: kad::Behaviour<kad::store::MemoryStore>,
+    autonat: autonat::Behaviour,
+    pub rendezvous: rendezvous::server::Behaviour,
+    pub identify: identify::Behaviour,
+}
+
+impl NodeBehaviour {
+    pub fn new(keypair: &Keypair, config: &NodeConfig) -> Self {
+        let local_peer_id = keypair.public().to_peer_id();
+
+        let protocol = if config.public_dht {
+            kad::PROTOCOL_NAME
+        } else {
+            STREUEN_KAD_PROTOCOL
+        };
+        let mut kad = kad::Behaviour::with_config(
+            local_peer_id,
+            kad::store::MemoryStore::new(local_peer_id),
+            kad::Config::new(protocol),
+        );
+        // answer queries even before autonat confirmed an external address
+        kad.set_mode(Some(kad::Mode::Server));
+
+        Self {
+            limits: connection_limits::Behaviour::new(config.limits.connection_limits()),
+            relay: relay::Behaviour::new(local_peer_id, config.limits.relay_config()),
+            kad,
+            autonat: autonat::Behaviour::new(local_peer_id, autonat::Config::default()),
+            rendezvous: rendezvous::server::Behaviour::new(rendezvous::server::Config::default()),
+            identify: identify::Behaviour::new(identify::Config::new(
+                "streuen/node/0.1.0".to_string(),
+                keypair.public(),
+            )),
+        }
+    }
+}
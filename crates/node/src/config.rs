@@ -0,0 +1,237 @@
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use libp2p::{
+    Multiaddr, connection_limits, identity::Keypair, pnet::PreSharedKey, relay, websocket,
+};
+use serde::Deserialize;
+use streuen_chat::WebRtcCertificate;
+
+/// File inside the data directory holding the protobuf encoded node identity.
+const IDENTITY_FILE: &str = "identity.key";
+
+/// File inside the data directory holding the pre-shared key of the private network, if the node
+/// serves one.
+const SWARM_KEY_FILE: &str = "swarm.key";
+
+/// File inside the data directory holding the certificate of the WebRTC-direct listeners, whose
+/// hash is part of their addresses.
+const WEBRTC_CERTIFICATE_FILE: &str = "webrtc.pem";
+
+/// Settings of the node, read from a TOML file.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    /// Directory holding the identity and the swarm key, relative to the config file.
+    pub data_dir: PathBuf,
+    pub listen_addresses: Vec<Multiaddr>,
+    /// Publicly reachable addresses of the node, handed out to peers making relay reservations.
+    pub external_addresses: Vec<Multiaddr>,
+    /// Other nodes to join the DHT through, each address has to end with its `/p2p` peer id.
+    pub bootstrap_peers: Vec<Multiaddr>,
+    /// Serves the public IPFS DHT rather than the DHT of streuen nodes.
+    pub public_dht: bool,
+    /// PEM encoded certificate chain served on `/tls/ws` listen addresses, relative to the config
+    /// file.
+    pub websocket_certificate: Option<PathBuf>,
+    /// PEM encoded private key of `websocket_certificate`, relative to the config file.
+    pub websocket_private_key: Option<PathBuf>,
+    #[serde(with = "seconds")]
+    pub idle_connection_timeout: Duration,
+    pub limits: Limits,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: PathBuf::from("."),
+            listen_addresses: vec![
+                "/ip4/0.0.0.0/tcp/4001".parse().expect("valid address"),
+                "/ip4/0.0.0.0/udp/4001/quic-v1"
+                    .parse()
+                    .expect("valid address"),
+            ],
+            external_addresses: Vec::new(),
+            bootstrap_peers: Vec::new(),
+            public_dht: false,
+            websocket_certificate: None,
+            websocket_private_key: None,
+            idle_connection_timeout: Duration::from_secs(60),
+            limits: Limits::default(),
+        }
+    }
+}
+
+/// Bounds on what the node spends on other peers.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_connections: u32,
+    pub max_connections_per_peer: u32,
+    /// Incoming connections still being negotiated.
+    pub max_pending_incoming: u32,
+    pub max_reservations: usize,
+    pub max_reservations_per_peer: usize,
+    pub max_circuits: usize,
+    pub max_circuits_per_peer: usize,
+    #[serde(with = "seconds")]
+    pub max_circuit_duration: Duration,
+    pub max_circuit_bytes: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        let relay = relay::Config::default();
+        Self {
+            max_connections: 1024,
+            max_connections_per_peer: 4,
+            max_pending_incoming: 128,
+            max_reservations: relay.max_reservations,
+            max_reservations_per_peer: relay.max_reservations_per_peer,
+            max_circuits: relay.max_circuits,
+            max_circuits_per_peer: relay.max_circuits_per_peer,
+            max_circuit_duration: relay.max_circuit_duration,
+            max_circuit_bytes: relay.max_circuit_bytes,
+        }
+    }
+}
+
+impl Limits {
+    pub fn connection_limits(&self) -> connection_limits::ConnectionLimits {
+        connection_limits::ConnectionLimits::default()
+            .with_max_established(Some(self.max_connections))
+            .with_max_established_per_peer(Some(self.max_connections_per_peer))
+            .with_max_pending_incoming(Some(self.max_pending_incoming))
+    }
+
+    /// The relay config with its default rate limits along with these limits.
+    pub fn relay_config(&self) -> relay::Config {
+        relay::Config {
+            max_reservations: self.max_reservations,
+            max_reservations_per_peer: self.max_reservations_per_peer,
+            max_circuits: self.max_circuits,
+            max_circuits_per_peer: self.max_circuits_per_peer,
+            max_circuit_duration: self.max_circuit_duration,
+            max_circuit_bytes: self.max_circuit_bytes,
+            ..relay::Config::default()
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Reads the config at `path`, falling back to the defaults if there is no such file.
+    pub fn load(path: &Path) -> color_eyre::Result<Self> {
+        let mut config: Self = match std::fs::read_to_string(path) {
+            Ok(config) => toml::from_str(&config)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                tracing::info!("No config at {}, using the defaults", path.display());
+                Self::default()
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if let Some(dir) = path.parent() {
+            config.data_dir = dir.join(&config.data_dir);
+            for file in [
+                &mut config.websocket_certificate,
+                &mut config.websocket_private_key,
+            ]
+            .into_iter()
+            .flatten()
+            {
+                *file = dir.join(&*file);
+            }
+        }
+        std::fs::create_dir_all(&config.data_dir)?;
+        Ok(config)
+    }
+
+    /// Loads the node identity from the data directory, generating and saving a new one on the
+    /// first start.
+    pub fn load_or_generate_keypair(&self) -> io::Result<Keypair> {
+        let identity_path = self.data_dir.join(IDENTITY_FILE);
+        match std::fs::read(&identity_path) {
+            Ok(bytes) => Keypair::from_protobuf_encoding(&bytes)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let keypair = Keypair::generate_ed25519();
+                let bytes = keypair
+                    .to_protobuf_encoding()
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                write_private_file(&identity_path, &bytes)?;
+                tracing::info!("Generated new identity: {}", keypair.public().to_peer_id());
+                Ok(keypair)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Loads the key of the private network the node serves, if it serves one.
+    pub fn pre_shared_key(&self) -> io::Result<Option<PreSharedKey>> {
+        match std::fs::read_to_string(self.data_dir.join(SWARM_KEY_FILE)) {
+            Ok(key) => key
+                .parse()
+                .map(Some)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Loads the certificate of the WebRTC-direct listeners, generating and saving a new one on
+    /// the first start so their addresses stay the same.
+    pub fn load_or_generate_webrtc_certificate(&self) -> io::Result<WebRtcCertificate> {
+        let path = self.data_dir.join(WEBRTC_CERTIFICATE_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(pem) => WebRtcCertificate::from_pem(&pem)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let certificate = streuen_chat::generate_webrtc_certificate();
+                write_private_file(&path, certificate.serialize_pem().as_bytes())?;
+                Ok(certificate)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The TLS config of the secure WebSocket listeners, if a certificate is configured.
+    pub fn websocket_tls(&self) -> color_eyre::Result<Option<websocket::tls::Config>> {
+        match (&self.websocket_certificate, &self.websocket_private_key) {
+            (Some(certificate), Some(private_key)) => Ok(Some(streuen_chat::websocket_tls_config(
+                &std::fs::read(certificate)?,
+                &std::fs::read(private_key)?,
+            )?)),
+            (None, None) => Ok(None),
+            _ => color_eyre::eyre::bail!(
+                "websocket_certificate and websocket_private_key have to be set together"
+            ),
+        }
+    }
+}
+
+/// Writes a file only readable by the current user.
+fn write_private_file(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(contents)
+}
+
+/// Durations given in whole seconds.
+mod seconds {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer};
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_secs)
+    }
+}
@@ -0,0 +1,150 @@
+mod behaviour;
+mod config;
+
+use std::path::PathBuf;
+
+use futures::StreamExt;
+use libp2p::{
+    Swarm, SwarmBuilder, identify, identity::Keypair, kad, multiaddr::Protocol, noise, relay,
+    rendezvous, swarm::SwarmEvent,
+};
+
+use crate::{
+    behaviour::{NodeBehaviour, NodeBehaviourEvent},
+    config::NodeConfig,
+};
+
+/// Config read when no other path is given.
+const DEFAULT_CONFIG: &str = "streuen-node.toml";
+
+#[tokio::main]
+async fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+    initialize_logging();
+
+    let path = std::env::args()
+        .nth(1)
+        .map_or_else(|| PathBuf::from(DEFAULT_CONFIG), PathBuf::from);
+    let config = NodeConfig::load(&path)?;
+    let mut swarm = build_swarm(config.load_or_generate_keypair()?, &config)?;
+
+    for addr in &config.listen_addresses {
+        swarm.listen_on(addr.clone())?;
+    }
+    for addr in &config.external_addresses {
+        swarm.add_external_address(addr.clone());
+    }
+    for addr in &config.bootstrap_peers {
+        let Some(Protocol::P2p(peer_id)) = addr.iter().last() else {
+            color_eyre::eyre::bail!("Bootstrap address [{addr}] has to end with the peer id");
+        };
+        swarm
+            .behaviour_mut()
+            .kad
+            .add_address(&peer_id, addr.clone());
+    }
+    if !config.bootstrap_peers.is_empty() {
+        // cannot fail as the routing table holds the peers just added
+        let _ = swarm.behaviour_mut().kad.bootstrap();
+    }
+    tracing::info!("Running node {}", swarm.local_peer_id());
+
+    loop {
+        tokio::select! {
+            event = swarm.select_next_some() => handle_event(&mut swarm, event),
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    tracing::info!("Shutting down");
+    Ok(())
+}
+
+fn build_swarm(keypair: Keypair, config: &NodeConfig) -> color_eyre::Result<Swarm<NodeBehaviour>> {
+    let pre_shared_key = config.pre_shared_key()?;
+    if let Some(key) = pre_shared_key {
+        tracing::info!("Serving the private network {}", key.fingerprint());
+    }
+    let noise = noise::Config::new(&keypair)?;
+    let transport = streuen_chat::native_transport(
+        &keypair,
+        noise,
+        pre_shared_key,
+        config.websocket_tls()?,
+        config.load_or_generate_webrtc_certificate()?,
+    )?;
+    let swarm = SwarmBuilder::with_existing_identity(keypair)
+        .with_tokio()
+        .with_other_transport(|_| transport)
+        .unwrap() // this is Infallible so this is safe
+        .with_dns()?
+        .with_behaviour(|keypair| NodeBehaviour::new(keypair, config))
+        .unwrap() // this is Infallible so this is safe
+        .with_swarm_config(|swarm_config| {
+            swarm_config.with_idle_connection_timeout(config.idle_connection_timeout)
+        })
+        .build();
+    Ok(swarm)
+}
+
+fn handle_event(swarm: &mut Swarm<NodeBehaviour>, event: SwarmEvent<NodeBehaviourEvent>) {
+    match event {
+        SwarmEvent::NewListenAddr { address, .. } => {
+            let peer_id = *swarm.local_peer_id();
+            tracing::info!("Listening on {}", address.with(Protocol::P2p(peer_id)));
+        }
+        SwarmEvent::ExternalAddrConfirmed { address } => {
+            tracing::info!("External address confirmed: {address}");
+        }
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Identify(identify::Event::Received {
+            peer_id,
+            info,
+            ..
+        })) => {
+            // peers serving the DHT are only added once their listen addresses are known
+            let kad = &mut swarm.behaviour_mut().kad;
+            if info
+                .protocols
+                .iter()
+                .any(|protocol| kad.protocol_names().contains(protocol))
+            {
+                for addr in info.listen_addrs {
+                    kad.add_address(&peer_id, addr);
+                }
+            }
+        }
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Relay(event)) => match event {
+            relay::Event::ReservationReqAccepted { src_peer_id, .. } => {
+                tracing::info!("Relay reservation accepted for [{src_peer_id}]");
+            }
+            relay::Event::CircuitReqAccepted {
+                src_peer_id,
+                dst_peer_id,
+            } => tracing::info!("Relaying [{src_peer_id}] to [{dst_peer_id}]"),
+            event => tracing::debug!("Relay event: {event:?}"),
+        },
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Rendezvous(
+            rendezvous::server::Event::PeerRegistered { peer, registration },
+        )) => tracing::info!(
+            "Peer [{peer}] registered in namespace [{}]",
+            registration.namespace
+        ),
+        SwarmEvent::Behaviour(NodeBehaviourEvent::Kad(kad::Event::RoutingUpdated {
+            peer,
+            is_new_peer: true,
+            ..
+        })) => tracing::debug!("DHT peer added: {peer}"),
+        SwarmEvent::IncomingConnectionError {
+            send_back_addr,
+            error,
+            ..
+        } => tracing::debug!("Incoming connection from [{send_back_addr}] failed: {error}"),
+        event => tracing::trace!("Swarm Event: {event:?}"),
+    }
+}
+
+fn initialize_logging() {
+    let env_filter = tracing_subscriber::EnvFilter::builder()
+        .with_default_directive(tracing_subscriber::filter::LevelFilter::INFO.into())
+        .from_env_lossy();
+    tracing_subscriber::fmt().with_env_filter(env_filter).init();
+}